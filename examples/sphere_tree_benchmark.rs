@@ -0,0 +1,91 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Compare the collision checks with and without the sphere trees
+//!
+//! `cargo run --release --example sphere_tree_benchmark -- [robot urdf] [obstacle urdf] [num]`
+extern crate gear;
+extern crate nalgebra as na;
+extern crate ncollide3d;
+extern crate structopt;
+
+use gear::FromUrdf;
+use ncollide3d::shape::{Ball, Compound};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "sphere_tree_benchmark")]
+struct Opt {
+    #[structopt(default_value = "sample.urdf")]
+    robot_path: String,
+    #[structopt(default_value = "obstacles.urdf")]
+    obstacle_path: String,
+    #[structopt(default_value = "10000")]
+    num_samples: usize,
+}
+
+fn to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let obstacles =
+        Compound::<f64>::from_urdf_file(&opt.obstacle_path).expect("obstacle file not found");
+    let ball = Ball::new(0.1);
+    let ball_pose = na::Isometry3::new(na::Vector3::new(0.5, 0.2, 0.5), na::zero());
+    let mut results = Vec::new();
+    for &sphere_trees in &[false, true] {
+        let planner = gear::JointPathPlannerBuilder::from_urdf_file(&opt.robot_path)
+            .expect("failed to create planner from urdf file")
+            .collision_check_margin(0.01f64)
+            .sphere_trees(sphere_trees)
+            .finalize();
+        let robot = &planner.collision_check_robot;
+        let checker = &planner.collision_checker;
+        let mut num_colliding = 0;
+        let mut single_shape = Duration::new(0, 0);
+        let mut objects = Duration::new(0, 0);
+        for _ in 0..opt.num_samples {
+            gear::set_random_joint_positions(robot).unwrap();
+            let start = Instant::now();
+            if checker.has_any_colliding(robot, &ball, &ball_pose) {
+                num_colliding += 1;
+            }
+            single_shape += start.elapsed();
+            let start = Instant::now();
+            if checker.has_any_colliding_with_objects(robot, &obstacles) {
+                num_colliding += 1;
+            }
+            objects += start.elapsed();
+        }
+        println!(
+            "sphere trees: {:5}, has_any_colliding: {:.3} sec, \
+             has_any_colliding_with_objects: {:.3} sec, colliding: {}",
+            sphere_trees,
+            to_secs(single_shape),
+            to_secs(objects),
+            num_colliding
+        );
+        results.push((to_secs(single_shape), to_secs(objects)));
+    }
+    println!(
+        "speed up: has_any_colliding x{:.2}, has_any_colliding_with_objects x{:.2}",
+        results[0].0 / results[1].0,
+        results[0].1 / results[1].1
+    );
+}
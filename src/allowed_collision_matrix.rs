@@ -0,0 +1,60 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use std::collections::HashSet;
+
+/// Pairs of links which are allowed to collide with each other
+///
+/// The names are the same as the ones returned by `CollisionChecker::colliding_link_names`.
+/// The order of the names in a pair does not matter.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedCollisionMatrix {
+    pairs: HashSet<(String, String)>,
+}
+
+fn ordered_pair(name1: &str, name2: &str) -> (String, String) {
+    if name1 < name2 {
+        (name1.to_owned(), name2.to_owned())
+    } else {
+        (name2.to_owned(), name1.to_owned())
+    }
+}
+
+impl AllowedCollisionMatrix {
+    /// Create empty matrix, which allows no collisions
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Allow the collision between `name1` and `name2`
+    pub fn allow(&mut self, name1: &str, name2: &str) {
+        self.pairs.insert(ordered_pair(name1, name2));
+    }
+    /// Disallow the collision between `name1` and `name2` again
+    pub fn disallow(&mut self, name1: &str, name2: &str) {
+        self.pairs.remove(&ordered_pair(name1, name2));
+    }
+    /// Check if the collision between `name1` and `name2` is allowed
+    pub fn is_allowed(&self, name1: &str, name2: &str) -> bool {
+        self.pairs.contains(&ordered_pair(name1, name2))
+    }
+    /// Iterate all the allowed pairs
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.pairs.iter()
+    }
+    /// Remove all the allowed pairs
+    pub fn clear(&mut self) {
+        self.pairs.clear();
+    }
+}
@@ -0,0 +1,335 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Isometry3, Matrix3, Point3, Real, Rotation3, UnitQuaternion, Vector3};
+use ncollide3d::shape::{Ball, Capsule, Compound, Cuboid, Shape, ShapeHandle, TriMesh};
+use std::f64::consts::{FRAC_PI_2, PI};
+
+/// Simplified shape which replaces all the collision shapes of a link
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundingPrimitive {
+    /// Box along the principal axes of the geometry
+    OrientedBox,
+    /// Capsule along the longest principal axis
+    Capsule,
+    /// Spheres along the longest principal axis, which cover the oriented box
+    Spheres { num_spheres: usize },
+}
+
+/// Number of the directions to sample the points on the convex shapes
+const NUM_SAMPLE_DIRECTIONS: usize = 64;
+
+/// Directions which are distributed uniformly on the unit sphere (Fibonacci sphere)
+fn sample_directions<T>(num: usize) -> Vec<Vector3<T>>
+where
+    T: Real,
+{
+    let golden_angle = PI * (3.0 - 5.0f64.sqrt());
+    (0..num)
+        .map(|i| {
+            let z = 1.0 - 2.0 * (i as f64 + 0.5) / num as f64;
+            let r = (1.0 - z * z).sqrt();
+            let theta = golden_angle * i as f64;
+            Vector3::new(
+                na::convert(r * theta.cos()),
+                na::convert(r * theta.sin()),
+                na::convert(z),
+            )
+        })
+        .collect()
+}
+
+/// Collect the points on the surface of the shape
+fn collect_points<T>(
+    shape: &Shape<T>,
+    pose: &Isometry3<T>,
+    directions: &[Vector3<T>],
+    points: &mut Vec<Point3<T>>,
+) where
+    T: Real,
+{
+    if let Some(compound) = shape.as_shape::<Compound<T>>() {
+        for &(ref sub_pose, ref sub_shape) in compound.shapes() {
+            collect_points(&**sub_shape, &(pose * sub_pose), directions, points);
+        }
+    } else if let Some(trimesh) = shape.as_shape::<TriMesh<T>>() {
+        points.extend(trimesh.points().iter().map(|p| pose * p));
+    } else if let Some(support_map) = shape.as_support_map() {
+        // the same point is returned for many directions (e.g. the corners of a box)
+        let mut support_points = Vec::new();
+        for d in directions {
+            let p = support_map.support_point(pose, d);
+            if !support_points.contains(&p) {
+                support_points.push(p);
+            }
+        }
+        points.extend(support_points);
+    } else {
+        warn!("the shape is not supported by the bounding primitive");
+    }
+}
+
+/// Box along the principal axes of the points
+struct OrientedBox<T>
+where
+    T: Real,
+{
+    pose: Isometry3<T>,
+    half_extents: Vector3<T>,
+    /// Points in the frame of the box
+    local_points: Vec<Vector3<T>>,
+}
+
+impl<T> OrientedBox<T>
+where
+    T: Real,
+{
+    fn from_points(points: &[Point3<T>]) -> Self {
+        let n: T = na::convert(points.len() as f64);
+        let mean = points
+            .iter()
+            .fold(Vector3::zeros(), |sum, p| sum + p.coords)
+            / n;
+        let covariance = points.iter().fold(Matrix3::zeros(), |sum, p| {
+            let d = p.coords - mean;
+            sum + d * d.transpose()
+        }) / n;
+        let mut axes = covariance.symmetric_eigen().eigenvectors;
+        if axes.determinant() < T::zero() {
+            let flipped = -axes.column(2).into_owned();
+            axes.set_column(2, &flipped);
+        }
+        let rotation =
+            UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(axes));
+        let inverse = rotation.inverse();
+        let projected = points
+            .iter()
+            .map(|p| inverse * (p.coords - mean))
+            .collect::<Vec<_>>();
+        let mut min = projected[0];
+        let mut max = projected[0];
+        for v in &projected {
+            for i in 0..3 {
+                if v[i] < min[i] {
+                    min[i] = v[i];
+                }
+                if v[i] > max[i] {
+                    max[i] = v[i];
+                }
+            }
+        }
+        let two: T = na::convert(2.0);
+        let local_center = (min + max) / two;
+        let mut pose = Isometry3::identity();
+        pose.translation.vector = mean + rotation * local_center;
+        pose.rotation = rotation;
+        OrientedBox {
+            pose,
+            half_extents: (max - min) / two,
+            local_points: projected.into_iter().map(|v| v - local_center).collect(),
+        }
+    }
+    /// Index of the longest axis and the other two axes
+    fn axes_by_length(&self) -> (usize, usize, usize) {
+        let h = &self.half_extents;
+        if h[0] >= h[1] && h[0] >= h[2] {
+            (0, 1, 2)
+        } else if h[1] >= h[2] {
+            (1, 2, 0)
+        } else {
+            (2, 0, 1)
+        }
+    }
+    /// Rotation from the Y axis of `ncollide3d` shapes to the axis of the box
+    fn y_to_axis(axis: usize) -> Isometry3<T> {
+        match axis {
+            0 => Isometry3::new(na::zero(), Vector3::z() * na::convert::<f64, T>(-FRAC_PI_2)),
+            1 => Isometry3::identity(),
+            _ => Isometry3::new(na::zero(), Vector3::x() * na::convert::<f64, T>(FRAC_PI_2)),
+        }
+    }
+    fn to_cuboid(&self) -> (ShapeHandle<T>, Isometry3<T>) {
+        (ShapeHandle::new(Cuboid::new(self.half_extents)), self.pose)
+    }
+    /// The smallest capsule on the longest axis which contains all the points
+    fn to_capsule(&self) -> (ShapeHandle<T>, Isometry3<T>) {
+        let (axis, a, b) = self.axes_by_length();
+        let perpendicular = |v: &Vector3<T>| (v[a] * v[a] + v[b] * v[b]).sqrt();
+        let radius = self
+            .local_points
+            .iter()
+            .fold(T::zero(), |r, v| na::sup(&r, &perpendicular(v)));
+        let half_height = self.local_points.iter().fold(T::zero(), |h, v| {
+            let d = perpendicular(v);
+            let cap = (radius * radius - d * d).max(T::zero()).sqrt();
+            na::sup(&h, &(v[axis].abs() - cap))
+        });
+        (
+            ShapeHandle::new(Capsule::new(half_height, radius)),
+            self.pose * Self::y_to_axis(axis),
+        )
+    }
+    /// Spheres on the longest axis, each one contains a slice of the box
+    fn to_spheres(&self, num_spheres: usize) -> Vec<(ShapeHandle<T>, Isometry3<T>)> {
+        let (axis, a, b) = self.axes_by_length();
+        let num: T = na::convert(num_spheres.max(1) as f64);
+        let half_length = self.half_extents[axis];
+        let half_slice = half_length / num;
+        let radius = (half_slice * half_slice
+            + self.half_extents[a] * self.half_extents[a]
+            + self.half_extents[b] * self.half_extents[b])
+            .sqrt();
+        (0..num_spheres.max(1))
+            .map(|i| {
+                let index: T = na::convert(2.0 * i as f64 + 1.0);
+                let mut center = Vector3::zeros();
+                center[axis] = -half_length + half_slice * index;
+                (
+                    ShapeHandle::new(Ball::new(radius)),
+                    self.pose * Isometry3::new(center, na::zero()),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Replace the shapes with the bounding primitive
+///
+/// The shapes are returned as they are if no point is found.
+pub(crate) fn bounding_primitive_shapes<T>(
+    shapes: Vec<(ShapeHandle<T>, Isometry3<T>)>,
+    primitive: BoundingPrimitive,
+) -> Vec<(ShapeHandle<T>, Isometry3<T>)>
+where
+    T: Real,
+{
+    let directions = sample_directions(NUM_SAMPLE_DIRECTIONS);
+    let mut points = Vec::new();
+    for &(ref shape, ref pose) in &shapes {
+        collect_points(&**shape, pose, &directions, &mut points);
+    }
+    if points.is_empty() {
+        return shapes;
+    }
+    let obb = OrientedBox::from_points(&points);
+    match primitive {
+        BoundingPrimitive::OrientedBox => vec![obb.to_cuboid()],
+        BoundingPrimitive::Capsule => vec![obb.to_capsule()],
+        BoundingPrimitive::Spheres { num_spheres } => obb.to_spheres(num_spheres),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collision_checker::{CollisionChecker, CollisionModelOptions};
+    use k;
+    use ncollide3d::query;
+    use urdf_rs;
+
+    #[test]
+    fn bounding_primitives() {
+        // long box along the rotated x axis
+        let pose = Isometry3::new(Vector3::new(0.1, 0.2, 0.3), Vector3::new(0.3, 0.2, 0.5));
+        let cuboid = Cuboid::new(Vector3::new(0.5, 0.1, 0.05));
+        let shapes = vec![(ShapeHandle::new(cuboid.clone()), pose)];
+        let directions = sample_directions::<f64>(NUM_SAMPLE_DIRECTIONS);
+        let mut corners = Vec::new();
+        collect_points(&cuboid, &pose, &directions, &mut corners);
+
+        for primitive in &[
+            BoundingPrimitive::OrientedBox,
+            BoundingPrimitive::Capsule,
+            BoundingPrimitive::Spheres { num_spheres: 5 },
+        ] {
+            let bounding = bounding_primitive_shapes(shapes.clone(), *primitive);
+            for corner in &corners {
+                assert!(
+                    bounding.iter().any(|&(ref shape, ref pose)| shape
+                        .as_point_query()
+                        .unwrap()
+                        .distance_to_point(pose, corner, true)
+                        < 1e-6),
+                    "{:?} does not contain {}",
+                    primitive,
+                    corner
+                );
+            }
+            // the far point is outside
+            let far = pose * Point3::new(0.0, 0.5, 0.0);
+            assert!(bounding.iter().all(|&(ref shape, ref pose)| {
+                shape
+                    .as_point_query()
+                    .unwrap()
+                    .distance_to_point(pose, &far, true)
+                    > 0.1
+            }));
+        }
+        let obb = bounding_primitive_shapes(shapes, BoundingPrimitive::OrientedBox);
+        let half_extents = obb[0].0.as_shape::<Cuboid<f64>>().unwrap().half_extents();
+        let mut sorted = [half_extents[0], half_extents[1], half_extents[2]];
+        sorted.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        assert!((sorted[0] - 0.5).abs() < 1e-6);
+        assert!((sorted[1] - 0.1).abs() < 1e-6);
+        assert!((sorted[2] - 0.05).abs() < 1e-6);
+        let ball = Ball::new(0.01);
+        let inside = Isometry3::new(pose.translation.vector, na::zero());
+        assert!(query::distance(&inside, &ball, &obb[0].1, &*obb[0].0) <= 0.0);
+    }
+    #[test]
+    fn visual_fallback_and_bounding_primitives() {
+        let urdf_robot = urdf_rs::read_from_string(
+            r#"<robot name="visual_only">
+  <link name="base"/>
+  <link name="arm">
+    <visual>
+      <origin xyz="0.5 0 0"/>
+      <geometry><box size="1.0 0.1 0.1"/></geometry>
+    </visual>
+  </link>
+  <joint name="arm_joint" type="revolute">
+    <parent link="base"/>
+    <child link="arm"/>
+    <axis xyz="0 0 1"/>
+    <limit lower="-1" upper="1" effort="1" velocity="1"/>
+  </joint>
+</robot>"#,
+        ).unwrap();
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let ball = Compound::new(vec![(
+            Isometry3::new(Vector3::new(0.9, 0.0, 0.0), na::zero()),
+            ShapeHandle::new(Ball::new(0.02)),
+        )]);
+        let names = |options: &CollisionModelOptions| {
+            CollisionChecker::from_urdf_robot_with_options(&urdf_robot, options, 0.0)
+                .unwrap()
+                .colliding_link_names_with_objects(&robot, &ball)
+        };
+        assert!(names(&CollisionModelOptions::default()).is_empty());
+        let mut options = CollisionModelOptions {
+            visual_fallback: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&options), vec!["arm_joint".to_owned()]);
+        for primitive in &[
+            BoundingPrimitive::OrientedBox,
+            BoundingPrimitive::Capsule,
+            BoundingPrimitive::Spheres { num_spheres: 4 },
+        ] {
+            options.bounding_primitive = Some(*primitive);
+            assert_eq!(names(&options), vec!["arm_joint".to_owned()]);
+        }
+    }
+}
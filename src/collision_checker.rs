@@ -13,74 +13,79 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 */
-use assimp;
 use k;
 use na::{self, Real, Vector3};
-use ncollide3d;
-use ncollide3d::procedural::IndexBuffer::{Split, Unified};
+use ncollide3d::bounding_volume::{BoundingVolume, AABB};
 use ncollide3d::query;
-use ncollide3d::query::Proximity;
-use ncollide3d::shape::{Ball, Compound, Cuboid, Cylinder, Shape, ShapeHandle, TriMesh};
+use ncollide3d::query::{ClosestPoints, Proximity};
+use ncollide3d::shape::{
+    Ball, Capsule, Compound, ConvexHull, Cuboid, Cylinder, Shape, ShapeHandle,
+};
 use ncollide3d::transformation::ToTriMesh;
-use std::collections::HashMap;
-use std::path::Path;
+use num_traits::Float;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use urdf_rs;
+use xml::reader::{EventReader, XmlEvent};
+use xml::writer::{EmitterConfig, XmlEvent as WriterEvent};
 
+use allowed_collision_matrix::AllowedCollisionMatrix;
+use bounding::{bounding_primitive_shapes, BoundingPrimitive};
+use distance_field::{FieldDistance, SignedDistanceField};
 use errors::*;
+use mesh::*;
+use planning_scene::{CollisionObject, CollisionObjects, DynamicCompound, PlanningScene};
+use resolver::{ResourceResolver, Resolver};
+use sphere_tree::SphereTree;
 
-fn load_mesh<P, T>(filename: P, scale: &[f64]) -> Result<TriMesh<T>>
+/// Returns true if the distance between the shapes is smaller than `margin`
+///
+/// `query::proximity` returns `WithinMargin` as soon as the lower bound of the distance is
+/// smaller than `margin`, which happens for the separated convex hulls, so it is confirmed by
+/// the distance.
+fn is_within_margin<T>(
+    m1: &na::Isometry3<T>,
+    g1: &Shape<T>,
+    m2: &na::Isometry3<T>,
+    g2: &Shape<T>,
+    margin: T,
+) -> bool
 where
-    P: AsRef<Path>,
     T: Real,
 {
-    let mut importer = assimp::Importer::new();
-    importer.pre_transform_vertices(|x| x.enable = true);
-    importer.collada_ignore_up_direction(true);
-    let file_string = filename
-        .as_ref()
-        .to_str()
-        .ok_or("faild to get string from path")?;
-    Ok(assimp_scene_to_ncollide_mesh(
-        importer.read_file(file_string)?,
-        scale,
-    ))
-}
-
-fn assimp_scene_to_ncollide_mesh<T>(scene: assimp::Scene, scale: &[f64]) -> TriMesh<T>
+    match query::proximity(m1, g1, m2, g2, margin) {
+        Proximity::Disjoint => false,
+        Proximity::Intersecting => true,
+        Proximity::WithinMargin => query::distance(m1, g1, m2, g2) <= margin,
+    }
+}
+
+/// Number of the sides of the convex hull of `<cylinder>`
+const CYLINDER_SUBDIVISIONS: u32 = 30;
+
+/// Rotation from the Y axis of `ncollide3d` to the Z axis of URDF
+fn y_to_z_axis<T>() -> na::Isometry3<T>
 where
     T: Real,
 {
-    let mut vertices = Vec::new();
-    let mut indices = Vec::new();
-    let mut last_index: usize = 0;
-    for mesh in scene.mesh_iter() {
-        vertices.extend(mesh.vertex_iter().map(|v| {
-            na::Point3::<T>::new(
-                na::convert(v.x as f64 * scale[0]),
-                na::convert(v.y as f64 * scale[1]),
-                na::convert(v.z as f64 * scale[2]),
-            )
-        }));
-        indices.extend(mesh.face_iter().filter_map(|f| {
-            if f.num_indices == 3 {
-                Some(na::Point3::<usize>::new(
-                    f[0] as usize + last_index,
-                    f[1] as usize + last_index,
-                    f[2] as usize + last_index,
-                ))
-            } else {
-                None
-            }
-        }));
-        last_index = vertices.len() as usize;
-    }
-    TriMesh::new(vertices, indices, None)
+    na::Isometry3::new(
+        na::zero(),
+        Vector3::x() * na::convert::<f64, T>(::std::f64::consts::FRAC_PI_2),
+    )
 }
 
+/// Returns the shape and its pose in the frame of the geometry
+///
+/// `<cylinder>` is converted to `Capsule` if `is_capsule` is true.
 fn urdf_geometry_to_shape_handle<T>(
     collision_geometry: &urdf_rs::Geometry,
-    base_dir: Option<&Path>,
-) -> Option<ShapeHandle<T>>
+    resolver: &ResourceResolver,
+    is_capsule: bool,
+    mesh_mode: MeshMode,
+    mesh_cache: &MeshCache<T>,
+) -> Result<(ShapeHandle<T>, na::Isometry3<T>)>
 where
     T: Real,
 {
@@ -91,65 +96,601 @@ where
                 na::convert(size[1] * 0.5),
                 na::convert(size[2] * 0.5),
             ));
-            Some(ShapeHandle::new(cube))
+            Ok((ShapeHandle::new(cube), na::Isometry3::identity()))
         }
         urdf_rs::Geometry::Cylinder { radius, length } => {
-            let y_cylinder = Cylinder::new(na::convert(length * 0.5), na::convert(radius));
-            let tri_mesh = ncollide3d::transformation::convex_hull(
-                &y_cylinder
-                    .to_trimesh(30)
-                    .coords
-                    .iter()
-                    .map(|point| point.xzy())
-                    .collect::<Vec<_>>(),
-            );
-            let ind = match tri_mesh.indices {
-                Unified(ind) => ind
-                    .into_iter()
-                    .map(|p| na::Point3::new(p[0] as usize, p[1] as usize, p[2] as usize))
-                    .collect(),
-                Split(_) => {
-                    panic!("convex_hull implemenataion has been changed by ncollide3d update?");
-                }
+            // the axes of the shapes of ncollide3d are Y, but the ones of URDF are Z.
+            let shape = if is_capsule {
+                ShapeHandle::new(Capsule::new(na::convert(length * 0.5), na::convert(radius)))
+            } else {
+                // `Cylinder` of ncollide3d is not a `Shape`, so use the convex hull of its points
+                let cylinder = Cylinder::new(na::convert(length * 0.5), na::convert(radius));
+                let hull = ConvexHull::try_from_points(
+                    &cylinder.to_trimesh(CYLINDER_SUBDIVISIONS).coords,
+                ).ok_or_else(|| {
+                    Error::from(format!(
+                        "failed to create cylinder (radius {}, length {})",
+                        radius, length
+                    ))
+                })?;
+                ShapeHandle::new(hull)
             };
-            Some(ShapeHandle::new(TriMesh::new(
-                tri_mesh.coords,
-                ind,
-                tri_mesh.uvs,
-            )))
-        }
-        urdf_rs::Geometry::Sphere { radius } => {
-            Some(ShapeHandle::new(Ball::new(na::convert(radius))))
+            Ok((shape, y_to_z_axis()))
         }
+        urdf_rs::Geometry::Sphere { radius } => Ok((
+            ShapeHandle::new(Ball::new(na::convert(radius))),
+            na::Isometry3::identity(),
+        )),
         urdf_rs::Geometry::Mesh {
             ref filename,
             scale,
         } => {
-            let replaced_filename = urdf_rs::utils::expand_package_path(filename, base_dir);
-            let path = Path::new(&replaced_filename);
-            if !path.exists() {
-                error!("{} not found", replaced_filename);
-                return None;
+            let resource = resolver.resolve(filename).ok_or_else(|| Error::Mesh {
+                error: format!("{} not found", filename),
+            })?;
+            Ok((
+                mesh_cache.get_or_load_resource(&resource, &scale, mesh_mode)?,
+                na::Isometry3::identity(),
+            ))
+        }
+    }
+}
+
+/// Returns the results of loading the geometries of the link and their poses
+///
+/// The `<visual>` elements are used if `options.visual_fallback` is true and the link has no
+/// `<collision>`.
+fn load_urdf_link_geometries<T>(
+    link: &urdf_rs::Link,
+    options: &CollisionModelOptions,
+    resolver: &ResourceResolver,
+    mesh_cache: &MeshCache<T>,
+) -> Vec<Result<(ShapeHandle<T>, na::Isometry3<T>)>>
+where
+    T: Real,
+{
+    let geometries = if link.collision.is_empty() && options.visual_fallback {
+        link.visual
+            .iter()
+            .map(|visual| (&visual.geometry, &visual.origin, false))
+            .collect::<Vec<_>>()
+    } else {
+        link.collision
+            .iter()
+            .enumerate()
+            .map(|(i, collision)| {
+                (
+                    &collision.geometry,
+                    &collision.origin,
+                    options.capsules.is_capsule(&link.name, i),
+                )
+            })
+            .collect()
+    };
+    geometries
+        .into_iter()
+        .map(|(geometry, origin, is_capsule)| {
+            let (shape, local_pose) = urdf_geometry_to_shape_handle(
+                geometry,
+                resolver,
+                is_capsule,
+                options.link_mesh_mode(&link.name),
+                mesh_cache,
+            )?;
+            Ok((shape, k::urdf::isometry_from(origin) * local_pose))
+        })
+        .collect()
+}
+
+/// Replace the shapes with the bounding primitive if it is set for the link
+fn with_link_bounding_primitive<T>(
+    link: &urdf_rs::Link,
+    options: &CollisionModelOptions,
+    shapes: Vec<(ShapeHandle<T>, na::Isometry3<T>)>,
+) -> Vec<(ShapeHandle<T>, na::Isometry3<T>)>
+where
+    T: Real,
+{
+    match options.link_bounding_primitive(&link.name) {
+        Some(primitive) if !shapes.is_empty() => bounding_primitive_shapes(shapes, primitive),
+        _ => shapes,
+    }
+}
+
+/// Returns the shapes and their poses of the `<collision>` elements of the link
+///
+/// The geometries which can not be loaded are skipped with the error log. `options.strict`
+/// is not used.
+fn urdf_link_to_shape_handles<T>(
+    link: &urdf_rs::Link,
+    options: &CollisionModelOptions,
+    resolver: &ResourceResolver,
+    mesh_cache: &MeshCache<T>,
+) -> Vec<(ShapeHandle<T>, na::Isometry3<T>)>
+where
+    T: Real,
+{
+    let shapes = load_urdf_link_geometries(link, options, resolver, mesh_cache)
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(shape) => Some(shape),
+            Err(err) => {
+                error!("link {}: {}", link.name, err);
+                None
             }
-            match load_mesh(path, &scale) {
-                Ok(mesh) => Some(ShapeHandle::new(mesh)),
-                Err(err) => {
-                    error!("load_mesh {:?} failed: {}", path, err);
-                    None
+        })
+        .collect();
+    with_link_bounding_primitive(link, options, shapes)
+}
+
+/// Returns the shapes and their poses of the `<collision>` elements of the link
+///
+/// Returns the error if any geometry can not be loaded.
+fn urdf_link_to_shape_handles_strict<T>(
+    link: &urdf_rs::Link,
+    options: &CollisionModelOptions,
+    resolver: &ResourceResolver,
+    mesh_cache: &MeshCache<T>,
+) -> Result<Vec<(ShapeHandle<T>, na::Isometry3<T>)>>
+where
+    T: Real,
+{
+    let shapes = load_urdf_link_geometries(link, options, resolver, mesh_cache)
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    Ok(with_link_bounding_primitive(link, options, shapes))
+}
+
+/// Returns the shapes of all the links in the order of `robot.links`
+///
+/// The geometries which can not be loaded are skipped with the error log even if
+/// `options.strict` is true.
+pub(crate) fn urdf_robot_to_shape_handles<T>(
+    robot: &urdf_rs::Robot,
+    options: &CollisionModelOptions,
+    mesh_cache: &MeshCache<T>,
+) -> Vec<Vec<(ShapeHandle<T>, na::Isometry3<T>)>>
+where
+    T: Real,
+{
+    let resolver = options.resolver();
+    robot
+        .links
+        .iter()
+        .map(|l| urdf_link_to_shape_handles(l, options, &*resolver, mesh_cache))
+        .collect()
+}
+
+/// Returns the shapes of all the links in the order of `robot.links`
+///
+/// Returns the error if `options.strict` is true and any geometry can not be loaded,
+/// otherwise it is the same as `urdf_robot_to_shape_handles()`.
+pub(crate) fn try_urdf_robot_to_shape_handles<T>(
+    robot: &urdf_rs::Robot,
+    options: &CollisionModelOptions,
+    mesh_cache: &MeshCache<T>,
+) -> Result<Vec<Vec<(ShapeHandle<T>, na::Isometry3<T>)>>>
+where
+    T: Real,
+{
+    if !options.strict {
+        return Ok(urdf_robot_to_shape_handles(robot, options, mesh_cache));
+    }
+    let resolver = options.resolver();
+    robot
+        .links
+        .iter()
+        .map(|l| urdf_link_to_shape_handles_strict(l, options, &*resolver, mesh_cache))
+        .collect()
+}
+
+/// Distance between the two AABBs, zero if they intersect
+fn aabb_distance<T>(aabb1: &AABB<T>, aabb2: &AABB<T>) -> T
+where
+    T: Real,
+{
+    let mut squared = T::zero();
+    for i in 0..3 {
+        let gap = (aabb1.mins()[i] - aabb2.maxs()[i])
+            .max(aabb2.mins()[i] - aabb1.maxs()[i])
+            .max(T::zero());
+        squared += gap * gap;
+    }
+    squared.sqrt()
+}
+
+/// Distance between the shape and the closest shape of `objects`
+///
+/// The shapes are checked in the order of the distances of their AABBs, so the ones which are
+/// farther than the closest shape are skipped.
+fn distance_to_objects<T, O>(pose: &na::Isometry3<T>, shape: &Shape<T>, objects: &O) -> T
+where
+    T: Real,
+    O: CollisionObjects<T>,
+{
+    let aabb = shape.aabb(pose);
+    let mut candidates = objects
+        .bounding_volumes()
+        .iter()
+        .map(|obstacle_aabb| aabb_distance(&aabb, obstacle_aabb))
+        .enumerate()
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+    let mut min = T::max_value();
+    for (i, lower_bound) in candidates {
+        if lower_bound >= min {
+            break;
+        }
+        let obstacle = &objects.shapes()[i];
+        min = min.min(query::distance(pose, shape, &obstacle.0, &*obstacle.1));
+    }
+    min
+}
+
+/// `<collision>` elements whose geometry is the `<capsule>` extension of URDF
+///
+/// `urdf_rs` does not know `<capsule>`, so `read_urdf_with_capsules` replaces it with
+/// `<cylinder>`, which has the same attributes, and remembers the replaced ones.
+#[derive(Debug, Clone, Default)]
+pub struct UrdfCapsules {
+    /// Pairs of the link name and the index of the `<collision>` in the link
+    collisions: HashSet<(String, usize)>,
+}
+
+impl UrdfCapsules {
+    /// Check if the `<collision>` of the link was `<capsule>`
+    pub fn is_capsule(&self, link_name: &str, collision_index: usize) -> bool {
+        self.collisions
+            .contains(&(link_name.to_owned(), collision_index))
+    }
+    pub fn len(&self) -> usize {
+        self.collisions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.collisions.is_empty()
+    }
+}
+
+/// Parse URDF which can contain `<capsule radius="..." length="..." />` in `<collision>`
+///
+/// The axis of the capsule is Z, and `length` is the length of the cylinder part. Only the
+/// `<capsule>` elements directly in `<geometry>` are replaced with `<cylinder>`, and the
+/// other elements are kept as they are.
+pub fn read_urdf_with_capsules(xml: &str) -> Result<(urdf_rs::Robot, UrdfCapsules)> {
+    let mut capsules = UrdfCapsules::default();
+    let mut link_name = None;
+    let mut collision_index = 0;
+    let mut in_collision = false;
+    // local names of the open elements and whether they are replaced
+    let mut elements: Vec<(String, bool)> = Vec::new();
+    let mut replaced = Vec::new();
+    {
+        let mut writer = EmitterConfig::new().create_writer(&mut replaced);
+        for event in EventReader::new(xml.as_bytes()) {
+            let event = event.map_err(|e| Error::from(format!("failed to parse URDF: {}", e)))?;
+            let result = match event {
+                XmlEvent::StartElement {
+                    ref name,
+                    ref attributes,
+                    ..
+                } => {
+                    let is_geometry = elements
+                        .last()
+                        .map(|&(ref parent, _)| parent == "geometry")
+                        .unwrap_or(false);
+                    let is_capsule = is_geometry && name.local_name == "capsule";
+                    match name.local_name.as_str() {
+                        "link" => {
+                            link_name = attributes
+                                .iter()
+                                .find(|attr| attr.name.local_name == "name")
+                                .map(|attr| attr.value.to_owned());
+                            collision_index = 0;
+                        }
+                        "collision" => in_collision = true,
+                        "capsule" if is_capsule && in_collision => {
+                            if let Some(ref link_name) = link_name {
+                                capsules
+                                    .collisions
+                                    .insert((link_name.to_owned(), collision_index));
+                            }
+                        }
+                        _ => {}
+                    }
+                    elements.push((name.local_name.clone(), is_capsule));
+                    if is_capsule {
+                        writer.write(attributes.iter().fold(
+                            WriterEvent::start_element("cylinder"),
+                            |element, attr| element.attr(attr.name.borrow(), &attr.value),
+                        ))
+                    } else {
+                        writer.write(event.as_writer_event().unwrap())
+                    }
                 }
+                XmlEvent::EndElement { ref name } => {
+                    match name.local_name.as_str() {
+                        "collision" => {
+                            in_collision = false;
+                            collision_index += 1;
+                        }
+                        "link" => link_name = None,
+                        _ => {}
+                    }
+                    match elements.pop() {
+                        Some((_, true)) => writer.write(WriterEvent::end_element()),
+                        _ => writer.write(event.as_writer_event().unwrap()),
+                    }
+                }
+                _ => match event.as_writer_event() {
+                    Some(writer_event) => writer.write(writer_event),
+                    None => Ok(()),
+                },
+            };
+            result.map_err(|e| Error::from(format!("failed to write URDF: {}", e)))?;
+        }
+    }
+    let replaced = String::from_utf8(replaced)
+        .map_err(|e| Error::from(format!("failed to write URDF: {}", e)))?;
+    Ok((urdf_rs::read_from_string(&replaced)?, capsules))
+}
+
+/// Options to create the collision models of the links from URDF
+#[derive(Debug, Clone, Default)]
+pub struct CollisionModelOptions {
+    /// Mesh files are loaded from this dir if the path does not start with "package://"
+    ///
+    /// This is not used if `resolver` is set.
+    pub base_dir: Option<PathBuf>,
+    /// Resolver of the mesh files, `Resolver` with `base_dir` is used if it is `None`
+    pub resolver: Option<Arc<ResourceResolver>>,
+    /// Returns the error if any collision geometry can not be loaded
+    ///
+    /// If it is false, the geometry is skipped with the error log.
+    pub strict: bool,
+    /// `<collision>` elements which were `<capsule>`
+    pub capsules: UrdfCapsules,
+    /// Mode for the meshes of all the links
+    pub mesh_mode: MeshMode,
+    /// Mode for the meshes of each link, the key is the link name of URDF
+    pub link_mesh_modes: HashMap<String, MeshMode>,
+    /// Use the `<visual>` elements for the links which have no `<collision>`
+    pub visual_fallback: bool,
+    /// Replace the shapes of all the links with the simplified primitive
+    pub bounding_primitive: Option<BoundingPrimitive>,
+    /// Primitive for each link, the key is the link name of URDF
+    pub link_bounding_primitives: HashMap<String, BoundingPrimitive>,
+}
+
+impl CollisionModelOptions {
+    /// Mode for the meshes of the link, `mesh_mode` is used if it is not in `link_mesh_modes`
+    pub fn link_mesh_mode(&self, link_name: &str) -> MeshMode {
+        self.link_mesh_modes
+            .get(link_name)
+            .cloned()
+            .unwrap_or(self.mesh_mode)
+    }
+    /// Primitive for the link, `bounding_primitive` is used if it is not in
+    /// `link_bounding_primitives`
+    pub fn link_bounding_primitive(&self, link_name: &str) -> Option<BoundingPrimitive> {
+        self.link_bounding_primitives
+            .get(link_name)
+            .cloned()
+            .or(self.bounding_primitive)
+    }
+    /// `resolver` or `Resolver` with `base_dir`
+    pub fn resolver(&self) -> Arc<ResourceResolver> {
+        match self.resolver {
+            Some(ref resolver) => resolver.clone(),
+            None => {
+                let resolver = Resolver::new();
+                Arc::new(match self.base_dir {
+                    Some(ref base_dir) => resolver.base_dir(base_dir),
+                    None => resolver,
+                })
             }
         }
     }
 }
 
+/// Signed distance between a link of the robot and an obstacle
+#[derive(Debug, Clone)]
+pub struct LinkDistance<T>
+where
+    T: Real,
+{
+    /// Name of the link (the same as the ones of `colliding_link_names`)
+    pub link_name: String,
+    /// Index of the closest shape in `CollisionObjects::shapes`
+    pub obstacle_index: usize,
+    /// Id of the closest object if the obstacles are named (e.g. `PlanningScene`)
+    pub obstacle_id: Option<String>,
+    /// Signed distance, it is negative when the link is penetrating the obstacle
+    pub distance: T,
+    /// The closest (or the deepest) point on the link in world frame
+    pub link_point: na::Point3<T>,
+    /// The closest (or the deepest) point on the obstacle in world frame
+    pub obstacle_point: na::Point3<T>,
+}
+
+/// Contact between a link of the robot and an obstacle
+#[derive(Debug, Clone)]
+pub struct ContactReport<T>
+where
+    T: Real,
+{
+    /// Name of the link (the same as the ones of `colliding_link_names`)
+    pub link_name: String,
+    /// Index of the shape in `CollisionObjects::shapes`
+    pub obstacle_index: usize,
+    /// Id of the object if the obstacles are named (e.g. `PlanningScene`)
+    pub obstacle_id: Option<String>,
+    /// The deepest point on the link in world frame
+    pub link_point: na::Point3<T>,
+    /// The deepest point on the obstacle in world frame
+    pub obstacle_point: na::Point3<T>,
+    /// Contact normal, directed from the link to the obstacle
+    pub normal: na::Unit<Vector3<T>>,
+    /// Penetration depth, it is negative if the shapes are only closer than the margin
+    pub depth: T,
+}
+
+fn to_f64<T>(value: T) -> f64
+where
+    T: Float,
+{
+    value.to_f64().unwrap_or(::std::f64::NAN)
+}
+
+impl<T> ContactReport<T>
+where
+    T: Real + Float,
+{
+    /// Convert to `f64` to be stored in `Error`
+    pub fn to_f64(&self) -> ContactReport<f64> {
+        let link_point = &self.link_point;
+        let obstacle_point = &self.obstacle_point;
+        let normal = self.normal.as_ref();
+        ContactReport {
+            link_name: self.link_name.to_owned(),
+            obstacle_index: self.obstacle_index,
+            obstacle_id: self.obstacle_id.clone(),
+            link_point: na::Point3::new(
+                to_f64(link_point.x),
+                to_f64(link_point.y),
+                to_f64(link_point.z),
+            ),
+            obstacle_point: na::Point3::new(
+                to_f64(obstacle_point.x),
+                to_f64(obstacle_point.y),
+                to_f64(obstacle_point.z),
+            ),
+            normal: na::Unit::new_unchecked(Vector3::new(
+                to_f64(normal.x),
+                to_f64(normal.y),
+                to_f64(normal.z),
+            )),
+            depth: to_f64(self.depth),
+        }
+    }
+}
+
+/// Returns (signed distance, point on shape1, point on shape2) in world frame
+///
+/// Returns `None` if neither the closest points nor the contact are found.
+fn signed_distance<T>(
+    pose1: &na::Isometry3<T>,
+    shape1: &Shape<T>,
+    pose2: &na::Isometry3<T>,
+    shape2: &Shape<T>,
+) -> Option<(T, na::Point3<T>, na::Point3<T>)>
+where
+    T: Real,
+{
+    let distance = query::distance(pose1, shape1, pose2, shape2);
+    if distance > T::zero() {
+        // the margin only needs to be larger than the distance
+        let margin = distance * na::convert(2.0);
+        if let ClosestPoints::WithinMargin(point1, point2) =
+            query::closest_points(pose1, shape1, pose2, shape2, margin)
+        {
+            return Some((distance, point1, point2));
+        }
+    }
+    query::contact(pose1, shape1, pose2, shape2, T::zero())
+        .map(|contact| (-contact.depth, contact.world1, contact.world2))
+}
+
+/// Collision object which is attached to a link and moves with it
+///
+/// It is checked as a part of the robot, and reported by its id instead of the link name.
+#[derive(Clone)]
+pub struct AttachedObject<T>
+where
+    T: Real,
+{
+    /// The object, `object.pose` is relative to the link
+    pub object: CollisionObject<T>,
+    /// Name of the link (the same as the ones of `colliding_link_names`)
+    pub link_name: String,
+    /// Links which are allowed to touch the object (e.g. the gripper fingers)
+    pub touch_links: Vec<String>,
+    /// Shapes and their poses in the link frame
+    shapes: Vec<(ShapeHandle<T>, na::Isometry3<T>)>,
+    /// Leaf spheres of `shapes` for `CollisionChecker::field_distances()`
+    spheres: Arc<Vec<(na::Point3<T>, T)>>,
+}
+
+impl<T> AttachedObject<T>
+where
+    T: Real,
+{
+    /// Attach `object` to the link, `object.pose` must be relative to the link
+    pub fn new(object: CollisionObject<T>, link_name: &str, touch_links: Vec<String>) -> Self {
+        let shapes = object
+            .shapes
+            .iter()
+            .map(|&(ref local_pose, ref shape)| (shape.clone(), object.pose * local_pose))
+            .collect::<Vec<_>>();
+        let spheres = Arc::new(SphereTree::new(&shapes).leaf_spheres());
+        AttachedObject {
+            object,
+            link_name: link_name.to_owned(),
+            touch_links,
+            shapes,
+            spheres,
+        }
+    }
+}
+
+/// Link or attached object which has collision models
+struct CollisionBody<'a, T>
+where
+    T: Real,
+{
+    name: String,
+    pose: na::Isometry3<T>,
+    models: &'a [(ShapeHandle<T>, na::Isometry3<T>)],
+    padding: T,
+    attached: Option<&'a AttachedObject<T>>,
+}
+
 /// Collision checker for a robot
+///
+/// The attached objects are checked as parts of the robot. They are reported by their ids
+/// in the place of the link names.
+///
+/// It is cheap to clone because the collision models are shared by the clones.
+#[derive(Clone)]
 pub struct CollisionChecker<T>
 where
     T: Real,
 {
-    name_collision_model_map: HashMap<String, Vec<(ShapeHandle<T>, na::Isometry3<T>)>>,
+    name_collision_model_map: Arc<HashMap<String, Vec<(ShapeHandle<T>, na::Isometry3<T>)>>>,
     /// margin length for collision check
+    ///
+    /// This is the default padding of the links which are not in `link_paddings`.
     pub prediction: T,
+    /// Padding of each link, the key is the link name (same as `colliding_link_names`)
+    pub link_paddings: HashMap<String, T>,
+    /// Padding of each obstacle, the key is the id of the object
+    ///
+    /// The id is `CollisionObjects::obstacle_id` (e.g. the id of the object in
+    /// `PlanningScene`), or the index of the shape if the obstacles are not named
+    /// (e.g. `Compound`). The margin between a link and an obstacle is the sum of the link
+    /// padding and the obstacle padding. The obstacles which are not in this map have no
+    /// padding. If the obstacles have their own paddings (e.g. `CollisionObject::padding`),
+    /// they are used first.
+    pub obstacle_paddings: HashMap<String, T>,
+    /// Link pairs which are not checked by the self collision check
+    ///
+    /// Adjacent links are always skipped, so they don't need to be added.
+    pub allowed_collision_matrix: AllowedCollisionMatrix,
+    attached_objects: Vec<AttachedObject<T>>,
+    mesh_cache: MeshCache<T>,
+    /// Sphere trees of the links, the exact shapes are used if it is `None`
+    sphere_trees: Option<Arc<HashMap<String, SphereTree<T>>>>,
+    /// Leaf spheres of the links for `field_distances()`, which are created at the first use
+    field_spheres: Arc<Mutex<HashMap<String, Arc<Vec<(na::Point3<T>, T)>>>>>,
 }
 
 impl<T> CollisionChecker<T>
@@ -162,8 +703,15 @@ where
         prediction: T,
     ) -> Self {
         CollisionChecker {
-            name_collision_model_map,
+            name_collision_model_map: Arc::new(name_collision_model_map),
             prediction,
+            link_paddings: HashMap::new(),
+            obstacle_paddings: HashMap::new(),
+            allowed_collision_matrix: AllowedCollisionMatrix::new(),
+            attached_objects: Vec::new(),
+            mesh_cache: MeshCache::new(),
+            sphere_trees: None,
+            field_spheres: Arc::new(Mutex::new(HashMap::new())),
         }
     }
     /// Create CollisionChecker from urdf_rs::Robot
@@ -177,18 +725,88 @@ where
         urdf_robot: &urdf_rs::Robot,
         base_dir: Option<&Path>,
         prediction: T,
+    ) -> Self {
+        let options = CollisionModelOptions {
+            base_dir: base_dir.map(Path::to_path_buf),
+            ..Default::default()
+        };
+        Self::from_urdf_robot_without_strict(urdf_robot, &options, prediction)
+    }
+    /// Create CollisionChecker from urdf_rs::Robot finding the mesh files by `resolver`
+    pub fn from_urdf_robot_with_resolver(
+        urdf_robot: &urdf_rs::Robot,
+        resolver: Arc<ResourceResolver>,
+        prediction: T,
+    ) -> Self {
+        let options = CollisionModelOptions {
+            resolver: Some(resolver),
+            ..Default::default()
+        };
+        Self::from_urdf_robot_without_strict(urdf_robot, &options, prediction)
+    }
+    /// Create CollisionChecker from urdf_rs::Robot which is read by `read_urdf_with_capsules`
+    ///
+    /// The `<cylinder>` elements which were `<capsule>` are loaded as capsules.
+    pub fn from_urdf_robot_with_capsules(
+        urdf_robot: &urdf_rs::Robot,
+        capsules: &UrdfCapsules,
+        base_dir: Option<&Path>,
+        prediction: T,
+    ) -> Self {
+        let options = CollisionModelOptions {
+            base_dir: base_dir.map(Path::to_path_buf),
+            capsules: capsules.clone(),
+            ..Default::default()
+        };
+        Self::from_urdf_robot_without_strict(urdf_robot, &options, prediction)
+    }
+    /// Create CollisionChecker from urdf_rs::Robot with the options for the collision models
+    ///
+    /// Returns the error only if `options.strict` is true.
+    pub fn from_urdf_robot_with_options(
+        urdf_robot: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+        prediction: T,
+    ) -> Result<Self> {
+        Self::from_urdf_robot_with_mesh_cache(urdf_robot, options, &MeshCache::new(), prediction)
+    }
+    /// Create CollisionChecker from urdf_rs::Robot sharing the loaded meshes in `mesh_cache`
+    ///
+    /// Use `mesh_cache()` of another checker to share the meshes with it.
+    pub fn from_urdf_robot_with_mesh_cache(
+        urdf_robot: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+        mesh_cache: &MeshCache<T>,
+        prediction: T,
+    ) -> Result<Self> {
+        let link_shapes = try_urdf_robot_to_shape_handles(urdf_robot, options, mesh_cache)?;
+        Ok(Self::from_link_shapes(
+            urdf_robot,
+            link_shapes,
+            mesh_cache,
+            prediction,
+        ))
+    }
+    /// Skip the geometries which can not be loaded even if `options.strict` is true
+    fn from_urdf_robot_without_strict(
+        urdf_robot: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+        prediction: T,
+    ) -> Self {
+        let mesh_cache = MeshCache::new();
+        let link_shapes = urdf_robot_to_shape_handles(urdf_robot, options, &mesh_cache);
+        Self::from_link_shapes(urdf_robot, link_shapes, &mesh_cache, prediction)
+    }
+    /// `link_shapes` are the shapes of the links in the order of `urdf_robot.links`
+    fn from_link_shapes(
+        urdf_robot: &urdf_rs::Robot,
+        link_shapes: Vec<Vec<(ShapeHandle<T>, na::Isometry3<T>)>>,
+        mesh_cache: &MeshCache<T>,
+        prediction: T,
     ) -> Self {
         let mut name_collision_model_map = HashMap::new();
         let link_joint_map = k::urdf::link_to_joint_map(&urdf_robot);
-        for l in &urdf_robot.links {
-            let col_pose_vec = l
-                .collision
-                .iter()
-                .filter_map(|collision| {
-                    urdf_geometry_to_shape_handle(&collision.geometry, base_dir)
-                        .map(|col| (col, k::urdf::isometry_from(&collision.origin)))
-                })
-                .collect::<Vec<_>>();
+        for (l, col_pose_vec) in urdf_robot.links.iter().zip(link_shapes) {
             debug!("name={}, ln={}", l.name, col_pose_vec.len());
             if !col_pose_vec.is_empty() {
                 if let Some(joint_name) = link_joint_map.get(&l.name) {
@@ -197,9 +815,178 @@ where
             }
         }
         CollisionChecker {
-            name_collision_model_map,
+            name_collision_model_map: Arc::new(name_collision_model_map),
             prediction,
+            link_paddings: HashMap::new(),
+            obstacle_paddings: HashMap::new(),
+            allowed_collision_matrix: AllowedCollisionMatrix::new(),
+            attached_objects: Vec::new(),
+            mesh_cache: mesh_cache.clone(),
+            sphere_trees: None,
+            field_spheres: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Check the sphere trees of the links before their exact shapes
+    ///
+    /// The trees are generated from the collision models of the links. The results are the
+    /// same, but it is faster if the links have many shapes or meshes. The attached objects
+    /// are always checked by their shapes.
+    pub fn enable_sphere_trees(&mut self) {
+        let trees = self
+            .name_collision_model_map
+            .iter()
+            .map(|(name, models)| (name.to_owned(), SphereTree::new(models)))
+            .collect();
+        self.sphere_trees = Some(Arc::new(trees));
+    }
+    /// Check the exact shapes only
+    pub fn disable_sphere_trees(&mut self) {
+        self.sphere_trees = None;
+    }
+    /// Sphere tree of the link if `enable_sphere_trees()` is called
+    pub fn sphere_tree(&self, link_name: &str) -> Option<&SphereTree<T>> {
+        self.sphere_trees
+            .as_ref()
+            .and_then(|trees| trees.get(link_name))
+    }
+    fn sphere_tree_of(&self, body: &CollisionBody<T>) -> Option<&SphereTree<T>> {
+        if body.attached.is_some() {
+            None
+        } else {
+            self.sphere_tree(&body.name)
+        }
+    }
+    /// Leaf spheres of the link or the attached object in the body frame
+    ///
+    /// The spheres of the links are cached, so the sphere trees are built only once.
+    fn field_spheres_of(&self, body: &CollisionBody<T>) -> Arc<Vec<(na::Point3<T>, T)>> {
+        if let Some(attached) = body.attached {
+            return attached.spheres.clone();
+        }
+        self.field_spheres
+            .lock()
+            .unwrap()
+            .entry(body.name.clone())
+            .or_insert_with(|| {
+                Arc::new(match self.sphere_tree(&body.name) {
+                    Some(tree) => tree.leaf_spheres(),
+                    None => SphereTree::new(body.models).leaf_spheres(),
+                })
+            })
+            .clone()
+    }
+    /// Cache of the meshes which are loaded by this checker
+    pub fn mesh_cache(&self) -> &MeshCache<T> {
+        &self.mesh_cache
+    }
+    /// Padding of the link, `prediction` is used if it is not in `link_paddings`
+    pub fn link_padding(&self, link_name: &str) -> T {
+        self.link_paddings
+            .get(link_name)
+            .cloned()
+            .unwrap_or(self.prediction)
+    }
+    /// Padding of the obstacle, zero if it is not in `obstacle_paddings`
+    pub fn obstacle_padding(&self, obstacle_id: &str) -> T {
+        self.obstacle_paddings
+            .get(obstacle_id)
+            .cloned()
+            .unwrap_or_else(T::zero)
+    }
+    /// Padding of the obstacle in `objects`
+    fn padding_of<O>(&self, objects: &O, obstacle_index: usize) -> T
+    where
+        O: CollisionObjects<T>,
+    {
+        if let Some(padding) = objects.obstacle_padding(obstacle_index) {
+            return padding;
+        }
+        if self.obstacle_paddings.is_empty() {
+            return T::zero();
         }
+        match objects.obstacle_id(obstacle_index) {
+            Some(id) => self.obstacle_padding(id),
+            None => self.obstacle_padding(&obstacle_index.to_string()),
+        }
+    }
+    pub(crate) fn max_obstacle_padding<O>(&self, objects: &O) -> T
+    where
+        O: CollisionObjects<T>,
+    {
+        (0..objects.shapes().len())
+            .map(|i| self.padding_of(objects, i))
+            .fold(T::zero(), |max, padding| max.max(padding))
+    }
+    /// Attach the object to the link, the object which has the same id is replaced
+    ///
+    /// `object.pose` must be relative to the link. The padding of the object is used if it
+    /// exists, otherwise the padding of the link is used.
+    pub fn attach_object(
+        &mut self,
+        object: CollisionObject<T>,
+        link_name: &str,
+        touch_links: Vec<String>,
+    ) {
+        self.attached_objects.retain(|a| a.object.id != object.id);
+        self.attached_objects
+            .push(AttachedObject::new(object, link_name, touch_links));
+    }
+    /// Detach the object and return it
+    pub fn detach_object(&mut self, id: &str) -> Option<AttachedObject<T>> {
+        let index = self
+            .attached_objects
+            .iter()
+            .position(|a| a.object.id == id)?;
+        Some(self.attached_objects.remove(index))
+    }
+    /// Find the attached object by id
+    pub fn attached_object(&self, id: &str) -> Option<&AttachedObject<T>> {
+        self.attached_objects.iter().find(|a| a.object.id == id)
+    }
+    /// All the attached objects
+    pub fn attached_objects(&self) -> &[AttachedObject<T>] {
+        &self.attached_objects
+    }
+    /// Links which have collision models and the attached objects
+    ///
+    /// `robot.update_transforms()` must be called before.
+    fn collision_bodies<'a>(&'a self, robot: &k::Chain<T>) -> Vec<CollisionBody<'a, T>> {
+        let mut bodies = Vec::new();
+        for node in robot.iter() {
+            let name = node.joint().name.to_owned();
+            if let Some(models) = self.name_collision_model_map.get(&name) {
+                bodies.push(CollisionBody {
+                    padding: self.link_padding(&name),
+                    name,
+                    pose: node.world_transform().unwrap(),
+                    models,
+                    attached: None,
+                });
+            }
+        }
+        for attached in &self.attached_objects {
+            let node = match robot.find(&attached.link_name) {
+                Some(node) => node,
+                None => {
+                    warn!(
+                        "link {} of attached object {} not found",
+                        attached.link_name, attached.object.id
+                    );
+                    continue;
+                }
+            };
+            bodies.push(CollisionBody {
+                name: attached.object.id.to_owned(),
+                pose: node.world_transform().unwrap(),
+                models: &attached.shapes,
+                padding: attached
+                    .object
+                    .padding
+                    .unwrap_or_else(|| self.link_padding(&attached.link_name)),
+                attached: Some(attached),
+            });
+        }
+        bodies
     }
     /// Check if there are any colliding links
     pub fn has_any_colliding(
@@ -230,47 +1017,727 @@ where
         first_return: bool,
     ) -> Vec<String> {
         let mut names = Vec::new();
+        let mut near_models = Vec::new();
+        let target_aabb = target_shape.aabb(target_pose);
         robot.update_transforms();
-        for joint in robot.iter() {
-            let trans = joint.world_transform().unwrap();
-            let joint_name = &joint.joint().name;
-            match self.name_collision_model_map.get(joint_name) {
-                Some(obj_vec) => {
-                    for obj in obj_vec {
-                        let ctct = query::proximity(
-                            &(trans * obj.1),
-                            &*obj.0,
-                            target_pose,
-                            target_shape,
-                            self.prediction,
+        for body in self.collision_bodies(robot) {
+            let is_model_colliding = |obj: &(ShapeHandle<T>, na::Isometry3<T>)| {
+                is_within_margin(
+                    &(body.pose * obj.1),
+                    &*obj.0,
+                    target_pose,
+                    target_shape,
+                    body.padding,
+                )
+            };
+            let is_colliding = match self.sphere_tree_of(&body) {
+                Some(tree) => {
+                    tree.intersecting_models(
+                        &body.pose,
+                        &target_aabb,
+                        body.padding,
+                        &mut near_models,
+                    );
+                    near_models
+                        .iter()
+                        .any(|&i| is_model_colliding(&body.models[i]))
+                }
+                None => body.models.iter().any(is_model_colliding),
+            };
+            if is_colliding {
+                names.push(body.name);
+                if first_return {
+                    return names;
+                }
+            }
+        }
+        names
+    }
+    /// Check if there are any links colliding with the shapes in `objects`
+    ///
+    /// The bounding volume tree of `objects` is used to skip the shapes which are far from
+    /// the links, so it is faster than checking each shape by `has_any_colliding`.
+    pub fn has_any_colliding_with_objects<O>(&self, robot: &k::Chain<T>, objects: &O) -> bool
+    where
+        O: CollisionObjects<T>,
+    {
+        !self
+            .colliding_link_names_with_objects_and_first_return_flag(robot, objects, true)
+            .is_empty()
+    }
+    /// Returns the names of the links which are colliding with any shapes in `objects`
+    pub fn colliding_link_names_with_objects<O>(
+        &self,
+        robot: &k::Chain<T>,
+        objects: &O,
+    ) -> Vec<String>
+    where
+        O: CollisionObjects<T>,
+    {
+        self.colliding_link_names_with_objects_and_first_return_flag(robot, objects, false)
+    }
+
+    /// Collect the indices of the shapes in `objects` whose AABB intersects with the shape
+    fn collect_obstacle_candidates<O>(
+        &self,
+        pose: &na::Isometry3<T>,
+        shape: &Shape<T>,
+        margin: T,
+        objects: &O,
+        candidates: &mut Vec<usize>,
+    ) where
+        O: CollisionObjects<T>,
+    {
+        objects.interferences_with_aabb(&shape.aabb(pose).loosened(margin), candidates);
+    }
+
+    fn colliding_link_names_with_objects_and_first_return_flag<O>(
+        &self,
+        robot: &k::Chain<T>,
+        objects: &O,
+        first_return: bool,
+    ) -> Vec<String>
+    where
+        O: CollisionObjects<T>,
+    {
+        let mut names = Vec::new();
+        let mut candidates = Vec::new();
+        let mut near_models = Vec::new();
+        let max_obstacle_padding = self.max_obstacle_padding(objects);
+        robot.update_transforms();
+        for body in self.collision_bodies(robot) {
+            let link_padding = body.padding;
+            let is_model_colliding = |obj: &(ShapeHandle<T>, na::Isometry3<T>), i: usize| {
+                let obstacle = &objects.shapes()[i];
+                is_within_margin(
+                    &(body.pose * obj.1),
+                    &*obj.0,
+                    &obstacle.0,
+                    &*obstacle.1,
+                    link_padding + self.padding_of(objects, i),
+                )
+            };
+            let is_colliding = match self.sphere_tree_of(&body) {
+                Some(tree) => {
+                    // the spheres are checked first, and the exact shapes only near the obstacle
+                    let root_aabb = match tree.root_aabb(&body.pose) {
+                        Some(aabb) => aabb.loosened(link_padding + max_obstacle_padding),
+                        None => continue,
+                    };
+                    objects.interferences_with_aabb(&root_aabb, &mut candidates);
+                    candidates.iter().any(|&i| {
+                        tree.intersecting_models(
+                            &body.pose,
+                            &objects.bounding_volumes()[i],
+                            link_padding + self.padding_of(objects, i),
+                            &mut near_models,
                         );
-                        if ctct != Proximity::Disjoint {
-                            names.push(joint_name.to_owned());
-                            if first_return {
-                                return names;
-                            } else {
-                                break;
+                        near_models
+                            .iter()
+                            .any(|&model| is_model_colliding(&body.models[model], i))
+                    })
+                }
+                None => body.models.iter().any(|obj| {
+                    self.collect_obstacle_candidates(
+                        &(body.pose * obj.1),
+                        &*obj.0,
+                        link_padding + max_obstacle_padding,
+                        objects,
+                        &mut candidates,
+                    );
+                    candidates.iter().any(|&i| is_model_colliding(obj, i))
+                }),
+            };
+            if is_colliding {
+                names.push(body.name);
+                if first_return {
+                    return names;
+                }
+            }
+        }
+        names
+    }
+    /// Returns the distance between each link and `objects`
+    ///
+    /// It is faster than `closest_obstacles` because the closest points are not computed.
+    /// The link padding and the largest obstacle padding are subtracted from the distance,
+    /// so the returned value can be smaller than the actual one.
+    /// If `objects` is empty, the returned vector is empty.
+    pub fn link_distances<O>(&self, robot: &k::Chain<T>, objects: &O) -> Vec<(String, T)>
+    where
+        O: CollisionObjects<T>,
+    {
+        let mut distances = Vec::new();
+        if objects.shapes().is_empty() {
+            return distances;
+        }
+        let max_obstacle_padding = self.max_obstacle_padding(objects);
+        robot.update_transforms();
+        for body in self.collision_bodies(robot) {
+            let distance = body
+                .models
+                .iter()
+                .map(|obj| distance_to_objects(&(body.pose * obj.1), &*obj.0, objects))
+                .fold(T::max_value(), |min, d| min.min(d));
+            let padding = body.padding + max_obstacle_padding;
+            distances.push((body.name, distance - padding));
+        }
+        distances
+    }
+    /// Returns the upper bound of the motion of each link for the unit motion of the joints
+    ///
+    /// The returned map contains the coefficients for `joint_names` for each link and
+    /// each attached object.
+    /// If the joint `i` moves `dq_i`, any point of the link moves at most
+    /// `sum(coefficients[i] * |dq_i|)`. This is the bound of the norm of the Jacobian,
+    /// which does not depend on the current joint positions.
+    pub fn link_motion_bounds(
+        &self,
+        robot: &k::Chain<T>,
+        joint_names: &[String],
+    ) -> HashMap<String, Vec<T>> {
+        let mut bounds = HashMap::new();
+        let links = robot.iter().filter_map(|node| {
+            let link_name = node.joint().name.to_owned();
+            self.name_collision_model_map
+                .get(&link_name)
+                .map(|obj_vec| (link_name, node, obj_vec.as_slice()))
+        });
+        let attached_objects = self.attached_objects.iter().filter_map(|attached| {
+            robot.find(&attached.link_name).map(|node| {
+                (
+                    attached.object.id.to_owned(),
+                    node,
+                    attached.shapes.as_slice(),
+                )
+            })
+        });
+        for (link_name, node, obj_vec) in links.chain(attached_objects) {
+            // the radius of the link from the origin of its joint
+            let mut radius = obj_vec
+                .iter()
+                .map(|obj| {
+                    let aabb = obj.0.aabb(&obj.1);
+                    aabb.center().coords.norm() + aabb.half_extents().norm()
+                })
+                .fold(T::zero(), |max, r| max.max(r));
+            let mut coefficients = vec![T::zero(); joint_names.len()];
+            let mut current = Some(node.clone());
+            while let Some(node) = current {
+                {
+                    let joint = node.joint();
+                    let is_linear =
+                        matches!(joint.joint_type, k::joint::JointType::Linear { .. });
+                    if let Some(i) = joint_names.iter().position(|name| *name == joint.name) {
+                        coefficients[i] = if is_linear { T::one() } else { radius };
+                    }
+                    radius += joint.origin().translation.vector.norm();
+                    if is_linear {
+                        if let Some(ref range) = joint.limits {
+                            radius += range.min.abs().max(range.max.abs());
+                        }
+                    }
+                }
+                current = node.parent();
+            }
+            bounds.insert(link_name, coefficients);
+        }
+        bounds
+    }
+    /// Returns the AABBs of the links and the attached objects which contain them while the
+    /// joints move by `joint_motions` from the current positions
+    ///
+    /// `link_motion_bounds` is the result of `link_motion_bounds` for the joints of
+    /// `joint_motions`. The AABBs are loosened by the paddings of the links and `margin`.
+    pub fn swept_aabbs(
+        &self,
+        robot: &k::Chain<T>,
+        link_motion_bounds: &HashMap<String, Vec<T>>,
+        joint_motions: &[T],
+        margin: T,
+    ) -> Vec<AABB<T>> {
+        robot.update_transforms();
+        self.collision_bodies(robot)
+            .into_iter()
+            .filter_map(|body| {
+                let motion = link_motion_bounds.get(&body.name).map_or(T::zero(), |c| {
+                    c.iter()
+                        .zip(joint_motions)
+                        .fold(T::zero(), |sum, (c, d)| sum + *c * d.abs())
+                });
+                body.models
+                    .iter()
+                    .map(|obj| obj.0.aabb(&(body.pose * obj.1)))
+                    .fold(None, |merged: Option<AABB<T>>, aabb| match merged {
+                        Some(merged) => Some(merged.merged(&aabb)),
+                        None => Some(aabb),
+                    })
+                    .map(|aabb| aabb.loosened(body.padding + margin + motion))
+            })
+            .collect()
+    }
+    /// Returns the closest shape of `objects` and the signed distance for each link
+    ///
+    /// The distance is measured between the padded shapes, which means the link padding and
+    /// the obstacle padding are subtracted. The witness points are on the actual shapes.
+    /// The links without collision models are not included. If `objects` is empty,
+    /// the returned vector is empty.
+    pub fn closest_obstacles<O>(&self, robot: &k::Chain<T>, objects: &O) -> Vec<LinkDistance<T>>
+    where
+        O: CollisionObjects<T>,
+    {
+        let mut distances = Vec::new();
+        robot.update_transforms();
+        for body in self.collision_bodies(robot) {
+            let link_padding = body.padding;
+            let mut closest: Option<LinkDistance<T>> = None;
+            for obj in body.models {
+                let link_pose = body.pose * obj.1;
+                for (obstacle_index, obstacle) in objects.shapes().iter().enumerate() {
+                    let (distance, link_point, obstacle_point) =
+                        match signed_distance(&link_pose, &*obj.0, &obstacle.0, &*obstacle.1) {
+                            Some(result) => result,
+                            None => {
+                                warn!(
+                                    "no closest points between {} and obstacle {}",
+                                    body.name, obstacle_index
+                                );
+                                continue;
                             }
+                        };
+                    let distance =
+                        distance - link_padding - self.padding_of(objects, obstacle_index);
+                    let is_closer = match closest {
+                        Some(ref current) => distance < current.distance,
+                        None => true,
+                    };
+                    if is_closer {
+                        closest = Some(LinkDistance {
+                            link_name: body.name.to_owned(),
+                            obstacle_index,
+                            obstacle_id: objects.obstacle_id(obstacle_index).map(String::from),
+                            distance,
+                            link_point,
+                            obstacle_point,
+                        });
+                    }
+                }
+            }
+            if let Some(closest) = closest {
+                distances.push(closest);
+            }
+        }
+        distances
+    }
+    /// Returns the contacts between the links and the shapes of `objects`
+    ///
+    /// A contact is reported for every pair of a link and an obstacle shape which are closer
+    /// than the sum of their paddings. If a link has several collision models, the deepest one
+    /// is reported.
+    pub fn contacts<O>(&self, robot: &k::Chain<T>, objects: &O) -> Vec<ContactReport<T>>
+    where
+        O: CollisionObjects<T>,
+    {
+        let mut contacts = Vec::new();
+        let max_obstacle_padding = self.max_obstacle_padding(objects);
+        robot.update_transforms();
+        for body in self.collision_bodies(robot) {
+            let link_padding = body.padding;
+            let mut candidates = Vec::new();
+            for obj in body.models {
+                let mut obj_candidates = Vec::new();
+                self.collect_obstacle_candidates(
+                    &(body.pose * obj.1),
+                    &*obj.0,
+                    link_padding + max_obstacle_padding,
+                    objects,
+                    &mut obj_candidates,
+                );
+                candidates.append(&mut obj_candidates);
+            }
+            candidates.sort();
+            candidates.dedup();
+            for obstacle_index in candidates {
+                let obstacle = &objects.shapes()[obstacle_index];
+                let margin = link_padding + self.padding_of(objects, obstacle_index);
+                let mut deepest: Option<query::Contact<T>> = None;
+                for obj in body.models {
+                    if let Some(contact) = query::contact(
+                        &(body.pose * obj.1),
+                        &*obj.0,
+                        &obstacle.0,
+                        &*obstacle.1,
+                        margin,
+                    ) {
+                        let is_deeper = match deepest {
+                            Some(ref deepest) => contact.depth > deepest.depth,
+                            None => true,
+                        };
+                        if is_deeper {
+                            deepest = Some(contact);
                         }
                     }
                 }
-                None => {
-                    debug!("collision model {} not found", joint_name);
+                if let Some(contact) = deepest {
+                    contacts.push(ContactReport {
+                        link_name: body.name.to_owned(),
+                        obstacle_index,
+                        obstacle_id: objects.obstacle_id(obstacle_index).map(String::from),
+                        link_point: contact.world1,
+                        obstacle_point: contact.world2,
+                        normal: contact.normal,
+                        depth: contact.depth,
+                    });
                 }
             }
         }
-        names
+        contacts
+    }
+    /// Remove the points which are on the links or the attached objects
+    ///
+    /// The points which are closer than `padding` to the collision models are removed,
+    /// so the robot does not see itself as an obstacle in the clouds of the sensors.
+    pub fn filter_robot_points(
+        &self,
+        robot: &k::Chain<T>,
+        points: &[na::Point3<T>],
+        padding: T,
+    ) -> Vec<na::Point3<T>> {
+        robot.update_transforms();
+        let models = self
+            .collision_bodies(robot)
+            .iter()
+            .flat_map(|body| {
+                body.models.iter().map(move |obj| {
+                    let pose = body.pose * obj.1;
+                    let aabb = obj.0.aabb(&pose).loosened(padding);
+                    (pose, obj.0.clone(), aabb)
+                })
+            })
+            .collect::<Vec<_>>();
+        points
+            .iter()
+            .filter(|point| {
+                !models.iter().any(|&(ref pose, ref shape, ref aabb)| {
+                    aabb.intersects(&AABB::new(**point, **point))
+                        && match shape.as_point_query() {
+                            Some(query) => query.distance_to_point(pose, *point, true) <= padding,
+                            None => true,
+                        }
+                })
+            })
+            .cloned()
+            .collect()
+    }
+    /// Check if there are any pairs of colliding links in the robot
+    pub fn has_self_colliding(&self, robot: &k::Chain<T>) -> bool {
+        !self
+            .self_colliding_link_pairs_with_first_return_flag(robot, true)
+            .is_empty()
+    }
+    /// Returns the pairs of names of the links which are colliding with each other
+    ///
+    /// The attached objects are included and reported by their ids.
+    /// Adjacent links, the touch links of the attached objects and the pairs in
+    /// `allowed_collision_matrix` are skipped.
+    /// The larger padding of the two links is used as the margin.
+    pub fn self_colliding_link_pairs(&self, robot: &k::Chain<T>) -> Vec<(String, String)> {
+        self.self_colliding_link_pairs_with_first_return_flag(robot, false)
+    }
+
+    /// Returns the name of the nearest ancestor which has collision models
+    fn adjacent_parent_name(&self, node: &k::Node<T>) -> Option<String> {
+        let mut parent = node.parent();
+        while let Some(node) = parent {
+            let name = node.joint().name.to_owned();
+            if self.name_collision_model_map.contains_key(&name) {
+                return Some(name);
+            }
+            parent = node.parent();
+        }
+        None
+    }
+
+    fn self_colliding_link_pairs_with_first_return_flag(
+        &self,
+        robot: &k::Chain<T>,
+        first_return: bool,
+    ) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        robot.update_transforms();
+        // the names of the bodies which are allowed to touch each body
+        let bodies = self
+            .collision_bodies(robot)
+            .into_iter()
+            .map(|body| {
+                let touch_names = match body.attached {
+                    Some(attached) => {
+                        let mut names = attached.touch_links.clone();
+                        names.push(attached.link_name.to_owned());
+                        names
+                    }
+                    None => robot
+                        .find(&body.name)
+                        .and_then(|node| self.adjacent_parent_name(node))
+                        .into_iter()
+                        .collect(),
+                };
+                (body, touch_names)
+            })
+            .collect::<Vec<_>>();
+        for (i, &(ref body1, ref touch1)) in bodies.iter().enumerate() {
+            for &(ref body2, ref touch2) in bodies.iter().skip(i + 1) {
+                let name1 = &body1.name;
+                let name2 = &body2.name;
+                if touch1.contains(name2)
+                    || touch2.contains(name1)
+                    || self.allowed_collision_matrix.is_allowed(name1, name2)
+                {
+                    continue;
+                }
+                let margin = body1.padding.max(body2.padding);
+                let is_colliding = body1.models.iter().any(|obj1| {
+                    body2.models.iter().any(|obj2| {
+                        is_within_margin(
+                            &(body1.pose * obj1.1),
+                            &*obj1.0,
+                            &(body2.pose * obj2.1),
+                            &*obj2.0,
+                            margin,
+                        )
+                    })
+                });
+                if is_colliding {
+                    pairs.push((name1.to_owned(), name2.to_owned()));
+                    if first_return {
+                        return pairs;
+                    }
+                }
+            }
+        }
+        pairs
+    }
+}
+
+impl<T> CollisionChecker<T>
+where
+    T: Real + Float,
+{
+    /// Returns the distances between the links and the obstacles in the field
+    ///
+    /// The links are approximated by the leaf spheres of their sphere trees, so it takes
+    /// constant time for each sphere. The spheres are created at the first call and reused
+    /// even if `enable_sphere_trees()` is not called. The links whose spheres are out of the
+    /// field are not returned.
+    pub fn field_distances(
+        &self,
+        robot: &k::Chain<T>,
+        field: &SignedDistanceField<T>,
+    ) -> Vec<FieldDistance<T>> {
+        robot.update_transforms();
+        let mut distances = Vec::new();
+        for body in self.collision_bodies(robot) {
+            let spheres = self.field_spheres_of(&body);
+            let mut closest: Option<FieldDistance<T>> = None;
+            for &(center, radius) in spheres.iter() {
+                let point = body.pose * center;
+                if let Some((distance, gradient)) = field.distance_and_gradient(&point) {
+                    let distance = distance - radius - body.padding;
+                    if closest.as_ref().map(|c| distance < c.distance).unwrap_or(true) {
+                        closest = Some(FieldDistance {
+                            link_name: body.name.clone(),
+                            distance,
+                            gradient,
+                            point,
+                        });
+                    }
+                }
+            }
+            distances.extend(closest);
+        }
+        distances
     }
 }
 
 pub trait FromUrdf {
     fn from_urdf_robot(robot: &urdf_rs::Robot) -> Self;
+    /// Load the obstacles with the options
+    ///
+    /// `capsules` and `link_mesh_modes` of the options are keyed by the link names of the
+    /// obstacles. Returns the error only if `options.strict` is true.
+    ///
+    /// The default implementation ignores the options and calls `from_urdf_robot`.
+    fn from_urdf_robot_with_options(
+        robot: &urdf_rs::Robot,
+        _options: &CollisionModelOptions,
+    ) -> Result<Self>
+    where
+        Self: ::std::marker::Sized,
+    {
+        Ok(Self::from_urdf_robot(robot))
+    }
+    /// Load the obstacles skipping the geometries which can not be loaded with the error log
+    ///
+    /// base_dir: mesh files are loaded from this dir if the path does not start with "package://"
+    ///
+    /// The default implementation ignores `base_dir` and calls `from_urdf_robot`.
+    fn from_urdf_robot_with_base_dir(robot: &urdf_rs::Robot, _base_dir: Option<&Path>) -> Self
+    where
+        Self: ::std::marker::Sized,
+    {
+        Self::from_urdf_robot(robot)
+    }
     fn from_urdf_file<P>(path: P) -> ::std::result::Result<Self, urdf_rs::UrdfError>
     where
         Self: ::std::marker::Sized,
         P: AsRef<Path>,
     {
-        Ok(Self::from_urdf_robot(&urdf_rs::read_file(path)?))
+        let path = path.as_ref();
+        let robot = urdf_rs::read_file(path)?;
+        Ok(Self::from_urdf_robot_with_base_dir(&robot, path.parent()))
+    }
+    /// Load the obstacles from the file, `base_dir` is the dir of the file if it is `None`
+    fn from_urdf_file_with_options<P>(path: P, options: &CollisionModelOptions) -> Result<Self>
+    where
+        Self: ::std::marker::Sized,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let robot = urdf_rs::read_file(path)?;
+        if options.base_dir.is_none() {
+            if let Some(base_dir) = path.parent() {
+                let options = CollisionModelOptions {
+                    base_dir: Some(base_dir.to_owned()),
+                    ..options.clone()
+                };
+                return Self::from_urdf_robot_with_options(&robot, &options);
+            }
+        }
+        Self::from_urdf_robot_with_options(&robot, options)
+    }
+}
+
+/// Obstacles which are loaded from URDF
+///
+/// Each `<link>` becomes an object whose id is the link name, so the collision reports can
+/// name the obstacle. The `<collision>` elements of the link are the shapes of the object.
+pub struct UrdfObstacles<T>
+where
+    T: Real,
+{
+    objects: Vec<CollisionObject<T>>,
+    /// `Compound` can not have the meshes
+    shapes: DynamicCompound<T>,
+    /// Index of the owner object for each shape in `shapes`
+    shape_owners: Vec<usize>,
+}
+
+impl<T> UrdfObstacles<T>
+where
+    T: Real,
+{
+    /// Objects whose ids are the link names
+    pub fn objects(&self) -> &[CollisionObject<T>] {
+        &self.objects
+    }
+    /// Object which has the shape of the index in `shapes()`
+    pub fn object_of(&self, obstacle_index: usize) -> Option<&CollisionObject<T>> {
+        self.shape_owners
+            .get(obstacle_index)
+            .map(|i| &self.objects[*i])
+    }
+    /// Returns the `Compound` of all the shapes
+    ///
+    /// It panics if there are meshes, because `Compound` can not have the composite shapes.
+    pub fn into_compound(self) -> Compound<T> {
+        Compound::new(self.shapes.shapes().to_vec())
+    }
+    /// Returns the named objects
+    pub fn into_objects(self) -> Vec<CollisionObject<T>> {
+        self.objects
+    }
+}
+
+impl<T> CollisionObjects<T> for UrdfObstacles<T>
+where
+    T: Real,
+{
+    fn shapes(&self) -> &[(na::Isometry3<T>, ShapeHandle<T>)] {
+        self.shapes.shapes()
+    }
+    fn bounding_volumes(&self) -> &[AABB<T>] {
+        self.shapes.bounding_volumes()
+    }
+    fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>) {
+        self.shapes.interferences_with_aabb(aabb, indices)
+    }
+    fn obstacle_id(&self, obstacle_index: usize) -> Option<&str> {
+        self.object_of(obstacle_index).map(|o| o.id.as_str())
+    }
+}
+
+impl<T> UrdfObstacles<T>
+where
+    T: Real,
+{
+    /// `link_shapes` are the shapes of the links in the order of `urdf_obstacle.links`
+    fn from_link_shapes(
+        urdf_obstacle: &urdf_rs::Robot,
+        link_shapes: Vec<Vec<(ShapeHandle<T>, na::Isometry3<T>)>>,
+    ) -> Self {
+        let mut objects = Vec::new();
+        let mut all_shapes = DynamicCompound::new();
+        let mut shape_owners = Vec::new();
+        for (l, link_shapes) in urdf_obstacle.links.iter().zip(link_shapes) {
+            let shapes = link_shapes
+                .into_iter()
+                .map(|(col, pose)| (pose, col))
+                .collect::<Vec<_>>();
+            if !shapes.is_empty() {
+                for &(ref pose, ref shape) in &shapes {
+                    all_shapes.push(*pose, shape.clone());
+                }
+                shape_owners.extend(shapes.iter().map(|_| objects.len()));
+                objects.push(CollisionObject::with_shapes(
+                    &l.name,
+                    shapes,
+                    na::Isometry3::identity(),
+                ));
+            }
+        }
+        UrdfObstacles {
+            objects,
+            shapes: all_shapes,
+            shape_owners,
+        }
+    }
+}
+
+impl<T> FromUrdf for UrdfObstacles<T>
+where
+    T: Real,
+{
+    fn from_urdf_robot(urdf_obstacle: &urdf_rs::Robot) -> Self {
+        Self::from_urdf_robot_with_base_dir(urdf_obstacle, None)
+    }
+    fn from_urdf_robot_with_options(
+        urdf_obstacle: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+    ) -> Result<Self> {
+        let link_shapes =
+            try_urdf_robot_to_shape_handles(urdf_obstacle, options, &MeshCache::new())?;
+        Ok(Self::from_link_shapes(urdf_obstacle, link_shapes))
+    }
+    fn from_urdf_robot_with_base_dir(
+        urdf_obstacle: &urdf_rs::Robot,
+        base_dir: Option<&Path>,
+    ) -> Self {
+        let options = CollisionModelOptions {
+            base_dir: base_dir.map(Path::to_path_buf),
+            ..Default::default()
+        };
+        let link_shapes = urdf_robot_to_shape_handles(urdf_obstacle, &options, &MeshCache::new());
+        Self::from_link_shapes(urdf_obstacle, link_shapes)
     }
 }
 
@@ -278,24 +1745,254 @@ pub trait FromUrdf {
 ///
 /// The `<link>` elements are used as obstacles. set the origin/geometry of
 /// `<visual>` and `<collision>`. You can skip `<inertia>`.
-impl FromUrdf for Compound<f64> {
+/// Use `UrdfObstacles` to keep the link names.
+impl<T> FromUrdf for Compound<T>
+where
+    T: Real,
+{
+    fn from_urdf_robot(urdf_obstacle: &urdf_rs::Robot) -> Self {
+        Self::from_urdf_robot_with_base_dir(urdf_obstacle, None)
+    }
+    fn from_urdf_robot_with_options(
+        urdf_obstacle: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+    ) -> Result<Self> {
+        Ok(UrdfObstacles::from_urdf_robot_with_options(urdf_obstacle, options)?.into_compound())
+    }
+    fn from_urdf_robot_with_base_dir(
+        urdf_obstacle: &urdf_rs::Robot,
+        base_dir: Option<&Path>,
+    ) -> Self {
+        UrdfObstacles::from_urdf_robot_with_base_dir(urdf_obstacle, base_dir).into_compound()
+    }
+}
+
+/// Create `PlanningScene` from URDF file
+///
+/// Each `<link>` becomes an object whose id is the link name. The `<collision>` elements
+/// of the link are the shapes of the object.
+impl<T> FromUrdf for PlanningScene<T>
+where
+    T: Real,
+{
     fn from_urdf_robot(urdf_obstacle: &urdf_rs::Robot) -> Self {
-        let compound_data = urdf_obstacle
-            .links
+        Self::from_urdf_robot_with_base_dir(urdf_obstacle, None)
+    }
+    fn from_urdf_robot_with_options(
+        urdf_obstacle: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+    ) -> Result<Self> {
+        Ok(PlanningScene::from_objects(
+            UrdfObstacles::from_urdf_robot_with_options(urdf_obstacle, options)?.into_objects(),
+        ))
+    }
+    fn from_urdf_robot_with_base_dir(
+        urdf_obstacle: &urdf_rs::Robot,
+        base_dir: Option<&Path>,
+    ) -> Self {
+        PlanningScene::from_objects(
+            UrdfObstacles::from_urdf_robot_with_base_dir(urdf_obstacle, base_dir).into_objects(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::Isometry3;
+    use ncollide3d::shape::Cuboid;
+    use resolver::MeshResource;
+
+    #[test]
+    fn cylinder_and_capsule() {
+        let (urdf_robot, capsules) = read_urdf_with_capsules(
+            r#"
+<robot name="shapes">
+  <link name="base" />
+  <link name="cylinder_link">
+    <collision>
+      <geometry>
+        <cylinder radius="0.1" length="1.0" />
+      </geometry>
+    </collision>
+  </link>
+  <link name="capsule_link">
+    <collision>
+      <origin xyz="2.0 0.0 0.0" />
+      <geometry>
+        <capsule radius="0.1" length="1.0"></capsule >
+      </geometry>
+    </collision>
+  </link>
+  <joint name="cylinder_joint" type="fixed">
+    <parent link="base" />
+    <child link="cylinder_link" />
+  </joint>
+  <joint name="capsule_joint" type="fixed">
+    <parent link="base" />
+    <child link="capsule_link" />
+  </joint>
+</robot>"#,
+        ).unwrap();
+        assert_eq!(capsules.len(), 1);
+        assert!(capsules.is_capsule("capsule_link", 0));
+        let checker = CollisionChecker::from_urdf_robot_with_capsules(
+            &urdf_robot,
+            &capsules,
+            None,
+            0.0,
+        );
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let ball = Ball::new(0.05);
+        let colliding_names = |x: f64, y: f64, z: f64| {
+            checker.colliding_link_names(
+                &robot,
+                &ball,
+                &Isometry3::new(Vector3::new(x, y, z), na::zero()),
+            )
+        };
+        // the axis is Z
+        assert_eq!(colliding_names(0.0, 0.0, 0.52), vec!["cylinder_joint"]);
+        assert!(colliding_names(0.0, 0.3, 0.0).is_empty());
+        // the obstacle inside the cylinder
+        assert_eq!(colliding_names(0.0, 0.0, 0.0), vec!["cylinder_joint"]);
+        // the hemisphere at the end of the capsule
+        assert_eq!(colliding_names(2.0, 0.0, 0.62), vec!["capsule_joint"]);
+        assert!(colliding_names(2.0, 0.0, 0.7).is_empty());
+    }
+    #[test]
+    fn resolver_and_strict_mode() {
+        let stl = b"solid t
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+endsolid t
+";
+        let test_dir = ::std::env::temp_dir().join(format!(
+            "gear_resolver_test_{}",
+            ::std::process::id()
+        ));
+        let package_dir = test_dir.join("my_pkg/meshes");
+        ::std::fs::create_dir_all(&package_dir).unwrap();
+        ::std::fs::write(package_dir.join("triangle.stl"), &stl[..]).unwrap();
+        let urdf = urdf_rs::read_from_string(
+            r#"<robot name="obstacles">
+  <link name="package">
+    <collision><geometry><mesh filename="package://my_pkg/meshes/triangle.stl"/></geometry></collision>
+  </link>
+  <link name="memory">
+    <collision><geometry><mesh filename="memory://triangle.stl"/></geometry></collision>
+  </link>
+  <link name="custom">
+    <collision><geometry><mesh filename="custom://triangle"/></geometry></collision>
+  </link>
+  <link name="missing">
+    <collision><geometry><mesh filename="package://other_pkg/missing.stl"/></geometry></collision>
+  </link>
+</robot>"#,
+        ).unwrap();
+        let resolver = Resolver::new()
+            .ros_package_path(false)
+            .search_path(&test_dir)
+            .mesh_bytes("memory://triangle.stl", stl.to_vec())
+            .scheme("custom", move |_| {
+                Some(MeshResource::Bytes {
+                    name: "custom.stl".to_owned(),
+                    data: Arc::new(stl.to_vec()),
+                })
+            });
+        let mut options = CollisionModelOptions {
+            resolver: Some(Arc::new(resolver)),
+            ..Default::default()
+        };
+        let scene = PlanningScene::<f64>::from_urdf_robot_with_options(&urdf, &options).unwrap();
+        assert_eq!(scene.objects().len(), 3);
+        assert!(scene.object("missing").is_none());
+
+        options.strict = true;
+        assert!(PlanningScene::<f64>::from_urdf_robot_with_options(&urdf, &options).is_err());
+        let checker = CollisionChecker::<f64>::from_urdf_robot_with_options(&urdf, &options, 0.0);
+        assert!(checker.is_err());
+        let _ = ::std::fs::remove_dir_all(&test_dir);
+    }
+    #[test]
+    fn urdf_obstacles() {
+        let obstacles = UrdfObstacles::<f32>::from_urdf_file("obstacles.urdf").unwrap();
+        let ids = obstacles
+            .objects()
             .iter()
-            .flat_map(|l| {
-                l.collision
-                    .iter()
-                    .map(|collision| {
-                        match urdf_geometry_to_shape_handle(&collision.geometry, None) {
-                            Some(col) => Some((k::urdf::isometry_from(&collision.origin), col)),
-                            None => None,
-                        }
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .filter_map(|col_tuple| col_tuple)
+            .map(|o| o.id.clone())
             .collect::<Vec<_>>();
-        Compound::new(compound_data)
+        assert_eq!(ids, vec!["obj1".to_owned(), "obj2".to_owned()]);
+        let num_shapes = obstacles.shapes().len();
+        assert_eq!(num_shapes, 2);
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(obstacles.obstacle_id(i), Some(id.as_str()));
+        }
+        let compound = Compound::<f32>::from_urdf_file("obstacles.urdf").unwrap();
+        assert_eq!(compound.shapes().len(), num_shapes);
+        assert!(obstacles.obstacle_id(num_shapes).is_none());
+    }
+    #[test]
+    fn attached_object() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let mut checker = CollisionChecker::<f64>::from_urdf_robot(&urdf_robot, 0.0);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        robot.update_transforms();
+        let wrist = robot
+            .find("l_wrist_pitch")
+            .unwrap()
+            .world_transform()
+            .unwrap();
+        // the poses of the attached objects are relative to the link
+        let on_wrist = |id: &str, half_extents: Vector3<f64>, x: f64| {
+            CollisionObject::new(
+                id,
+                ShapeHandle::new(Cuboid::new(half_extents)),
+                wrist.inverse() * Isometry3::new(Vector3::new(x, 0.4, 0.5), na::zero()),
+            )
+        };
+        let mut scene = PlanningScene::new();
+        scene.add_object(CollisionObject::new(
+            "wall",
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.05, 0.05, 0.05))),
+            Isometry3::new(Vector3::new(1.25, 0.4, 0.5), na::zero()),
+        ));
+        assert!(checker
+            .colliding_link_names_with_objects(&robot, &scene)
+            .is_empty());
+        checker.attach_object(
+            on_wrist("part", Vector3::new(0.02, 0.02, 0.02), 1.2),
+            "l_wrist_pitch",
+            Vec::new(),
+        );
+        assert_eq!(
+            checker.colliding_link_names_with_objects(&robot, &scene),
+            vec!["part"]
+        );
+
+        // the object between the fingers collides with them unless they are touch links
+        let grasped = on_wrist("grasped", Vector3::new(0.03, 0.04, 0.02), 0.85);
+        checker.attach_object(grasped.clone(), "l_wrist_pitch", Vec::new());
+        assert_eq!(checker.self_colliding_link_pairs(&robot).len(), 2);
+        // the object which has the same id is replaced
+        checker.attach_object(
+            grasped,
+            "l_wrist_pitch",
+            vec!["l_gripper_linear1".to_owned(), "l_gripper_linear2".to_owned()],
+        );
+        assert_eq!(checker.attached_objects().len(), 2);
+        assert!(!checker.has_self_colliding(&robot));
+
+        assert!(checker.detach_object("part").is_some());
+        assert!(checker.attached_object("part").is_none());
+        assert!(checker
+            .colliding_link_names_with_objects(&robot, &scene)
+            .is_empty());
+        assert!(checker.detach_object("part").is_none());
     }
 }
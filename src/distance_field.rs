@@ -0,0 +1,443 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Point3, Real, Vector3};
+use ncollide3d::bounding_volume::AABB;
+use num_traits::Float;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use errors::*;
+use planning_scene::CollisionObjects;
+
+const HEADER: &str = "gear signed distance field 1";
+/// Fields which have more grid points are rejected to avoid allocating too much memory
+const MAX_NUM_VALUES: usize = 1 << 28;
+
+/// Number of the grid points, `None` if it is zero or more than `MAX_NUM_VALUES`
+fn num_grid_points(size: &[usize; 3]) -> Option<usize> {
+    let num = size[0].checked_mul(size[1])?.checked_mul(size[2])?;
+    if num == 0 || num > MAX_NUM_VALUES {
+        None
+    } else {
+        Some(num)
+    }
+}
+
+/// Voxelized signed distance field of the obstacles
+///
+/// The distances are sampled at the grid points `origin + resolution * (i, j, k)`, and the
+/// values between them are interpolated (trilinear). The distance is negative inside the
+/// obstacles. It can be saved to a file to reuse it for the static environment.
+#[derive(Debug, Clone)]
+pub struct SignedDistanceField<T>
+where
+    T: Real,
+{
+    origin: Point3<T>,
+    resolution: T,
+    size: [usize; 3],
+    values: Vec<T>,
+}
+
+/// Distance between a link and the obstacles in `SignedDistanceField`
+#[derive(Debug, Clone)]
+pub struct FieldDistance<T>
+where
+    T: Real,
+{
+    /// Name of the link (same as `colliding_link_names`) or the id of the attached object
+    pub link_name: String,
+    /// Signed distance of the closest sphere of the link, the padding is subtracted
+    pub distance: T,
+    /// Gradient of the field at the center of the closest sphere
+    pub gradient: Vector3<T>,
+    /// Center of the closest sphere in the world frame
+    pub point: Point3<T>,
+}
+
+/// Signed distance from the point to the closest obstacle
+fn signed_distance_to_objects<T, O>(objects: &O, point: &Point3<T>) -> Option<T>
+where
+    T: Real,
+    O: CollisionObjects<T>,
+{
+    let mut closest: Option<T> = None;
+    for (&(ref pose, ref shape), aabb) in objects
+        .shapes()
+        .iter()
+        .zip(objects.bounding_volumes().iter())
+    {
+        if let Some(closest) = closest {
+            // the distance to the AABB is smaller than the one to the shape
+            let mut nearest = *point;
+            for i in 0..3 {
+                nearest[i] = na::sup(&aabb.mins()[i], &na::inf(&aabb.maxs()[i], &point[i]));
+            }
+            if na::distance(&nearest, point) >= closest {
+                continue;
+            }
+        }
+        let query = match shape.as_point_query() {
+            Some(query) => query,
+            None => continue,
+        };
+        let projection = query.project_point(pose, point, false);
+        let distance = na::distance(&projection.point, point);
+        let distance = if projection.is_inside {
+            -distance
+        } else {
+            distance
+        };
+        closest = Some(match closest {
+            Some(closest) => na::inf(&closest, &distance),
+            None => distance,
+        });
+    }
+    closest
+}
+
+impl<T> SignedDistanceField<T>
+where
+    T: Real + Float,
+{
+    /// Compute the field of `objects` (`Compound`, `PlanningScene` etc.) in `aabb`
+    ///
+    /// The distance is `T::max_value()` if `objects` is empty. Returns the error if
+    /// `resolution` is not positive or the field is too large.
+    pub fn from_objects<O>(objects: &O, aabb: &AABB<T>, resolution: T) -> Result<Self>
+    where
+        O: CollisionObjects<T>,
+    {
+        if Float::is_nan(resolution) || resolution <= na::zero() {
+            return Err(Error::from(format!(
+                "invalid resolution of distance field: {:?}",
+                resolution
+            )));
+        }
+        let extents = aabb.maxs() - aabb.mins();
+        let mut size = [0; 3];
+        // ignore the rounding error, the grid covers `aabb` if it is divisible by `resolution`
+        let tolerance: T = na::convert(1e-6);
+        for i in 0..3 {
+            let cells = Float::ceil(extents[i] / resolution - tolerance);
+            size[i] = cells
+                .to_usize()
+                .and_then(|cells| cells.checked_add(1))
+                .unwrap_or(usize::max_value());
+        }
+        let num_values = num_grid_points(&size)
+            .ok_or_else(|| Error::from(format!("distance field is too large: {:?}", size)))?;
+        let mut field = SignedDistanceField {
+            origin: *aabb.mins(),
+            resolution,
+            size,
+            values: Vec::with_capacity(num_values),
+        };
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let point = field.grid_point(x, y, z);
+                    field.values.push(
+                        signed_distance_to_objects(objects, &point)
+                            .unwrap_or_else(<T as Float>::max_value),
+                    );
+                }
+            }
+        }
+        Ok(field)
+    }
+    /// Position of the first grid point
+    pub fn origin(&self) -> &Point3<T> {
+        &self.origin
+    }
+    /// Distance between the grid points
+    pub fn resolution(&self) -> T {
+        self.resolution
+    }
+    /// Number of the grid points for each axis
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+    /// Region of the field
+    pub fn aabb(&self) -> AABB<T> {
+        let max = self.grid_point(self.size[0] - 1, self.size[1] - 1, self.size[2] - 1);
+        AABB::new(self.origin, max)
+    }
+    fn grid_point(&self, x: usize, y: usize, z: usize) -> Point3<T> {
+        self.origin
+            + Vector3::new(
+                na::convert::<f64, T>(x as f64),
+                na::convert(y as f64),
+                na::convert(z as f64),
+            ) * self.resolution
+    }
+    fn value(&self, x: usize, y: usize, z: usize) -> T {
+        self.values[x + self.size[0] * (y + self.size[1] * z)]
+    }
+    /// Signed distance at the point, `None` if it is out of the field
+    pub fn distance(&self, point: &Point3<T>) -> Option<T> {
+        self.distance_and_gradient(point).map(|(d, _)| d)
+    }
+    /// Gradient of the signed distance at the point, `None` if it is out of the field
+    ///
+    /// It points the direction to go away from the obstacles.
+    pub fn gradient(&self, point: &Point3<T>) -> Option<Vector3<T>> {
+        self.distance_and_gradient(point).map(|(_, g)| g)
+    }
+    /// Signed distance and its gradient at the point, `None` if it is out of the field
+    pub fn distance_and_gradient(&self, point: &Point3<T>) -> Option<(T, Vector3<T>)> {
+        let mut cell = [0; 3];
+        let mut t = Vector3::zeros();
+        for i in 0..3 {
+            let f = (point[i] - self.origin[i]) / self.resolution;
+            let last = self.size[i] - 1;
+            if f < na::zero() || f > na::convert(last as f64) {
+                return None;
+            }
+            // the last grid point uses the previous cell
+            let index = Float::floor(f).to_usize()?.min(last.saturating_sub(1));
+            cell[i] = index;
+            t[i] = f - na::convert(index as f64);
+        }
+        let one: T = na::one();
+        let mut distance: T = na::zero();
+        let mut gradient = Vector3::zeros();
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let mut index = [0; 3];
+            let mut weights = Vector3::zeros();
+            let mut slopes = Vector3::zeros();
+            for i in 0..3 {
+                index[i] = (cell[i] + offset[i]).min(self.size[i] - 1);
+                if offset[i] == 1 {
+                    weights[i] = t[i];
+                    slopes[i] = one;
+                } else {
+                    weights[i] = one - t[i];
+                    slopes[i] = -one;
+                }
+            }
+            let value = self.value(index[0], index[1], index[2]);
+            distance += value * weights[0] * weights[1] * weights[2];
+            gradient[0] += value * slopes[0] * weights[1] * weights[2];
+            gradient[1] += value * weights[0] * slopes[1] * weights[2];
+            gradient[2] += value * weights[0] * weights[1] * slopes[2];
+        }
+        Some((distance, gradient / self.resolution))
+    }
+    /// Write the field as text
+    pub fn write<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        let to_f64 = |v: T| v.to_f64().unwrap_or(::std::f64::MAX);
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(
+            writer,
+            "origin {} {} {}",
+            to_f64(self.origin[0]),
+            to_f64(self.origin[1]),
+            to_f64(self.origin[2])
+        )?;
+        writeln!(writer, "resolution {}", to_f64(self.resolution))?;
+        writeln!(
+            writer,
+            "size {} {} {}",
+            self.size[0], self.size[1], self.size[2]
+        )?;
+        for row in self.values.chunks(self.size[0].max(1)) {
+            let line = row
+                .iter()
+                .map(|v| to_f64(*v).to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+    /// Read the field which is written by `write`
+    pub fn read<R>(reader: R) -> Result<Self>
+    where
+        R: BufRead,
+    {
+        let invalid = |message: &str| Error::from(format!("invalid distance field: {}", message));
+        let mut lines = reader.lines();
+        let mut next_line = || -> Result<String> {
+            match lines.next() {
+                Some(line) => Ok(line?),
+                None => Err(invalid("unexpected end")),
+            }
+        };
+        if next_line()? != HEADER {
+            return Err(invalid("unknown header"));
+        }
+        let parse_values = |line: &str, name: &str| -> Result<Vec<f64>> {
+            let mut words = line.split_whitespace();
+            if words.next() != Some(name) {
+                return Err(invalid(name));
+            }
+            words
+                .map(|w| w.parse::<f64>().map_err(|_| invalid(name)))
+                .collect()
+        };
+        let origin = parse_values(&next_line()?, "origin")?;
+        let resolution = parse_values(&next_line()?, "resolution")?;
+        let size = {
+            let line = next_line()?;
+            let mut words = line.split_whitespace();
+            if words.next() != Some("size") {
+                return Err(invalid("size"));
+            }
+            words
+                .map(|w| w.parse::<usize>().map_err(|_| invalid("size")))
+                .collect::<Result<Vec<_>>>()?
+        };
+        if origin.len() != 3
+            || resolution.len() != 1
+            || size.len() != 3
+            || !(resolution[0] > 0.0 && resolution[0].is_finite())
+        {
+            return Err(invalid("header"));
+        }
+        let size = [size[0], size[1], size[2]];
+        let num_values = num_grid_points(&size).ok_or_else(|| invalid("size"))?;
+        let mut values = Vec::with_capacity(num_values);
+        while values.len() < num_values {
+            for word in next_line()?.split_whitespace() {
+                let value = word.parse::<f64>().map_err(|_| invalid("value"))?;
+                values.push(na::convert(value));
+            }
+        }
+        if values.len() != num_values {
+            return Err(invalid("number of the values"));
+        }
+        Ok(SignedDistanceField {
+            origin: Point3::new(
+                na::convert(origin[0]),
+                na::convert(origin[1]),
+                na::convert(origin[2]),
+            ),
+            resolution: na::convert(resolution[0]),
+            size,
+            values,
+        })
+    }
+    /// Save the field to the file
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.write(&mut BufWriter::new(File::create(path)?))
+    }
+    /// Load the field from the file which is saved by `save`
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collision_checker::CollisionChecker;
+    use k;
+    use na::Isometry3;
+    use ncollide3d::shape::{Compound, Cuboid, ShapeHandle};
+    use urdf_rs;
+
+    #[test]
+    fn signed_distance_field() {
+        let objects = Compound::new(vec![(
+            Isometry3::new(Vector3::new(0.5, 0.0, 0.0), na::zero()),
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.1, 0.2, 0.2))),
+        )]);
+        let aabb = AABB::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(1.0, 0.5, 0.5));
+        let field = SignedDistanceField::from_objects(&objects, &aabb, 0.05).unwrap();
+        assert_eq!(field.size(), [31, 21, 21]);
+        assert!(SignedDistanceField::from_objects(&objects, &aabb, 0.0).is_err());
+        assert!(SignedDistanceField::from_objects(&objects, &aabb, -0.05).is_err());
+
+        // the face of the box is at x = 0.4
+        let (distance, gradient): (f64, _) = field
+            .distance_and_gradient(&Point3::new(0.12, 0.01, 0.02))
+            .unwrap();
+        assert!((distance - 0.28).abs() < 1e-6);
+        assert!((gradient - Vector3::new(-1.0, 0.0, 0.0)).norm() < 1e-6);
+        assert!(field.distance(&Point3::new(0.5, 0.0, 0.0)).unwrap() < -0.05);
+        assert!(field.distance(&Point3::new(2.0, 0.0, 0.0)).is_none());
+        assert!(field.distance(&Point3::new(0.99, 0.49, 0.49)).is_some());
+
+        let mut bytes = Vec::new();
+        field.write(&mut bytes).unwrap();
+        let loaded = SignedDistanceField::<f64>::read(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.size(), field.size());
+        let point = Point3::new(0.23, -0.11, 0.07);
+        assert!((loaded.distance(&point).unwrap() - field.distance(&point).unwrap()).abs() < 1e-9);
+        assert!(SignedDistanceField::<f64>::read(&bytes[..bytes.len() / 2]).is_err());
+        let huge = format!(
+            "{}\norigin 0 0 0\nresolution 0.1\nsize {} {} 2\n0\n",
+            HEADER,
+            usize::max_value(),
+            usize::max_value()
+        );
+        assert!(SignedDistanceField::<f64>::read(huge.as_bytes()).is_err());
+        let negative = format!("{}\norigin 0 0 0\nresolution 0.1\nsize -1 1 1\n0\n", HEADER);
+        assert!(SignedDistanceField::<f64>::read(negative.as_bytes()).is_err());
+    }
+    #[test]
+    fn field_distances() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let mut checker = CollisionChecker::<f64>::from_urdf_robot(&urdf_robot, 0.01);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let aabb = AABB::new(Point3::new(-0.5, -1.0, -0.5), Point3::new(1.5, 1.0, 1.5));
+        let cuboid = Cuboid::new(Vector3::new(0.05, 0.05, 0.05));
+        let create_field = |x: f64| {
+            let objects = Compound::new(vec![(
+                Isometry3::new(Vector3::new(x, 0.4, 0.5), na::zero()),
+                ShapeHandle::new(cuboid.clone()),
+            )]);
+            (
+                SignedDistanceField::from_objects(&objects, &aabb, 0.05).unwrap(),
+                Isometry3::new(Vector3::new(x, 0.4, 0.5), na::zero()),
+            )
+        };
+        // the gripper is in the box
+        let (field, pose) = create_field(0.9);
+        let colliding = checker.colliding_link_names(&robot, &cuboid, &pose);
+        assert!(!colliding.is_empty());
+        let distances = checker.field_distances(&robot, &field);
+        for name in &colliding {
+            let d = distances.iter().find(|d| &d.link_name == name).unwrap();
+            assert!(d.distance < field.resolution(), "{}: {}", name, d.distance);
+        }
+        checker.enable_sphere_trees();
+        let with_trees = checker.field_distances(&robot, &field);
+        assert_eq!(with_trees.len(), distances.len());
+        for (a, b) in with_trees.iter().zip(distances.iter()) {
+            assert_eq!(a.link_name, b.link_name);
+            assert!((a.distance - b.distance).abs() < 1e-9);
+        }
+        // the box is far from the robot
+        let (field, _) = create_field(1.4);
+        assert!(checker
+            .field_distances(&robot, &field)
+            .iter()
+            .all(|d| d.distance > 0.0));
+    }
+}
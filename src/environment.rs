@@ -0,0 +1,256 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use k;
+use na::{Isometry3, Real};
+use ncollide3d::bounding_volume::AABB;
+use ncollide3d::shape::ShapeHandle;
+use std::path::Path;
+use urdf_rs;
+
+use collision_checker::{
+    try_urdf_robot_to_shape_handles, urdf_robot_to_shape_handles, CollisionModelOptions, FromUrdf,
+};
+use errors::*;
+use mesh::MeshCache;
+use planning_scene::{CollisionObjects, DynamicCompound};
+
+struct EnvironmentLink<T>
+where
+    T: Real,
+{
+    name: String,
+    /// The link is fixed to the world if it is `None`
+    joint_name: Option<String>,
+    /// Shapes and their poses in the link frame
+    shapes: Vec<(Isometry3<T>, ShapeHandle<T>)>,
+}
+
+/// Obstacles which have movable joints, like a cabinet with a door
+///
+/// The environment URDF is loaded as its own `k::Chain`. The shapes are placed by the forward
+/// kinematics of the current joint positions, so the environment can be passed to
+/// `JointPathPlanner` as `CollisionObjects`. Each link is reported by its link name.
+pub struct Environment<T>
+where
+    T: Real,
+{
+    chain: k::Chain<T>,
+    links: Vec<EnvironmentLink<T>>,
+    /// Shapes in the world frame, `Compound` can not have the meshes
+    shapes: DynamicCompound<T>,
+    /// Index of the owner link for each shape in `shapes`
+    shape_owners: Vec<usize>,
+}
+
+impl<T> Environment<T>
+where
+    T: Real,
+{
+    /// `link_shapes` are the shapes of the links in the order of `urdf_robot.links`
+    fn from_link_shapes(
+        urdf_robot: &urdf_rs::Robot,
+        link_shapes: Vec<Vec<(ShapeHandle<T>, Isometry3<T>)>>,
+    ) -> Self {
+        let link_joint_map = k::urdf::link_to_joint_map(urdf_robot);
+        let mut links = Vec::new();
+        for (l, shapes) in urdf_robot.links.iter().zip(link_shapes) {
+            if !shapes.is_empty() {
+                links.push(EnvironmentLink {
+                    name: l.name.clone(),
+                    joint_name: link_joint_map.get(&l.name).cloned(),
+                    shapes: shapes
+                        .into_iter()
+                        .map(|(shape, pose)| (pose, shape))
+                        .collect(),
+                });
+            }
+        }
+        let chain = k::Chain::from(urdf_robot);
+        chain.update_transforms();
+        let mut environment = Environment {
+            chain,
+            links,
+            shapes: DynamicCompound::new(),
+            shape_owners: Vec::new(),
+        };
+        for i in 0..environment.links.len() {
+            let pose = environment.pose_of(&environment.links[i]);
+            for &(ref local_pose, ref shape) in &environment.links[i].shapes {
+                environment.shapes.push(pose * local_pose, shape.clone());
+                environment.shape_owners.push(i);
+            }
+        }
+        environment
+    }
+    /// Kinematic chain of the environment
+    pub fn chain(&self) -> &k::Chain<T> {
+        &self.chain
+    }
+    /// Names of the movable joints
+    pub fn joint_names(&self) -> Vec<String> {
+        self.chain.iter_joints().map(|j| j.name.clone()).collect()
+    }
+    /// Positions of the movable joints
+    pub fn joint_positions(&self) -> Vec<T> {
+        self.chain.joint_positions()
+    }
+    /// Set the positions of all the movable joints and update the obstacles
+    pub fn set_joint_positions(&mut self, positions: &[T]) -> Result<()> {
+        self.chain.set_joint_positions(positions)?;
+        self.update_shapes();
+        Ok(())
+    }
+    /// Set the position of the joint and update the obstacles
+    pub fn set_joint_position(&mut self, joint_name: &str, position: T) -> Result<()> {
+        self.chain
+            .find(joint_name)
+            .ok_or_else(|| Error::from(format!("joint {} not found", joint_name)))?
+            .set_joint_position(position)?;
+        self.update_shapes();
+        Ok(())
+    }
+    /// Pose of the link in the world frame
+    pub fn link_pose(&self, link_name: &str) -> Option<Isometry3<T>> {
+        self.links
+            .iter()
+            .find(|l| l.name == link_name)
+            .map(|l| self.pose_of(l))
+    }
+    /// Names of the links which have collision shapes
+    pub fn link_names(&self) -> Vec<String> {
+        self.links.iter().map(|l| l.name.clone()).collect()
+    }
+    fn pose_of(&self, link: &EnvironmentLink<T>) -> Isometry3<T> {
+        link.joint_name
+            .as_ref()
+            .and_then(|name| self.chain.find(name))
+            .and_then(|node| node.world_transform())
+            .unwrap_or_else(Isometry3::identity)
+    }
+    /// Move the shapes to the current poses of the links
+    fn update_shapes(&mut self) {
+        self.chain.update_transforms();
+        let mut index = 0;
+        for link in &self.links {
+            let pose = self.pose_of(link);
+            for &(ref local_pose, _) in &link.shapes {
+                self.shapes.set_pose(index, pose * local_pose);
+                index += 1;
+            }
+        }
+    }
+}
+
+impl<T> CollisionObjects<T> for Environment<T>
+where
+    T: Real,
+{
+    fn shapes(&self) -> &[(Isometry3<T>, ShapeHandle<T>)] {
+        self.shapes.shapes()
+    }
+    fn bounding_volumes(&self) -> &[AABB<T>] {
+        self.shapes.bounding_volumes()
+    }
+    fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>) {
+        self.shapes.interferences_with_aabb(aabb, indices)
+    }
+    fn obstacle_id(&self, index: usize) -> Option<&str> {
+        self.shape_owners
+            .get(index)
+            .map(|i| self.links[*i].name.as_str())
+    }
+}
+
+/// Create `Environment` from URDF file
+///
+/// The joints of the URDF are the joints of the environment, and all the positions are zero
+/// at first.
+impl<T> FromUrdf for Environment<T>
+where
+    T: Real,
+{
+    fn from_urdf_robot(urdf_robot: &urdf_rs::Robot) -> Self {
+        Self::from_urdf_robot_with_base_dir(urdf_robot, None)
+    }
+    fn from_urdf_robot_with_options(
+        urdf_robot: &urdf_rs::Robot,
+        options: &CollisionModelOptions,
+    ) -> Result<Self> {
+        let link_shapes = try_urdf_robot_to_shape_handles(urdf_robot, options, &MeshCache::new())?;
+        Ok(Self::from_link_shapes(urdf_robot, link_shapes))
+    }
+    fn from_urdf_robot_with_base_dir(
+        urdf_robot: &urdf_rs::Robot,
+        base_dir: Option<&Path>,
+    ) -> Self {
+        let options = CollisionModelOptions {
+            base_dir: base_dir.map(Path::to_path_buf),
+            ..Default::default()
+        };
+        let link_shapes = urdf_robot_to_shape_handles(urdf_robot, &options, &MeshCache::new());
+        Self::from_link_shapes(urdf_robot, link_shapes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collision_checker::CollisionChecker;
+
+    #[test]
+    fn articulated_environment() {
+        let urdf = urdf_rs::read_from_string(
+            r#"<robot name="cabinet">
+  <link name="cabinet"/>
+  <link name="door">
+    <collision>
+      <origin xyz="0 0.4 0"/>
+      <geometry><box size="0.02 0.8 0.1"/></geometry>
+    </collision>
+  </link>
+  <joint name="door_hinge" type="revolute">
+    <origin xyz="0.9 0 0.5"/>
+    <parent link="cabinet"/>
+    <child link="door"/>
+    <axis xyz="0 0 1"/>
+    <limit lower="-1.6" upper="1.6" effort="1" velocity="1"/>
+  </joint>
+</robot>"#,
+        ).unwrap();
+        let mut environment = Environment::<f64>::from_urdf_robot(&urdf);
+        assert_eq!(environment.joint_names(), vec!["door_hinge".to_owned()]);
+        assert_eq!(environment.link_names(), vec!["door".to_owned()]);
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let checker = CollisionChecker::<f64>::from_urdf_robot(&urdf_robot, 0.0);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        // the closed door is at the gripper
+        let contacts = checker.contacts(&robot, &environment);
+        assert!(!contacts.is_empty());
+        assert!(contacts
+            .iter()
+            .all(|c| c.obstacle_id.as_deref() == Some("door")));
+
+        environment
+            .set_joint_position("door_hinge", -60.0f64.to_radians())
+            .unwrap();
+        assert!(!checker.has_any_colliding_with_objects(&robot, &environment));
+        let door = environment.link_pose("door").unwrap();
+        assert!((door.rotation.angle() - 60.0f64.to_radians()).abs() < 1e-6);
+        assert!(environment.set_joint_position("not_found", 0.0).is_err());
+        assert!(environment.set_joint_positions(&[0.0]).is_ok());
+        assert!(checker.has_any_colliding_with_objects(&robot, &environment));
+    }
+}
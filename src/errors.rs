@@ -17,6 +17,8 @@ use k;
 use std::io;
 use urdf_rs;
 
+use collision_checker::ContactReport;
+
 #[derive(Debug)]
 pub enum CollisionPart {
     Start,
@@ -28,12 +30,31 @@ pub enum CollisionPart {
 pub enum Error {
     #[fail(display = "{:?}", error)]
     Other { error: String },
-    #[fail(display = "Collision error: {:?} is colliding", part)]
-    Collision { part: CollisionPart },
+    #[fail(
+        display = "Collision error: {:?} is colliding, contacts: {:?}, self colliding pairs: {:?}",
+        part,
+        contacts,
+        self_colliding_pairs
+    )]
+    Collision {
+        part: CollisionPart,
+        /// Contacts with the obstacles
+        contacts: Vec<ContactReport<f64>>,
+        /// Pairs of the names of the links which are colliding with each other
+        self_colliding_pairs: Vec<(String, String)>,
+    },
     #[fail(display = "IO error {:?}", error)]
     Io { error: io::Error },
     #[fail(display = "URDF error: {:?}", error)]
     Urdf { error: urdf_rs::UrdfError },
+    #[fail(display = "SRDF error: {}", error)]
+    Srdf { error: String },
+    #[fail(display = "Point cloud error: {}", error)]
+    PointCloud { error: String },
+    #[fail(display = "Mesh error: {}", error)]
+    Mesh { error: String },
+    #[fail(display = "Timeout error: {}", error)]
+    Timeout { error: String },
     #[fail(display = "IK error: {:?}", error)]
     Ik { error: k::IKError },
     #[fail(display = "Joint error: {:?}", error)]
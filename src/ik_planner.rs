@@ -15,12 +15,12 @@ limitations under the License.
 */
 use k;
 use na;
-use ncollide3d::shape::Compound;
 use num_traits;
 use urdf_rs;
 
 use errors::*;
 use path_planner::JointPathPlanner;
+use planning_scene::CollisionObjects;
 
 /// Joint path planner which supports inverse kinematics
 pub struct JointPathPlannerWithIK<T, I>
@@ -72,15 +72,30 @@ where
     ) -> Result<()> {
         Ok(self.ik_solver.solve(arm, target_pose)?)
     }
-    pub fn colliding_link_names(&self, objects: &Compound<T>) -> Vec<String> {
+    pub fn colliding_link_names<O>(&self, objects: &O) -> Vec<String>
+    where
+        O: CollisionObjects<T>,
+    {
         self.path_planner.colliding_link_names(objects)
     }
-    pub fn plan_with_ik(
+    pub fn colliding_object_ids<O>(&self, objects: &O) -> Vec<String>
+    where
+        O: CollisionObjects<T>,
+    {
+        self.path_planner.colliding_object_ids(objects)
+    }
+    pub fn self_colliding_link_pairs(&self) -> Vec<(String, String)> {
+        self.path_planner.self_colliding_link_pairs()
+    }
+    pub fn plan_with_ik<O>(
         &mut self,
         target_name: &str,
         target_pose: &na::Isometry3<T>,
-        objects: &Compound<T>,
-    ) -> Result<Vec<Vec<T>>> {
+        objects: &O,
+    ) -> Result<Vec<Vec<T>>>
+    where
+        O: CollisionObjects<T>,
+    {
         self.plan_with_ik_with_constraints(
             target_name,
             target_pose,
@@ -88,13 +103,16 @@ where
             &k::Constraints::default(),
         )
     }
-    pub fn plan_with_ik_with_constraints(
+    pub fn plan_with_ik_with_constraints<O>(
         &mut self,
         target_name: &str,
         target_pose: &na::Isometry3<T>,
-        objects: &Compound<T>,
+        objects: &O,
         constraints: &k::Constraints,
-    ) -> Result<Vec<Vec<T>>> {
+    ) -> Result<Vec<Vec<T>>>
+    where
+        O: CollisionObjects<T>,
+    {
         let end_link: &k::Node<T> = self
             .path_planner
             .collision_check_robot
@@ -108,13 +126,16 @@ where
         let goal = arm.joint_positions();
         self.path_planner.plan(&arm, &initial, &goal, objects)
     }
-    pub fn plan_joints<K>(
+    pub fn plan_joints<K, O>(
         &mut self,
         use_joints: &k::Chain<T>,
         start_angles: &[T],
         goal_angles: &[T],
-        objects: &Compound<T>,
-    ) -> Result<Vec<Vec<T>>> {
+        objects: &O,
+    ) -> Result<Vec<Vec<T>>>
+    where
+        O: CollisionObjects<T>,
+    {
         self.path_planner
             .plan(use_joints, start_angles, goal_angles, objects)
     }
@@ -18,6 +18,7 @@ limitations under the License.
 //! Get the collision free trajectory of joint angles. `ncollide3d` is used to check the
 //! collision between the robot and the environment.
 //!
+#[cfg(feature = "assimp")]
 extern crate assimp;
 #[macro_use]
 extern crate failure;
@@ -31,24 +32,69 @@ extern crate rand;
 extern crate rrt;
 extern crate trajectory;
 extern crate urdf_rs;
+extern crate xml;
 
 mod errors;
 pub use errors::*;
 
+mod allowed_collision_matrix;
+pub use allowed_collision_matrix::*;
+
+mod resolver;
+pub use resolver::*;
+
+mod mesh;
+pub use mesh::*;
+
+#[cfg(feature = "pure-rust-mesh")]
+mod mesh_loader;
+
+mod bounding;
+pub use bounding::*;
+
+mod sphere_tree;
+pub use sphere_tree::*;
+
 mod collision_checker;
 pub use collision_checker::*;
 
+mod planning_scene;
+pub use planning_scene::*;
+
+mod point_cloud;
+pub use point_cloud::*;
+
+mod environment;
+pub use environment::*;
+
+mod distance_field;
+pub use distance_field::*;
+
 mod funcs;
 pub use funcs::*;
 
 mod ik;
 pub use ik::*;
 
+mod planning_algorithm;
+pub use planning_algorithm::*;
+
+mod rrt_star;
+pub use rrt_star::*;
+
+mod prm;
+pub use prm::*;
+
+mod path_optimizer;
+pub use path_optimizer::*;
+
 mod path_planner;
 pub use path_planner::*;
 
 mod ik_planner;
 pub use ik_planner::*;
 
+pub mod srdf;
+
 // re-export k::IK modules
 pub use k::{InverseKinematicsSolver, JacobianIKSolver};
@@ -0,0 +1,656 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+#[cfg(feature = "assimp")]
+use assimp;
+use na::{self, Isometry3, Point3, Real};
+use ncollide3d::procedural;
+use ncollide3d::procedural::IndexBuffer;
+use ncollide3d::shape::{Compound, ConvexHull, ShapeHandle, TriMesh};
+use ncollide3d::transformation;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use errors::*;
+use resolver::MeshResource;
+#[cfg(feature = "pure-rust-mesh")]
+use mesh_loader;
+
+/// How to convert the mesh geometries into the collision shapes
+#[derive(Debug, Clone, Copy)]
+pub enum MeshMode {
+    /// Use the triangles as they are
+    ///
+    /// Only the intersections with the surface are detected.
+    TriMesh,
+    /// Use the convex hull of the vertices
+    ConvexHull,
+    /// Use the approximate convex decomposition (HACD) of the mesh
+    ///
+    /// `concavity` is the max concavity of each convex part. The result is cached on disk
+    /// next to the mesh file (`<mesh file>.convex`).
+    ConvexDecomposition { concavity: f64 },
+}
+
+impl Default for MeshMode {
+    fn default() -> Self {
+        MeshMode::TriMesh
+    }
+}
+
+// `concavity` is compared by its bits to be used as the key of the mesh cache
+impl PartialEq for MeshMode {
+    fn eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (MeshMode::TriMesh, MeshMode::TriMesh) => true,
+            (MeshMode::ConvexHull, MeshMode::ConvexHull) => true,
+            (
+                MeshMode::ConvexDecomposition { concavity: c1 },
+                MeshMode::ConvexDecomposition { concavity: c2 },
+            ) => c1.to_bits() == c2.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MeshMode {}
+
+impl Hash for MeshMode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        if let MeshMode::ConvexDecomposition { concavity } = *self {
+            concavity.to_bits().hash(state);
+        }
+    }
+}
+
+/// Vertices and triangles of a mesh file
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MeshData {
+    pub vertices: Vec<Point3<f64>>,
+    pub indices: Vec<Point3<usize>>,
+}
+
+pub(crate) fn mesh_error<S>(error: S) -> Error
+where
+    S: Into<String>,
+{
+    Error::Mesh {
+        error: error.into(),
+    }
+}
+
+/// Add the polygon as the triangles (triangle fan), the polygon with less than 3 vertices is
+/// ignored
+pub(crate) fn push_polygon(indices: &mut Vec<Point3<usize>>, polygon: &[usize]) {
+    for i in 2..polygon.len() {
+        indices.push(Point3::new(polygon[0], polygon[i - 1], polygon[i]));
+    }
+}
+
+/// Load the mesh and scale it
+///
+/// With the `pure-rust-mesh` feature, STL, OBJ and DAE files are read without assimp.
+/// The other formats require the `assimp` feature.
+pub(crate) fn load_mesh(resource: &MeshResource, scale: &[f64]) -> Result<MeshData> {
+    let (name, mesh) = match *resource {
+        MeshResource::File(ref path) => (format!("{:?}", path), read_mesh(path)),
+        MeshResource::Bytes { ref name, ref data } => {
+            (name.to_owned(), read_mesh_bytes(name, data))
+        }
+    };
+    let mut mesh = mesh.map_err(|err| match err {
+        Error::Mesh { error } => mesh_error(format!("{}: {}", name, error)),
+        Error::Io { error } => mesh_error(format!("{}: {}", name, error)),
+        err => err,
+    })?;
+    for v in &mut mesh.vertices {
+        v.x *= scale[0];
+        v.y *= scale[1];
+        v.z *= scale[2];
+    }
+    Ok(mesh)
+}
+
+#[cfg(feature = "pure-rust-mesh")]
+fn read_mesh(path: &Path) -> Result<MeshData> {
+    match mesh_loader::read_mesh(path) {
+        Some(mesh) => mesh,
+        None => read_mesh_with_assimp(path),
+    }
+}
+
+#[cfg(not(feature = "pure-rust-mesh"))]
+fn read_mesh(path: &Path) -> Result<MeshData> {
+    read_mesh_with_assimp(path)
+}
+
+#[cfg(feature = "pure-rust-mesh")]
+fn read_mesh_bytes(name: &str, data: &[u8]) -> Result<MeshData> {
+    match mesh_loader::read_mesh_bytes(name, data) {
+        Some(mesh) => mesh,
+        None => read_mesh_bytes_with_assimp(name, data),
+    }
+}
+
+#[cfg(not(feature = "pure-rust-mesh"))]
+fn read_mesh_bytes(name: &str, data: &[u8]) -> Result<MeshData> {
+    read_mesh_bytes_with_assimp(name, data)
+}
+
+/// assimp reads only files, so the data is written to a temporary file
+fn read_mesh_bytes_with_assimp(name: &str, data: &[u8]) -> Result<MeshData> {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    let path = ::std::env::temp_dir().join(format!(
+        "gear-mesh-{}-{}.{}",
+        ::std::process::id(),
+        TEMP_MESH_COUNT.fetch_add(1, Ordering::SeqCst),
+        extension
+    ));
+    File::create(&path)?.write_all(data)?;
+    let mesh = read_mesh_with_assimp(&path);
+    let _ = fs::remove_file(&path);
+    mesh
+}
+
+static TEMP_MESH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "assimp")]
+fn read_mesh_with_assimp(path: &Path) -> Result<MeshData> {
+    let mut importer = assimp::Importer::new();
+    importer.pre_transform_vertices(|x| x.enable = true);
+    importer.collada_ignore_up_direction(true);
+    let file_string = path
+        .to_str()
+        .ok_or_else(|| mesh_error("faild to get string from path"))?;
+    Ok(assimp_scene_to_mesh_data(
+        importer.read_file(file_string).map_err(mesh_error)?,
+    ))
+}
+
+#[cfg(not(feature = "assimp"))]
+fn read_mesh_with_assimp(_path: &Path) -> Result<MeshData> {
+    Err(mesh_error(
+        "unsupported format, enable the `assimp` feature to load it",
+    ))
+}
+
+#[cfg(feature = "assimp")]
+fn assimp_scene_to_mesh_data(scene: assimp::Scene) -> MeshData {
+    let mut mesh_data = MeshData::default();
+    for mesh in scene.mesh_iter() {
+        let last_index = mesh_data.vertices.len();
+        mesh_data.vertices.extend(
+            mesh.vertex_iter()
+                .map(|v| Point3::new(f64::from(v.x), f64::from(v.y), f64::from(v.z))),
+        );
+        for f in mesh.face_iter() {
+            let polygon = (0..f.num_indices as isize)
+                .map(|i| f[i] as usize + last_index)
+                .collect::<Vec<_>>();
+            push_polygon(&mut mesh_data.indices, &polygon);
+        }
+    }
+    mesh_data
+}
+
+fn convert_points<T>(points: &[Point3<f64>]) -> Vec<Point3<T>>
+where
+    T: Real,
+{
+    points
+        .iter()
+        .map(|p| Point3::new(na::convert(p.x), na::convert(p.y), na::convert(p.z)))
+        .collect()
+}
+
+fn convex_hull_shape_handle<T>(points: &[Point3<f64>]) -> Option<ShapeHandle<T>>
+where
+    T: Real,
+{
+    ConvexHull::try_from_points(&convert_points(points)).map(ShapeHandle::new)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct MeshKey {
+    path: PathBuf,
+    scale: [u64; 3],
+    mode: MeshMode,
+}
+
+/// Cache of the loaded meshes, which is shared by the clones
+///
+/// The shapes are keyed by the resolved path, the scale and the `MeshMode`, so the links and
+/// the checkers which use the same mesh share one `ShapeHandle`.
+pub struct MeshCache<T>
+where
+    T: Real,
+{
+    shapes: Arc<Mutex<HashMap<MeshKey, ShapeHandle<T>>>>,
+}
+
+impl<T> Clone for MeshCache<T>
+where
+    T: Real,
+{
+    fn clone(&self) -> Self {
+        MeshCache {
+            shapes: self.shapes.clone(),
+        }
+    }
+}
+
+impl<T> Default for MeshCache<T>
+where
+    T: Real,
+{
+    fn default() -> Self {
+        MeshCache {
+            shapes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<T> MeshCache<T>
+where
+    T: Real,
+{
+    /// Create empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Number of the cached shapes
+    pub fn len(&self) -> usize {
+        self.shapes.lock().unwrap().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Remove all the cached shapes
+    pub fn clear(&self) {
+        self.shapes.lock().unwrap().clear();
+    }
+    /// Returns the cached shape of the mesh file, or load it
+    pub fn get_or_load(
+        &self,
+        path: &Path,
+        scale: &[f64],
+        mode: MeshMode,
+    ) -> Result<ShapeHandle<T>> {
+        self.get_or_load_resource(&MeshResource::File(path.to_owned()), scale, mode)
+    }
+    /// Returns the cached shape of the mesh which is found by `ResourceResolver`, or load it
+    ///
+    /// The meshes in memory are keyed by their names.
+    pub fn get_or_load_resource(
+        &self,
+        resource: &MeshResource,
+        scale: &[f64],
+        mode: MeshMode,
+    ) -> Result<ShapeHandle<T>> {
+        let (resource, path) = match *resource {
+            MeshResource::File(ref path) => {
+                let path = fs::canonicalize(path)
+                    .map_err(|err| mesh_error(format!("{:?}: {}", path, err)))?;
+                (MeshResource::File(path.clone()), path)
+            }
+            MeshResource::Bytes { ref name, .. } => {
+                (resource.clone(), PathBuf::from(format!("bytes:{}", name)))
+            }
+        };
+        let key = MeshKey {
+            path,
+            scale: [scale[0].to_bits(), scale[1].to_bits(), scale[2].to_bits()],
+            mode,
+        };
+        if let Some(shape) = self.shapes.lock().unwrap().get(&key) {
+            return Ok(shape.clone());
+        }
+        // the lock is released while loading, because it can take long time
+        let file_path = match resource {
+            MeshResource::File(ref path) => Some(path.as_path()),
+            MeshResource::Bytes { .. } => None,
+        };
+        let shape = mesh_to_shape_handle(load_mesh(&resource, scale)?, mode, file_path, scale)?;
+        Ok(self
+            .shapes
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert(shape)
+            .clone())
+    }
+}
+
+/// Create the collision shape of the mesh
+///
+/// `path` and `scale` are only used for the cache of the convex decomposition, which is not
+/// cached if `path` is `None`.
+pub(crate) fn mesh_to_shape_handle<T>(
+    mesh: MeshData,
+    mode: MeshMode,
+    path: Option<&Path>,
+    scale: &[f64],
+) -> Result<ShapeHandle<T>>
+where
+    T: Real,
+{
+    let parts = match mode {
+        MeshMode::TriMesh => {
+            return Ok(ShapeHandle::new(TriMesh::new(
+                convert_points(&mesh.vertices),
+                mesh.indices,
+                None,
+            )))
+        }
+        MeshMode::ConvexHull => vec![mesh.vertices],
+        MeshMode::ConvexDecomposition { concavity } => match path {
+            Some(path) => {
+                let cache_path = decomposition_cache_path(path);
+                match read_decomposition_cache(&cache_path, path, scale, concavity) {
+                    Some(parts) => parts,
+                    None => {
+                        let parts = convex_decomposition(mesh, concavity);
+                        if let Err(err) =
+                            write_decomposition_cache(&cache_path, path, scale, concavity, &parts)
+                        {
+                            warn!("failed to write cache {:?}: {}", cache_path, err);
+                        }
+                        parts
+                    }
+                }
+            }
+            None => convex_decomposition(mesh, concavity),
+        },
+    };
+    let hulls = parts
+        .iter()
+        .filter_map(|points| convex_hull_shape_handle(points))
+        .collect::<Vec<_>>();
+    match hulls.len() {
+        0 => Err(mesh_error(format!(
+            "failed to create convex hull of {:?}",
+            path.unwrap_or_else(|| Path::new("the mesh"))
+        ))),
+        1 => Ok(hulls[0].clone()),
+        _ => Ok(ShapeHandle::new(Compound::new(
+            hulls
+                .into_iter()
+                .map(|hull| (Isometry3::identity(), hull))
+                .collect(),
+        ))),
+    }
+}
+
+/// Returns the vertices of the convex parts
+fn convex_decomposition(mesh: MeshData, concavity: f64) -> Vec<Vec<Point3<f64>>> {
+    let indices = mesh
+        .indices
+        .iter()
+        .map(|i| Point3::new(i.x as u32, i.y as u32, i.z as u32))
+        .collect();
+    let mut trimesh = procedural::TriMesh::new(
+        mesh.vertices,
+        None,
+        None,
+        Some(IndexBuffer::Unified(indices)),
+    );
+    trimesh.recompute_normals();
+    let (parts, _) = transformation::hacd(trimesh, concavity, 1);
+    parts.into_iter().map(|part| part.coords).collect()
+}
+
+fn decomposition_cache_path(path: &Path) -> PathBuf {
+    let mut cache_path = OsString::from(path.as_os_str());
+    cache_path.push(".convex");
+    PathBuf::from(cache_path)
+}
+
+const DECOMPOSITION_CACHE_HEADER: &str = "gear convex decomposition 1";
+
+/// The first lines of the cache, the cache is used only if they are the same
+fn decomposition_cache_key(path: &Path, scale: &[f64], concavity: f64) -> Result<Vec<String>> {
+    let modified = fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Ok(vec![
+        DECOMPOSITION_CACHE_HEADER.to_owned(),
+        format!("modified {}", modified),
+        format!("scale {} {} {}", scale[0], scale[1], scale[2]),
+        format!("concavity {}", concavity),
+    ])
+}
+
+fn read_decomposition_cache(
+    cache_path: &Path,
+    path: &Path,
+    scale: &[f64],
+    concavity: f64,
+) -> Option<Vec<Vec<Point3<f64>>>> {
+    let key = decomposition_cache_key(path, scale, concavity).ok()?;
+    let mut lines = BufReader::new(File::open(cache_path).ok()?).lines();
+    for expected in key {
+        if lines.next()?.ok()? != expected {
+            debug!("cache {:?} is outdated", cache_path);
+            return None;
+        }
+    }
+    let mut parts = Vec::new();
+    let mut part = Vec::new();
+    for line in lines {
+        let line = line.ok()?;
+        if line == "part" {
+            if !part.is_empty() {
+                parts.push(part);
+            }
+            part = Vec::new();
+            continue;
+        }
+        let values = line
+            .split_whitespace()
+            .map(|v| v.parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        if values.len() != 3 {
+            return None;
+        }
+        part.push(Point3::new(values[0], values[1], values[2]));
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    debug!("use cache {:?}", cache_path);
+    Some(parts)
+}
+
+fn write_decomposition_cache(
+    cache_path: &Path,
+    path: &Path,
+    scale: &[f64],
+    concavity: f64,
+    parts: &[Vec<Point3<f64>>],
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(cache_path)?);
+    for line in decomposition_cache_key(path, scale, concavity)? {
+        writeln!(writer, "{}", line)?;
+    }
+    for part in parts {
+        writeln!(writer, "part")?;
+        for p in part {
+            writeln!(writer, "{} {} {}", p.x, p.y, p.z)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ncollide3d::query;
+    use ncollide3d::shape::Ball;
+    use std::env;
+    use std::process;
+    use std::ptr;
+
+    /// Path in the temp dir which is unique for the process
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("gear_{}_{}", process::id(), name))
+    }
+
+    /// Two separated cubes which have one vertex in common on the diagonal
+    fn two_cubes() -> MeshData {
+        let mut mesh = MeshData::default();
+        for offset in &[0.0, 1.0] {
+            let base = mesh.vertices.len();
+            for i in 0..8 {
+                mesh.vertices.push(Point3::new(
+                    offset + (i & 1) as f64,
+                    offset + ((i >> 1) & 1) as f64,
+                    offset + ((i >> 2) & 1) as f64,
+                ));
+            }
+            for face in &[
+                [0, 2, 1],
+                [1, 2, 3],
+                [4, 5, 6],
+                [5, 7, 6],
+                [0, 1, 4],
+                [1, 5, 4],
+                [2, 6, 3],
+                [3, 6, 7],
+                [0, 4, 2],
+                [2, 4, 6],
+                [1, 3, 5],
+                [3, 7, 5],
+            ] {
+                mesh.indices
+                    .push(Point3::new(base + face[0], base + face[1], base + face[2]));
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn mesh_cache() {
+        let path = temp_path("mesh_cache_test.stl");
+        {
+            let mut file = File::create(&path).unwrap();
+            writeln!(
+                file,
+                "solid t
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+endsolid t"
+            )
+            .unwrap();
+        }
+        let cache = MeshCache::<f64>::new();
+        let scale = [1.0, 1.0, 1.0];
+        let shape1 = cache.get_or_load(&path, &scale, MeshMode::TriMesh).unwrap();
+        let shared = cache.clone();
+        let shape2 = shared
+            .get_or_load(&path, &scale, MeshMode::TriMesh)
+            .unwrap();
+        assert!(ptr::eq(
+            &*shape1 as *const _ as *const u8,
+            &*shape2 as *const _ as *const u8
+        ));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(shared.len(), 1);
+        cache
+            .get_or_load(&path, &[2.0, 1.0, 1.0], MeshMode::TriMesh)
+            .unwrap();
+        assert_eq!(cache.len(), 2);
+        cache
+            .get_or_load(&path, &scale, MeshMode::ConvexHull)
+            .unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_ne!(
+            MeshMode::ConvexDecomposition { concavity: 0.1 },
+            MeshMode::ConvexDecomposition { concavity: 0.2 }
+        );
+        assert!(cache
+            .get_or_load(
+                &env::temp_dir().join("gear_not_found.stl"),
+                &scale,
+                MeshMode::TriMesh
+            )
+            .is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn mesh_modes() {
+        let ball = Ball::new(0.01);
+        let inside = Isometry3::translation(0.5, 0.5, 0.5);
+        // the hull of the two cubes contains the point between them
+        let between = Isometry3::translation(1.5, 0.5, 0.5);
+        let path = temp_path("mesh_modes_test.stl");
+        File::create(&path).unwrap();
+        let scale = [1.0, 1.0, 1.0];
+
+        let trimesh = mesh_to_shape_handle::<f64>(
+            two_cubes(),
+            MeshMode::TriMesh,
+            Some(&path),
+            &scale,
+        ).unwrap();
+        assert!(query::distance(&inside, &ball, &Isometry3::identity(), &*trimesh) > 0.0);
+
+        let hull = mesh_to_shape_handle::<f64>(
+            two_cubes(),
+            MeshMode::ConvexHull,
+            Some(&path),
+            &scale,
+        ).unwrap();
+        assert!(query::distance(&inside, &ball, &Isometry3::identity(), &*hull) <= 0.0);
+        assert!(query::distance(&between, &ball, &Isometry3::identity(), &*hull) <= 0.0);
+
+        let mode = MeshMode::ConvexDecomposition { concavity: 0.01 };
+        let cache_path = decomposition_cache_path(&path);
+        let _ = fs::remove_file(&cache_path);
+        let parts = mesh_to_shape_handle::<f64>(two_cubes(), mode, Some(&path), &scale).unwrap();
+        assert!(query::distance(&inside, &ball, &Isometry3::identity(), &*parts) <= 0.0);
+        assert!(query::distance(&between, &ball, &Isometry3::identity(), &*parts) > 0.0);
+        assert!(cache_path.exists());
+        assert!(
+            read_decomposition_cache(&cache_path, &path, &scale, 0.01)
+                .unwrap()
+                .len()
+                >= 2
+        );
+        // the cache is not used for the different parameters
+        assert!(read_decomposition_cache(&cache_path, &path, &scale, 0.1).is_none());
+        let _ = fs::remove_file(&cache_path);
+        let _ = fs::remove_file(&path);
+    }
+}
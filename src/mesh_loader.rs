@@ -0,0 +1,567 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+//! Pure Rust loaders of STL, OBJ and Collada (DAE) meshes
+use na::{Matrix4, Point3, Rotation3, Unit, Vector3};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use xml::reader::{EventReader, XmlEvent};
+
+use errors::*;
+use mesh::{mesh_error, push_polygon, MeshData};
+
+fn read_mesh_from<R>(extension: &str, reader: R) -> Option<Result<MeshData>>
+where
+    R: BufRead,
+{
+    Some(match extension {
+        "stl" => read_stl(reader),
+        "obj" => read_obj(reader),
+        "dae" => read_dae(reader),
+        _ => return None,
+    })
+}
+
+fn is_supported(extension: &str) -> bool {
+    matches!(extension, "stl" | "obj" | "dae")
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_lowercase())
+}
+
+/// Read the mesh if the extension is supported, returns `None` otherwise
+pub(crate) fn read_mesh(path: &Path) -> Option<Result<MeshData>> {
+    let extension = extension_of(path)?;
+    if !is_supported(&extension) {
+        return None;
+    }
+    Some(
+        File::open(path)
+            .map_err(Error::from)
+            .and_then(|file| read_mesh_from(&extension, BufReader::new(file)).unwrap()),
+    )
+}
+
+/// Read the mesh in memory, `name` is used to get the extension
+pub(crate) fn read_mesh_bytes(name: &str, data: &[u8]) -> Option<Result<MeshData>> {
+    read_mesh_from(&extension_of(Path::new(name))?, data)
+}
+
+fn parse_f64(value: &str) -> Result<f64> {
+    value
+        .parse::<f64>()
+        .map_err(|_| mesh_error(format!("invalid number {}", value)))
+}
+
+fn parse_f64s(text: &str) -> Result<Vec<f64>> {
+    text.split_whitespace().map(parse_f64).collect()
+}
+
+fn parse_usizes(text: &str) -> Result<Vec<usize>> {
+    text.split_whitespace()
+        .map(|v| {
+            v.parse::<usize>()
+                .map_err(|_| mesh_error(format!("invalid index {}", v)))
+        })
+        .collect()
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    (0..4).fold(0, |value, i| value | u32::from(bytes[i]) << (8 * i))
+}
+
+/// Read binary or ASCII STL
+pub(crate) fn read_stl<R>(mut reader: R) -> Result<MeshData>
+where
+    R: Read,
+{
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut mesh = MeshData::default();
+    // binary STL has 80 bytes header, the number of the triangles and 50 bytes for each one
+    if bytes.len() >= 84 && bytes.len() == 84 + 50 * read_u32_le(&bytes[80..]) as usize {
+        for triangle in bytes[84..].chunks(50) {
+            let base = mesh.vertices.len();
+            for i in 0..3 {
+                // skip the normal (12 bytes)
+                let offset = 12 + 12 * i;
+                let value = |j: usize| {
+                    f64::from(f32::from_bits(read_u32_le(&triangle[offset + 4 * j..])))
+                };
+                mesh.vertices.push(Point3::new(value(0), value(1), value(2)));
+            }
+            mesh.indices.push(Point3::new(base, base + 1, base + 2));
+        }
+        return Ok(mesh);
+    }
+    let text = String::from_utf8(bytes).map_err(|_| mesh_error("invalid STL"))?;
+    let mut polygon = Vec::new();
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let values = words.map(parse_f64).collect::<Result<Vec<_>>>()?;
+                if values.len() != 3 {
+                    return Err(mesh_error(format!("invalid vertex: {}", line)));
+                }
+                polygon.push(mesh.vertices.len());
+                mesh.vertices
+                    .push(Point3::new(values[0], values[1], values[2]));
+            }
+            Some("endloop") => {
+                push_polygon(&mut mesh.indices, &polygon);
+                polygon.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(mesh)
+}
+
+/// Read Wavefront OBJ, the polygons are triangulated
+pub(crate) fn read_obj<R>(reader: R) -> Result<MeshData>
+where
+    R: BufRead,
+{
+    let mut mesh = MeshData::default();
+    for line in reader.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let values = words.take(3).map(parse_f64).collect::<Result<Vec<_>>>()?;
+                if values.len() != 3 {
+                    return Err(mesh_error(format!("invalid vertex: {}", line)));
+                }
+                mesh.vertices
+                    .push(Point3::new(values[0], values[1], values[2]));
+            }
+            Some("f") => {
+                let num_vertices = mesh.vertices.len() as i64;
+                let polygon = words
+                    .map(|word| {
+                        // `v`, `v/vt`, `v/vt/vn` or `v//vn`, negative index is relative
+                        let index = word
+                            .split('/')
+                            .next()
+                            .and_then(|v| v.parse::<i64>().ok())
+                            .ok_or_else(|| mesh_error(format!("invalid face: {}", line)))?;
+                        let index = if index < 0 {
+                            num_vertices + index
+                        } else {
+                            index - 1
+                        };
+                        if index < 0 || index >= num_vertices {
+                            return Err(mesh_error(format!("index out of range: {}", line)));
+                        }
+                        Ok(index as usize)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                push_polygon(&mut mesh.indices, &polygon);
+            }
+            _ => {}
+        }
+    }
+    Ok(mesh)
+}
+
+/// `<triangles>`, `<polylist>`, `<polygons>`, `<trifans>` or `<tristrips>`
+#[derive(Debug, Default)]
+struct Primitive {
+    kind: String,
+    vertex_source: String,
+    vertex_offset: usize,
+    /// Number of the indices for one vertex
+    stride: usize,
+    vcount: Vec<usize>,
+    p: Vec<Vec<usize>>,
+}
+
+impl Primitive {
+    /// Returns the polygons of the vertex indices
+    fn polygons(&self) -> Vec<Vec<usize>> {
+        let stride = self.stride.max(1);
+        let vertices = |p: &[usize]| -> Vec<usize> {
+            p.chunks(stride)
+                .filter_map(|c| c.get(self.vertex_offset).cloned())
+                .collect()
+        };
+        match self.kind.as_str() {
+            "triangles" => self
+                .p
+                .iter()
+                .flat_map(|p| {
+                    vertices(p)
+                        .chunks(3)
+                        .map(|c| c.to_vec())
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            "polylist" => {
+                let all = self.p.iter().flat_map(|p| vertices(p)).collect::<Vec<_>>();
+                let mut start = 0;
+                let mut polygons = Vec::new();
+                for count in &self.vcount {
+                    if start + count > all.len() {
+                        break;
+                    }
+                    polygons.push(all[start..start + count].to_vec());
+                    start += count;
+                }
+                polygons
+            }
+            "tristrips" => self
+                .p
+                .iter()
+                .flat_map(|p| {
+                    let v = vertices(p);
+                    (2..v.len())
+                        .map(|i| {
+                            if i % 2 == 0 {
+                                vec![v[i - 2], v[i - 1], v[i]]
+                            } else {
+                                vec![v[i - 1], v[i - 2], v[i]]
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            // `<polygons>` and `<trifans>` have one polygon in each `<p>`
+            _ => self.p.iter().map(|p| vertices(p)).collect(),
+        }
+    }
+}
+
+fn attribute(attributes: &[::xml::attribute::OwnedAttribute], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|attr| attr.name.local_name == name)
+        .map(|attr| attr.value.trim_start_matches('#').to_owned())
+}
+
+fn is_primitive(name: &str) -> bool {
+    matches!(
+        name,
+        "triangles" | "polylist" | "polygons" | "trifans" | "tristrips"
+    )
+}
+
+/// Parse the transform element of `<node>`
+fn transform_element(name: &str, values: &[f64]) -> Result<Option<Matrix4<f64>>> {
+    let invalid = || mesh_error(format!("invalid <{}>", name));
+    Ok(match name {
+        "matrix" if values.len() == 16 => Some(Matrix4::from_row_slice(values)),
+        "translate" if values.len() == 3 => Some(Matrix4::new_translation(&Vector3::new(
+            values[0], values[1], values[2],
+        ))),
+        "rotate" if values.len() == 4 => Some(
+            Rotation3::from_axis_angle(
+                &Unit::new_normalize(Vector3::new(values[0], values[1], values[2])),
+                values[3].to_radians(),
+            ).to_homogeneous(),
+        ),
+        "scale" if values.len() == 3 => Some(Matrix4::new_nonuniform_scaling(&Vector3::new(
+            values[0], values[1], values[2],
+        ))),
+        "matrix" | "translate" | "rotate" | "scale" => return Err(invalid()),
+        _ => None,
+    })
+}
+
+/// Read Collada (DAE)
+///
+/// The meshes in `<library_geometries>` are placed by the nodes of the visual scene, and
+/// scaled by `<unit>`. The up axis is ignored. The polygons are triangulated.
+pub(crate) fn read_dae<R>(reader: R) -> Result<MeshData>
+where
+    R: Read,
+{
+    let mut unit = 1.0;
+    let mut float_arrays = HashMap::new();
+    let mut source_strides = HashMap::new();
+    let mut vertices_sources = HashMap::new();
+    let mut geometries: HashMap<String, Vec<Primitive>> = HashMap::new();
+    let mut instances = Vec::new();
+
+    let mut stack: Vec<String> = Vec::new();
+    let mut text = String::new();
+    let mut current_geometry = String::new();
+    let mut current_source = String::new();
+    let mut current_vertices = String::new();
+    let mut current_primitive: Option<Primitive> = None;
+    // transforms of the nodes from the root of the scene
+    let mut node_transforms = vec![Matrix4::identity()];
+
+    for event in EventReader::new(reader) {
+        match event.map_err(|e| mesh_error(e.to_string()))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name;
+                text.clear();
+                match name.as_str() {
+                    "unit" => {
+                        if let Some(meter) = attribute(&attributes, "meter") {
+                            unit = parse_f64(&meter)?;
+                        }
+                    }
+                    "geometry" => {
+                        current_geometry = attribute(&attributes, "id").unwrap_or_default();
+                    }
+                    "source" => current_source = attribute(&attributes, "id").unwrap_or_default(),
+                    "accessor" => {
+                        if let Some(stride) = attribute(&attributes, "stride") {
+                            let stride = parse_usizes(&stride)?.get(0).cloned().unwrap_or(1);
+                            source_strides.insert(current_source.clone(), stride);
+                        }
+                    }
+                    "vertices" => {
+                        current_vertices = attribute(&attributes, "id").unwrap_or_default();
+                    }
+                    "input" => {
+                        let semantic = attribute(&attributes, "semantic").unwrap_or_default();
+                        let source = attribute(&attributes, "source").unwrap_or_default();
+                        let offset = match attribute(&attributes, "offset") {
+                            Some(offset) => parse_usizes(&offset)?.get(0).cloned().unwrap_or(0),
+                            None => 0,
+                        };
+                        let parent = stack.last().map(|s| s.as_str()).unwrap_or("");
+                        if parent == "vertices" && semantic == "POSITION" {
+                            vertices_sources.insert(current_vertices.clone(), source);
+                        } else if let Some(ref mut primitive) = current_primitive {
+                            primitive.stride = primitive.stride.max(offset + 1);
+                            if semantic == "VERTEX" {
+                                primitive.vertex_source = source;
+                                primitive.vertex_offset = offset;
+                            }
+                        }
+                    }
+                    "node" => {
+                        let parent = *node_transforms.last().unwrap();
+                        node_transforms.push(parent);
+                    }
+                    "instance_geometry" => {
+                        if let Some(url) = attribute(&attributes, "url") {
+                            instances.push((*node_transforms.last().unwrap(), url));
+                        }
+                    }
+                    name if is_primitive(name) => {
+                        current_primitive = Some(Primitive {
+                            kind: name.to_owned(),
+                            ..Default::default()
+                        });
+                    }
+                    _ => {}
+                }
+                stack.push(name);
+            }
+            XmlEvent::Characters(chars) => text.push_str(&chars),
+            XmlEvent::EndElement { .. } => {
+                let name = stack.pop().unwrap_or_default();
+                match name.as_str() {
+                    "float_array" => {
+                        float_arrays.insert(current_source.clone(), parse_f64s(&text)?);
+                    }
+                    "vcount" => {
+                        if let Some(ref mut primitive) = current_primitive {
+                            primitive.vcount = parse_usizes(&text)?;
+                        }
+                    }
+                    "p" => {
+                        if let Some(ref mut primitive) = current_primitive {
+                            primitive.p.push(parse_usizes(&text)?);
+                        }
+                    }
+                    "node" => {
+                        node_transforms.pop();
+                    }
+                    name if is_primitive(name) => {
+                        if let Some(primitive) = current_primitive.take() {
+                            geometries
+                                .entry(current_geometry.clone())
+                                .or_insert_with(Vec::new)
+                                .push(primitive);
+                        }
+                    }
+                    name => {
+                        if stack.last().map(|s| s == "node").unwrap_or(false) {
+                            if let Some(transform) = transform_element(name, &parse_f64s(&text)?)? {
+                                let current = node_transforms.last_mut().unwrap();
+                                *current *= transform;
+                            }
+                        }
+                    }
+                }
+                text.clear();
+            }
+            _ => {}
+        }
+    }
+    if instances.is_empty() {
+        // no visual scene, use all the geometries as they are
+        instances = geometries
+            .keys()
+            .map(|id| (Matrix4::identity(), id.to_owned()))
+            .collect();
+    }
+
+    let unit_scale = Matrix4::new_scaling(unit);
+    let mut mesh = MeshData::default();
+    for (transform, geometry_id) in instances {
+        let transform = unit_scale * transform;
+        let primitives = match geometries.get(&geometry_id) {
+            Some(primitives) => primitives,
+            None => {
+                warn!("geometry {} not found", geometry_id);
+                continue;
+            }
+        };
+        for primitive in primitives {
+            let source = vertices_sources
+                .get(&primitive.vertex_source)
+                .unwrap_or(&primitive.vertex_source);
+            let positions = float_arrays
+                .get(source)
+                .ok_or_else(|| mesh_error(format!("source {} not found", source)))?;
+            let stride = source_strides.get(source).cloned().unwrap_or(3).max(3);
+            let base = mesh.vertices.len();
+            let num_positions = positions.len() / stride;
+            for i in 0..num_positions {
+                let p = Point3::new(
+                    positions[i * stride],
+                    positions[i * stride + 1],
+                    positions[i * stride + 2],
+                );
+                mesh.vertices.push(
+                    Point3::from_homogeneous(transform * p.to_homogeneous())
+                        .ok_or_else(|| mesh_error("invalid transform"))?,
+                );
+            }
+            for polygon in primitive.polygons() {
+                if polygon.iter().any(|i| *i >= num_positions) {
+                    return Err(mesh_error(format!("index out of range in {}", geometry_id)));
+                }
+                push_polygon(
+                    &mut mesh.indices,
+                    &polygon.iter().map(|i| base + i).collect::<Vec<_>>(),
+                );
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_stl_and_obj() {
+        let ascii = read_stl(
+            "solid t
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+endloop
+endfacet
+endsolid t"
+                .as_bytes(),
+        ).unwrap();
+        assert_eq!(ascii.vertices.len(), 3);
+        assert_eq!(ascii.indices.len(), 1);
+
+        let mut binary = vec![0u8; 80];
+        binary.extend(&[1, 0, 0, 0]);
+        binary.extend(vec![0u8; 12]);
+        for v in &[0.0f32, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0] {
+            let bits = v.to_bits();
+            binary.extend((0..4).map(|i| (bits >> (8 * i)) as u8));
+        }
+        binary.extend(&[0, 0]);
+        let mesh = read_stl(binary.as_slice()).unwrap();
+        assert_eq!(mesh.vertices[1], Point3::new(2.0, 0.0, 0.0));
+
+        let mesh = read_obj(
+            "# quad and triangle
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+f 1//1 2//1 3//1 4//1
+f -4 -3 -1
+"
+                .as_bytes(),
+        ).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices.len(), 3);
+        assert_eq!(mesh.indices[2], Point3::new(0, 1, 3));
+        assert!(read_obj("v 0 0 0\nf 1 2 3\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_collada() {
+        let mesh = read_dae(
+            r##"<?xml version="1.0" encoding="utf-8"?>
+<COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">
+  <asset><unit name="centimeter" meter="0.01"/><up_axis>Z_UP</up_axis></asset>
+  <library_geometries>
+    <geometry id="quad-mesh" name="quad">
+      <mesh>
+        <source id="quad-positions">
+          <float_array id="quad-positions-array" count="12">0 0 0 1 0 0 1 1 0 0 1 0</float_array>
+          <technique_common>
+            <accessor source="#quad-positions-array" count="4" stride="3">
+              <param name="X" type="float"/><param name="Y" type="float"/><param name="Z" type="float"/>
+            </accessor>
+          </technique_common>
+        </source>
+        <vertices id="quad-vertices">
+          <input semantic="POSITION" source="#quad-positions"/>
+        </vertices>
+        <polylist count="1">
+          <input semantic="VERTEX" source="#quad-vertices" offset="0"/>
+          <input semantic="NORMAL" source="#quad-normals" offset="1"/>
+          <vcount>4</vcount>
+          <p>0 0 1 0 2 0 3 0</p>
+        </polylist>
+      </mesh>
+    </geometry>
+  </library_geometries>
+  <library_visual_scenes>
+    <visual_scene id="Scene">
+      <node id="parent">
+        <translate>10 0 0</translate>
+        <node id="child">
+          <scale>2 2 2</scale>
+          <instance_geometry url="#quad-mesh"/>
+        </node>
+      </node>
+    </visual_scene>
+  </library_visual_scenes>
+</COLLADA>"##
+                .as_bytes(),
+        ).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices.len(), 2);
+        // (1, 1, 0) * 2 + (10, 0, 0) in centimeter
+        assert!((mesh.vertices[2] - Point3::new(0.12, 0.02, 0.0)).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,370 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use k;
+use na::{self, Real};
+use num_traits::Float;
+
+use planning_algorithm::distance;
+
+/// Optimizer of the path in the joint space like CHOMP
+///
+/// The cost is the sum of the smoothness cost (squared differences between the waypoints) and
+/// the obstacle cost of the distances between the links and the obstacles. The gradient is
+/// preconditioned by the smoothness metric (covariant gradient descent of CHOMP), so the
+/// update of a waypoint is spread smoothly over the path. The first and the last waypoints
+/// are fixed, and the waypoints are kept in the joint limits.
+#[derive(Debug, Clone)]
+pub struct PathOptimizer<N>
+where
+    N: Real,
+{
+    /// The path is resampled to this number of waypoints including the both ends
+    pub num_waypoints: usize,
+    /// Max number of the gradient descent steps
+    pub num_iterations: usize,
+    /// Weight of the smoothness cost
+    pub smoothness_weight: N,
+    /// Weight of the obstacle cost
+    pub obstacle_weight: N,
+    /// The obstacle cost is zero if the distance is larger than this
+    pub clearance: N,
+    /// Step size of the gradient descent, it is reduced to keep `max_step`
+    pub learning_rate: N,
+    /// Max change of a joint position in an iteration
+    pub max_step: N,
+}
+
+impl<N> Default for PathOptimizer<N>
+where
+    N: Real,
+{
+    fn default() -> Self {
+        PathOptimizer {
+            num_waypoints: 20,
+            num_iterations: 100,
+            smoothness_weight: na::one(),
+            obstacle_weight: na::convert(10.0),
+            clearance: na::convert(0.1),
+            learning_rate: na::convert(0.1),
+            max_step: na::convert(0.05),
+        }
+    }
+}
+
+/// Resample the path at the same intervals
+fn resample<N>(path: &[Vec<N>], num_waypoints: usize) -> Vec<Vec<N>>
+where
+    N: Real,
+{
+    let lengths = path
+        .windows(2)
+        .map(|edge| distance(&edge[0], &edge[1]))
+        .collect::<Vec<_>>();
+    let total = lengths.iter().fold(N::zero(), |sum, l| sum + *l);
+    let mut waypoints = vec![path[0].clone()];
+    let mut edge = 0;
+    let mut edge_start = N::zero();
+    for i in 1..num_waypoints - 1 {
+        let target = total * na::convert(i as f64 / (num_waypoints - 1) as f64);
+        while edge < lengths.len() - 1 && edge_start + lengths[edge] < target {
+            edge_start += lengths[edge];
+            edge += 1;
+        }
+        let t = if lengths[edge] > N::zero() {
+            na::inf(&((target - edge_start) / lengths[edge]), &N::one())
+        } else {
+            N::zero()
+        };
+        waypoints.push(
+            path[edge]
+                .iter()
+                .zip(&path[edge + 1])
+                .map(|(a, b)| *a + (*b - *a) * t)
+                .collect(),
+        );
+    }
+    waypoints.push(path[path.len() - 1].clone());
+    waypoints
+}
+
+/// Solve `A x = b` in place, `A` is the tridiagonal matrix of the smoothness (2 on the diagonal
+/// and -1 on the others)
+fn solve_smoothness_metric<N>(b: &mut [N])
+where
+    N: Real,
+{
+    let n = b.len();
+    let two: N = na::convert(2.0);
+    let mut c = vec![N::zero(); n];
+    for i in 0..n {
+        let (prev_c, prev_b) = if i == 0 {
+            (N::zero(), N::zero())
+        } else {
+            (c[i - 1], b[i - 1])
+        };
+        let m = two + prev_c;
+        c[i] = -N::one() / m;
+        b[i] = (b[i] + prev_b) / m;
+    }
+    for i in (0..n.saturating_sub(1)).rev() {
+        b[i] -= c[i] * b[i + 1];
+    }
+}
+
+fn clamp<N>(value: N, limit: Option<&k::joint::Range<N>>) -> N
+where
+    N: Real,
+{
+    match limit {
+        Some(range) => na::sup(&range.min, &na::inf(&range.max, &value)),
+        None => value,
+    }
+}
+
+impl<N> PathOptimizer<N>
+where
+    N: Real + Float,
+{
+    /// Create the optimizer with the default parameters
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Derivative of the obstacle cost of CHOMP by the distance
+    ///
+    /// The cost is `clearance / 2 - distance` in collision, `(clearance - distance)^2 / (2 *
+    /// clearance)` in the clearance and zero outside, so it is smooth at zero and the
+    /// clearance.
+    fn distance_cost_derivative(&self, distance: N) -> N {
+        let zero: N = na::zero();
+        if distance < zero {
+            -N::one()
+        } else if distance <= self.clearance {
+            (distance - self.clearance) / self.clearance
+        } else {
+            zero
+        }
+    }
+    /// Optimize the path, the first and the last joint positions are kept
+    ///
+    /// `path` can be only the start and the goal, then the optimization starts from the
+    /// straight line. `distances` returns the signed distances between the links and the
+    /// obstacles at the joint positions, and `limits` are the limits of the joints. The
+    /// gradients of the distances are computed by the numerical differentiation, which calls
+    /// `distances` `dof + 1` times for each waypoint, so use `optimize_with_gradients` if the
+    /// gradients are available. The optimized path is not checked, so it may be in collision
+    /// if the obstacle cost does not converge to zero.
+    pub fn optimize(
+        &self,
+        path: &[Vec<N>],
+        limits: &[Option<k::joint::Range<N>>],
+        distances: &Fn(&[N]) -> Vec<N>,
+    ) -> Vec<Vec<N>> {
+        let delta: N = na::convert(1e-4);
+        self.optimize_with_gradients(path, limits, &|positions: &[N]| {
+            let current = distances(positions);
+            let mut gradients = vec![Vec::with_capacity(positions.len()); current.len()];
+            for j in 0..positions.len() {
+                // numerical differentiation inside the limits
+                let limit = limits.get(j).and_then(Option::as_ref);
+                let step = if clamp(positions[j] + delta, limit) == positions[j] + delta {
+                    delta
+                } else {
+                    -delta
+                };
+                let mut moved = positions.to_vec();
+                moved[j] += step;
+                let moved_distances = distances(&moved);
+                for (index, gradient) in gradients.iter_mut().enumerate() {
+                    gradient.push(
+                        moved_distances
+                            .get(index)
+                            .map_or(na::zero(), |d| (*d - current[index]) / step),
+                    );
+                }
+            }
+            current.into_iter().zip(gradients).collect()
+        })
+    }
+    /// Optimize the path with the gradients of the distances
+    ///
+    /// `distances` returns the signed distances and their gradients by the joint positions.
+    /// See `optimize` for the others.
+    pub fn optimize_with_gradients(
+        &self,
+        path: &[Vec<N>],
+        limits: &[Option<k::joint::Range<N>>],
+        distances: &Fn(&[N]) -> Vec<(N, Vec<N>)>,
+    ) -> Vec<Vec<N>> {
+        if path.len() < 2 || self.num_waypoints < 3 {
+            return path.to_vec();
+        }
+        let mut waypoints = resample(path, self.num_waypoints);
+        let dof = waypoints[0].len();
+        let num_free = waypoints.len() - 2;
+        let zero: N = na::zero();
+        let two: N = na::convert(2.0);
+        for _ in 0..self.num_iterations {
+            // gradient of each joint for the free waypoints
+            let mut gradients = vec![vec![zero; num_free]; dof];
+            for i in 1..waypoints.len() - 1 {
+                let mut obstacle = vec![zero; dof];
+                for (distance, gradient) in distances(&waypoints[i]) {
+                    let scale = self.distance_cost_derivative(distance);
+                    for (o, g) in obstacle.iter_mut().zip(&gradient) {
+                        *o += scale * *g;
+                    }
+                }
+                for j in 0..dof {
+                    let smoothness =
+                        two * waypoints[i][j] - waypoints[i - 1][j] - waypoints[i + 1][j];
+                    gradients[j][i - 1] =
+                        self.smoothness_weight * smoothness + self.obstacle_weight * obstacle[j];
+                }
+            }
+            let mut max_update = zero;
+            for gradient in &mut gradients {
+                solve_smoothness_metric(gradient);
+                for g in gradient.iter() {
+                    max_update = na::sup(&max_update, &Float::abs(*g));
+                }
+            }
+            if max_update <= zero {
+                break;
+            }
+            let rate = na::inf(&self.learning_rate, &(self.max_step / max_update));
+            for i in 1..waypoints.len() - 1 {
+                for (j, gradient) in gradients.iter().enumerate() {
+                    let value = waypoints[i][j] - rate * gradient[i - 1];
+                    waypoints[i][j] = clamp(value, limits.get(j).and_then(Option::as_ref));
+                }
+            }
+        }
+        waypoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collision_checker::CollisionChecker;
+    use distance_field::SignedDistanceField;
+    use na::{Isometry3, Point3, Vector3};
+    use ncollide3d::bounding_volume::AABB;
+    use ncollide3d::shape::{Compound, Cuboid, ShapeHandle};
+    use urdf_rs;
+
+    #[test]
+    fn path_optimizer() {
+        // a point in 2D and the circle at the origin whose radius is 0.3
+        let distances = |p: &[f64]| vec![(p[0] * p[0] + p[1] * p[1]).sqrt() - 0.3];
+        let path = vec![vec![-1.0, 0.1], vec![1.0, 0.1]];
+        let optimizer = PathOptimizer::new();
+        let limits = vec![None, None];
+        let optimized = optimizer.optimize(&path, &limits, &distances);
+        assert_eq!(optimized.len(), 20);
+        assert_eq!(optimized[0], path[0]);
+        assert_eq!(optimized[19], path[1]);
+        for p in &optimized {
+            assert!(distances(p)[0] > 0.05, "{:?}", p);
+        }
+        let length = optimized
+            .windows(2)
+            .map(|e| distance(&e[0], &e[1]))
+            .sum::<f64>();
+        assert!(length < 2.5, "{}", length);
+
+        // the joint limits are kept
+        let limits = vec![
+            None,
+            Some(k::joint::Range {
+                min: -1.0,
+                max: 0.2,
+            }),
+        ];
+        for p in optimizer.optimize(&path, &limits, &distances) {
+            assert!(p[1] <= 0.2);
+        }
+
+        // the straight path far from the obstacle is not changed
+        let path = vec![vec![-1.0, 1.0], vec![0.0, 1.0], vec![1.0, 1.0]];
+        let optimized = optimizer.optimize(&path, &limits[..1], &distances);
+        for (i, p) in optimized.iter().enumerate() {
+            assert!((p[0] - (-1.0 + 2.0 * i as f64 / 19.0)).abs() < 1e-9);
+            assert!((p[1] - 1.0).abs() < 1e-9);
+        }
+
+        // the analytic gradients
+        let with_gradients = |p: &[f64]| {
+            let norm = (p[0] * p[0] + p[1] * p[1]).sqrt();
+            vec![(norm - 0.3, vec![p[0] / norm, p[1] / norm])]
+        };
+        let path = vec![vec![-1.0, 0.1], vec![1.0, 0.1]];
+        let optimized = optimizer.optimize_with_gradients(&path, &[None, None], &with_gradients);
+        assert_eq!(optimized.len(), 20);
+        for p in &optimized {
+            assert!(distances(p)[0] > 0.05, "{:?}", p);
+        }
+    }
+    #[test]
+    fn field_distances_of_robot() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let checker = CollisionChecker::<f64>::from_urdf_robot(&urdf_robot, 0.0);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let arm = k::SerialChain::from_end(robot.find("l_wrist_pitch").unwrap());
+        let start = vec![0.0; 6];
+        let goal = vec![0.0, 0.0, 0.0, -1.0, 0.0, 0.0];
+
+        // the box is between the start and the goal
+        arm.set_joint_positions(&[0.0, 0.0, 0.0, -0.5, 0.0, 0.0]).unwrap();
+        robot.update_transforms();
+        let gripper = robot
+            .find("l_gripper_linear1")
+            .unwrap()
+            .world_transform()
+            .unwrap();
+        let objects = Compound::new(vec![(
+            Isometry3::new(gripper.translation.vector, na::zero()),
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.05, 0.05, 0.05))),
+        )]);
+        let aabb = AABB::new(Point3::new(-0.5, -1.0, -0.5), Point3::new(1.5, 1.0, 1.5));
+        let field = SignedDistanceField::from_objects(&objects, &aabb, 0.05).unwrap();
+        let distances = |angles: &[f64]| {
+            arm.set_joint_positions(angles).unwrap();
+            checker
+                .field_distances(&robot, &field)
+                .iter()
+                .map(|d| d.distance)
+                .collect::<Vec<_>>()
+        };
+        // the start and the goal are not moved by the optimizer
+        let min_distance = |path: &[Vec<f64>]| {
+            path[1..path.len() - 1]
+                .iter()
+                .map(|angles| distances(angles).into_iter().fold(::std::f64::MAX, f64::min))
+                .fold(::std::f64::MAX, f64::min)
+        };
+        let straight = vec![start, goal];
+        let limits = arm
+            .iter_joints()
+            .map(|j| j.limits.clone())
+            .collect::<Vec<_>>();
+        let optimizer = PathOptimizer::new();
+        let optimized = optimizer.optimize(&straight, &limits, &distances);
+        let before = min_distance(&optimizer.optimize(&straight, &limits, &|_: &[f64]| Vec::new()));
+        let after = min_distance(&optimized);
+        assert!(after > before, "{} > {}", after, before);
+    }
+}
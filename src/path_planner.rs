@@ -15,15 +15,29 @@ limitations under the License.
 */
 use k;
 use na;
-use ncollide3d::shape::Compound;
 use num_traits;
+use rand;
 use rrt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
 use urdf_rs;
 
 use collision_checker::*;
+use distance_field::{FieldDistance, SignedDistanceField};
 use errors::*;
 use funcs::*;
+use mesh::MeshCache;
+use path_optimizer::PathOptimizer;
+use planning_algorithm::{is_edge_feasible, PlanningAlgorithm, PlanningBudget, RrtConnect};
+use planning_scene::{CollisionObjects, PlanningScene};
+use point_cloud::PointCloud;
+use srdf::Srdf;
+
+/// Max number of the steps of the conservative advancement for one edge
+const MAX_CONSERVATIVE_ADVANCEMENT_STEPS: usize = 1000;
 
 /// Collision Avoidance Path Planner
 pub struct JointPathPlanner<N>
@@ -33,6 +47,9 @@ where
     /// Instance of `k::HasLinks` to check the collision
     pub collision_check_robot: k::Chain<N>,
     /// Collision checker
+    ///
+    /// Call `algorithm.invalidate()` after changing the paddings or the allowed collision
+    /// matrix directly.
     pub collision_checker: CollisionChecker<N>,
     /// Unit length for searching
     ///
@@ -42,8 +59,19 @@ where
     pub max_try: usize,
     /// Num of path smoothing trials
     pub num_smoothing: usize,
+    /// Algorithm to search the path, `RrtConnect` by default
+    pub algorithm: Box<PlanningAlgorithm<N>>,
+    /// Give up the search after this duration if it is set
+    pub timeout: Option<Duration>,
+    /// Check the edges of the path continuously instead of the discrete points
+    ///
+    /// If true, the shortcuts of the smoothing and the final path are certified by
+    /// `is_motion_valid`, so thin obstacles are never tunnelled through.
+    pub continuous_collision_check: bool,
     /// The robot instance which is used to create the robot model
     pub urdf_robot: Option<urdf_rs::Robot>,
+    /// Semantic description of the robot (planning groups and named states)
+    pub srdf: Option<Srdf>,
 }
 
 impl<N> JointPathPlanner<N>
@@ -64,46 +92,384 @@ where
             step_length,
             max_try,
             num_smoothing,
+            algorithm: Box::new(RrtConnect),
+            timeout: None,
+            continuous_collision_check: false,
             urdf_robot: None,
+            srdf: None,
         }
     }
     /// Check if the joint_positions are OK
-    pub fn is_feasible(
+    ///
+    /// The collision with `objects` and the self collision are checked.
+    pub fn is_feasible<O>(
         &self,
         using_joints: &k::Chain<N>,
         joint_positions: &[N],
-        objects: &Compound<N>,
-    ) -> bool {
+        objects: &O,
+    ) -> bool
+    where
+        O: CollisionObjects<N>,
+    {
         if using_joints.set_joint_positions(joint_positions).is_err() {
             return false;
         }
-        !self.has_any_colliding(objects)
+        !self.has_any_colliding(objects) && !self.has_any_self_colliding()
     }
-    /// Check if there are any colliding links
-    pub fn has_any_colliding(&self, objects: &Compound<N>) -> bool {
-        for shape in objects.shapes() {
-            if self.collision_checker.has_any_colliding(
-                &self.collision_check_robot,
-                &*shape.1,
-                &shape.0,
-            ) {
+    /// Check if the straight motion from `start_angles` to `goal_angles` is collision free
+    ///
+    /// The conservative advancement is used: the interpolated joint positions are advanced by
+    /// the step which is guaranteed to be collision free by the distances between links and
+    /// `objects` and the bounds of the link motions (see `CollisionChecker::link_motion_bounds`).
+    /// The self collision is checked at each advanced joint positions, which are at most
+    /// `step_length` apart, even if there are no obstacles.
+    pub fn is_motion_valid<O>(
+        &self,
+        using_joints: &k::Chain<N>,
+        start_angles: &[N],
+        goal_angles: &[N],
+        objects: &O,
+    ) -> bool
+    where
+        O: CollisionObjects<N>,
+    {
+        if start_angles.len() != goal_angles.len() {
+            return false;
+        }
+        let joint_names = using_joints
+            .iter_joints()
+            .map(|j| j.name.clone())
+            .collect::<Vec<_>>();
+        let diff = goal_angles
+            .iter()
+            .zip(start_angles.iter())
+            .map(|(goal, start)| *goal - *start)
+            .collect::<Vec<_>>();
+        // the max motion of each link for the whole edge
+        let link_motions = self
+            .collision_checker
+            .link_motion_bounds(&self.collision_check_robot, &joint_names)
+            .into_iter()
+            .map(|(name, coefficients)| {
+                let motion = coefficients
+                    .iter()
+                    .zip(diff.iter())
+                    .fold(N::zero(), |sum, (c, d)| sum + *c * na::Real::abs(*d));
+                (name, motion)
+            })
+            .collect::<HashMap<_, _>>();
+        // the self collision is not bounded by the distances, so the step is limited
+        let length = na::Real::sqrt(diff.iter().fold(N::zero(), |sum, d| sum + *d * *d));
+        let max_dt = if length > self.step_length {
+            self.step_length / length
+        } else {
+            N::one()
+        };
+        let mut t = N::zero();
+        for _ in 0..MAX_CONSERVATIVE_ADVANCEMENT_STEPS {
+            let angles = start_angles
+                .iter()
+                .zip(diff.iter())
+                .map(|(start, d)| *start + *d * t)
+                .collect::<Vec<_>>();
+            if using_joints.set_joint_positions(&angles).is_err() || self.has_any_self_colliding()
+            {
+                return false;
+            }
+            let mut dt = max_dt;
+            // the distances are already reduced by the paddings
+            for (name, distance) in self
+                .collision_checker
+                .link_distances(&self.collision_check_robot, objects)
+            {
+                if distance <= N::zero() {
+                    return false;
+                }
+                if let Some(motion) = link_motions.get(&name) {
+                    if *motion > N::zero() {
+                        dt = na::Real::min(dt, distance / *motion);
+                    }
+                }
+            }
+            if t >= N::one() {
                 return true;
             }
+            t = na::Real::min(t + dt, N::one());
         }
+        debug!("conservative advancement did not reach the goal");
         false
     }
+    /// Shortcut the path by the edges which are certified by `is_motion_valid`
+    fn smooth_path_with_motion_check<O>(
+        &self,
+        using_joints: &k::Chain<N>,
+        path: &mut Vec<Vec<N>>,
+        objects: &O,
+    )
+    where
+        O: CollisionObjects<N>,
+    {
+        for _ in 0..self.num_smoothing {
+            let len = path.len();
+            if len < 3 {
+                return;
+            }
+            let i = rand::random::<usize>() % (len - 2);
+            let j = i + 2 + rand::random::<usize>() % (len - i - 2);
+            if self.is_motion_valid(using_joints, &path[i], &path[j], objects) {
+                path.drain(i + 1..j);
+            }
+        }
+    }
+    /// Check if there are any colliding links
+    pub fn has_any_colliding<O>(&self, objects: &O) -> bool
+    where
+        O: CollisionObjects<N>,
+    {
+        self.collision_checker
+            .has_any_colliding_with_objects(&self.collision_check_robot, objects)
+    }
     /// Get the names of colliding links
-    pub fn colliding_link_names(&self, objects: &Compound<N>) -> Vec<String> {
-        let mut ret = Vec::new();
-        for shape in objects.shapes() {
-            let mut colliding_names = self.collision_checker.colliding_link_names(
-                &self.collision_check_robot,
-                &*shape.1,
-                &shape.0,
-            );
-            ret.append(&mut colliding_names);
+    pub fn colliding_link_names<O>(&self, objects: &O) -> Vec<String>
+    where
+        O: CollisionObjects<N>,
+    {
+        self.collision_checker
+            .colliding_link_names_with_objects(&self.collision_check_robot, objects)
+    }
+    /// Get the ids of the objects which are colliding with the robot
+    ///
+    /// If `objects` are not named (e.g. `Compound`), the index of the shape is used as the id.
+    pub fn colliding_object_ids<O>(&self, objects: &O) -> Vec<String>
+    where
+        O: CollisionObjects<N>,
+    {
+        let mut ids = Vec::new();
+        for contact in self.contacts(objects) {
+            let obstacle_index = contact.obstacle_index;
+            let id = contact
+                .obstacle_id
+                .unwrap_or_else(|| obstacle_index.to_string());
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+    /// Get the names of the links which are colliding with the object
+    pub fn colliding_link_names_with_object(
+        &self,
+        scene: &PlanningScene<N>,
+        id: &str,
+    ) -> Result<Vec<String>> {
+        let object = scene
+            .object(id)
+            .ok_or_else(|| Error::from(format!("object {} not found", id)))?;
+        let objects = PlanningScene::from_objects(vec![object.clone()]);
+        Ok(self.colliding_link_names(&objects))
+    }
+    /// Get the closest obstacle and the signed distance for each link
+    pub fn closest_obstacles<O>(&self, objects: &O) -> Vec<LinkDistance<N>>
+    where
+        O: CollisionObjects<N>,
+    {
+        self.collision_checker
+            .closest_obstacles(&self.collision_check_robot, objects)
+    }
+    /// Get the minimum signed distance between the robot and `objects`
+    ///
+    /// Returns `None` if there are no obstacles.
+    pub fn clearance<O>(&self, objects: &O) -> Option<N>
+    where
+        O: CollisionObjects<N>,
+    {
+        self.closest_obstacles(objects)
+            .into_iter()
+            .map(|d| d.distance)
+            .fold(None, |min, d| match min {
+                Some(min) if min < d => Some(min),
+                _ => Some(d),
+            })
+    }
+    /// Get the contacts between the links and `objects`
+    pub fn contacts<O>(&self, objects: &O) -> Vec<ContactReport<N>>
+    where
+        O: CollisionObjects<N>,
+    {
+        self.collision_checker
+            .contacts(&self.collision_check_robot, objects)
+    }
+    /// Create `Error::Collision` with the contacts at the current joint positions
+    fn collision_error<O>(&self, part: CollisionPart, objects: &O) -> Error
+    where
+        O: CollisionObjects<N>,
+    {
+        Error::Collision {
+            part,
+            contacts: self
+                .contacts(objects)
+                .iter()
+                .map(|contact| contact.to_f64())
+                .collect(),
+            self_colliding_pairs: self.self_colliding_link_pairs(),
+        }
+    }
+    /// Check if there are any pairs of links which are colliding with each other
+    pub fn has_any_self_colliding(&self) -> bool {
+        self.collision_checker
+            .has_self_colliding(&self.collision_check_robot)
+    }
+    /// Get the pairs of names of self colliding links
+    pub fn self_colliding_link_pairs(&self) -> Vec<(String, String)> {
+        self.collision_checker
+            .self_colliding_link_pairs(&self.collision_check_robot)
+    }
+
+    /// Remove the points on the robot from the cloud of a sensor
+    ///
+    /// The points closer than `padding` to the links (and the attached objects) are removed.
+    /// The cloud must be in the world frame.
+    pub fn filter_robot_points(&self, cloud: &PointCloud<N>, padding: N) -> PointCloud<N> {
+        PointCloud::new(self.collision_checker.filter_robot_points(
+            &self.collision_check_robot,
+            &cloud.points,
+            padding,
+        ))
+    }
+    /// Move the object from `scene` onto the link, keeping the current relative pose
+    ///
+    /// `link_name` and `touch_links` can be the link names of URDF or the joint names.
+    /// The touch links are allowed to collide with the object (e.g. the gripper fingers).
+    pub fn attach_object(
+        &mut self,
+        scene: &mut PlanningScene<N>,
+        object_id: &str,
+        link_name: &str,
+        touch_links: &[&str],
+    ) -> Result<()> {
+        let link_joint_map = self.link_joint_map();
+        let to_joint_name = |name: &str| {
+            link_joint_map
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| name.to_owned())
+        };
+        let joint_name = to_joint_name(link_name);
+        self.collision_check_robot.update_transforms();
+        let link_pose = self
+            .collision_check_robot
+            .find(&joint_name)
+            .ok_or_else(|| Error::from(format!("link {} not found", link_name)))?
+            .world_transform()
+            .unwrap();
+        let mut object = scene
+            .remove_object(object_id)
+            .ok_or_else(|| Error::from(format!("object {} not found", object_id)))?;
+        object.pose = link_pose.inverse() * object.pose;
+        self.collision_checker.attach_object(
+            object,
+            &joint_name,
+            touch_links.iter().map(|name| to_joint_name(name)).collect(),
+        );
+        self.algorithm.invalidate();
+        Ok(())
+    }
+    /// Detach the object from the robot and put it back to `scene` at the current pose
+    pub fn detach_object(&mut self, scene: &mut PlanningScene<N>, object_id: &str) -> Result<()> {
+        let attached = self
+            .collision_checker
+            .detach_object(object_id)
+            .ok_or_else(|| Error::from(format!("attached object {} not found", object_id)))?;
+        self.collision_check_robot.update_transforms();
+        let mut object = attached.object;
+        if let Some(node) = self.collision_check_robot.find(&attached.link_name) {
+            object.pose = node.world_transform().unwrap() * object.pose;
         }
-        ret
+        scene.add_object(object);
+        self.algorithm.invalidate();
+        Ok(())
+    }
+    /// Set the padding of the link (see `CollisionChecker::link_paddings`)
+    ///
+    /// Use this instead of changing `collision_checker` directly, so that the results of the
+    /// collision checks which are kept by `algorithm` are discarded.
+    pub fn set_link_padding(&mut self, link_name: &str, length: N) {
+        self.collision_checker
+            .link_paddings
+            .insert(link_name.to_owned(), length);
+        self.algorithm.invalidate();
+    }
+    /// Set the padding of the obstacle (see `CollisionChecker::obstacle_paddings`)
+    pub fn set_obstacle_padding(&mut self, obstacle_id: &str, length: N) {
+        self.collision_checker
+            .obstacle_paddings
+            .insert(obstacle_id.to_owned(), length);
+        self.algorithm.invalidate();
+    }
+    /// Allow the collision between the links (see `CollisionChecker::allowed_collision_matrix`)
+    pub fn allow_collision(&mut self, link1: &str, link2: &str) {
+        self.collision_checker
+            .allowed_collision_matrix
+            .allow(link1, link2);
+        self.algorithm.invalidate();
+    }
+
+    fn link_joint_map(&self) -> HashMap<String, String> {
+        match self.urdf_robot {
+            Some(ref urdf_robot) => k::urdf::link_to_joint_map(urdf_robot),
+            None => HashMap::new(),
+        }
+    }
+    /// Create the kinematic chain of the planning group which is defined in SRDF
+    pub fn planning_group(&self, group_name: &str) -> Result<k::SerialChain<N>> {
+        let srdf = self.srdf.as_ref().ok_or("SRDF is not set")?;
+        srdf.create_serial_chain(
+            group_name,
+            &self.collision_check_robot,
+            &self.link_joint_map(),
+        )
+    }
+    /// Get the joint positions of `using_joints` for the named `<group_state>` of the group
+    ///
+    /// The current positions are used for the joints which are not in the state.
+    pub fn named_joint_positions(
+        &self,
+        using_joints: &k::Chain<N>,
+        group_name: &str,
+        state_name: &str,
+    ) -> Result<Vec<N>> {
+        let srdf = self.srdf.as_ref().ok_or("SRDF is not set")?;
+        let state_positions = srdf
+            .named_joint_positions(group_name, state_name)
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "group_state {} of group {} not found",
+                    state_name, group_name
+                ))
+            })?;
+        Ok(using_joints
+            .iter_joints()
+            .zip(using_joints.joint_positions().into_iter())
+            .map(|(joint, current)| match state_positions.get(&joint.name) {
+                Some(position) => na::convert(*position),
+                None => current,
+            })
+            .collect())
+    }
+    /// Plan the path to the named `<group_state>` of the group
+    pub fn plan_to_named_state<O>(
+        &self,
+        using_joints: &k::Chain<N>,
+        start_angles: &[N],
+        group_name: &str,
+        state_name: &str,
+        objects: &O,
+    ) -> Result<Vec<Vec<N>>>
+    where
+        O: CollisionObjects<N>,
+    {
+        let goal_angles = self.named_joint_positions(using_joints, group_name, state_name)?;
+        self.plan(using_joints, start_angles, &goal_angles, objects)
     }
 
     /// Plan the sequence of joint angles of `using_joints`
@@ -113,55 +479,251 @@ where
     /// - `start_angles`: initial joint angles of `using_joints`.
     /// - `goal_angles`: goal joint angles of `using_joints`.
     /// - `objects`: The collision between `self.collision_check_robot` and `objects`
-    ///   will be checked.
-    pub fn plan(
+    ///   will be checked. The self collision of `self.collision_check_robot` is also checked.
+    pub fn plan<O>(
         &self,
         using_joints: &k::Chain<N>,
         start_angles: &[N],
         goal_angles: &[N],
-        objects: &Compound<N>,
-    ) -> Result<Vec<Vec<N>>> {
+        objects: &O,
+    ) -> Result<Vec<Vec<N>>>
+    where
+        O: CollisionObjects<N>,
+    {
         let limits = using_joints
             .iter_joints()
             .map(|j| j.limits.clone())
             .collect();
+        let joint_names = using_joints
+            .iter_joints()
+            .map(|j| j.name.clone())
+            .collect::<Vec<_>>();
+        let fixed_joint_positions = self
+            .collision_check_robot
+            .iter_joints()
+            .zip(self.collision_check_robot.joint_positions())
+            .filter(|&(ref joint, _)| !joint_names.contains(&joint.name))
+            .map(|(_, position)| position)
+            .collect::<Vec<_>>();
+        self.algorithm
+            .update_joints(&joint_names, &fixed_joint_positions)?;
         let step_length = self.step_length;
-        let max_try = self.max_try;
         let current_angles = using_joints.joint_positions();
         if !self.is_feasible(using_joints, start_angles, objects) {
+            let error = self.collision_error(CollisionPart::Start, objects);
             using_joints.set_joint_positions(&current_angles)?;
-            return Err(Error::Collision {
-                part: CollisionPart::Start,
-            });
+            return Err(error);
         } else if !self.is_feasible(using_joints, goal_angles, objects) {
+            let error = self.collision_error(CollisionPart::End, objects);
             using_joints.set_joint_positions(&current_angles)?;
-            return Err(Error::Collision {
-                part: CollisionPart::End,
-            });
+            return Err(error);
         }
-        let mut path = match rrt::dual_rrt_connect(
+        let budget = PlanningBudget {
+            step_length,
+            max_try: self.max_try,
+            timeout: self.timeout,
+        };
+        let link_motion_bounds = self
+            .collision_checker
+            .link_motion_bounds(&self.collision_check_robot, &joint_names);
+        let max_obstacle_padding = self.collision_checker.max_obstacle_padding(objects);
+        self.algorithm
+            .update_obstacles(objects, &|from: &[N], to: &[N]| {
+                // the positions out of the limits are infeasible with any obstacles
+                if using_joints.set_joint_positions(from).is_err() {
+                    return Vec::new();
+                }
+                let motions = from
+                    .iter()
+                    .zip(to)
+                    .map(|(f, t)| *t - *f)
+                    .collect::<Vec<_>>();
+                self.collision_checker.swept_aabbs(
+                    &self.collision_check_robot,
+                    &link_motion_bounds,
+                    &motions,
+                    max_obstacle_padding,
+                )
+            });
+        let mut path = match self.algorithm.plan(
             start_angles,
             goal_angles,
-            |angles: &[N]| self.is_feasible(using_joints, angles, objects),
-            || generate_random_joint_positions_from_limits(&limits),
-            step_length,
-            max_try,
+            &|angles: &[N]| self.is_feasible(using_joints, angles, objects),
+            &|| generate_random_joint_positions_from_limits(&limits),
+            &budget,
         ) {
             Ok(p) => p,
             Err(error) => {
                 using_joints.set_joint_positions(&current_angles)?;
-                return Err(Error::from(error));
+                return Err(error);
             }
         };
-        let num_smoothing = self.num_smoothing;
-        rrt::smooth_path(
-            &mut path,
-            |angles: &[N]| self.is_feasible(using_joints, angles, objects),
-            step_length,
-            num_smoothing,
-        );
+        if self.continuous_collision_check {
+            self.smooth_path_with_motion_check(using_joints, &mut path, objects);
+            for (i, edge) in path.windows(2).enumerate() {
+                if !self.is_motion_valid(using_joints, &edge[0], &edge[1], objects) {
+                    using_joints.set_joint_positions(&current_angles)?;
+                    return Err(Error::from(format!(
+                        "edge {} of the path is not collision free",
+                        i
+                    )));
+                }
+            }
+        } else {
+            let num_smoothing = self.num_smoothing;
+            rrt::smooth_path(
+                &mut path,
+                |angles: &[N]| self.is_feasible(using_joints, angles, objects),
+                step_length,
+                num_smoothing,
+            );
+        }
         Ok(path)
     }
+    /// Optimize the path for the smoothness and the clearance from `objects` (see
+    /// `PathOptimizer`)
+    ///
+    /// It can be used for any path, e.g. the path of `plan` or only the start and the goal.
+    /// The distances between the links and `objects` are computed by
+    /// `CollisionChecker::link_distances`. Returns error if the optimized path is not collision
+    /// free, then the original path should be used.
+    pub fn optimize_path<O>(
+        &self,
+        optimizer: &PathOptimizer<N>,
+        using_joints: &k::Chain<N>,
+        path: &[Vec<N>],
+        objects: &O,
+    ) -> Result<Vec<Vec<N>>>
+    where
+        O: CollisionObjects<N>,
+    {
+        let limits = using_joints
+            .iter_joints()
+            .map(|j| j.limits.clone())
+            .collect::<Vec<_>>();
+        let current_angles = using_joints.joint_positions();
+        let optimized = optimizer.optimize(path, &limits, &|angles: &[N]| {
+            if using_joints.set_joint_positions(angles).is_err() {
+                return Vec::new();
+            }
+            self.collision_checker
+                .link_distances(&self.collision_check_robot, objects)
+                .into_iter()
+                .map(|(_, distance)| distance)
+                .collect()
+        });
+        self.check_optimized_path(using_joints, optimized, &current_angles, objects)
+    }
+    /// Optimize the path with the distances in the signed distance field of `objects`
+    ///
+    /// It is faster than `optimize_path`, because the links are approximated by the spheres
+    /// (see `CollisionChecker::field_distances`) and the gradients of the distances are the
+    /// gradients of the field mapped by the Jacobians of the links like CHOMP. The optimized
+    /// path is checked with `objects`.
+    pub fn optimize_path_with_field<O>(
+        &self,
+        optimizer: &PathOptimizer<N>,
+        using_joints: &k::Chain<N>,
+        path: &[Vec<N>],
+        field: &SignedDistanceField<N>,
+        objects: &O,
+    ) -> Result<Vec<Vec<N>>>
+    where
+        O: CollisionObjects<N>,
+    {
+        let limits = using_joints
+            .iter_joints()
+            .map(|j| j.limits.clone())
+            .collect::<Vec<_>>();
+        let joint_names = using_joints
+            .iter_joints()
+            .map(|j| j.name.clone())
+            .collect::<Vec<_>>();
+        let current_angles = using_joints.joint_positions();
+        let optimized = optimizer.optimize_with_gradients(path, &limits, &|angles: &[N]| {
+            if using_joints.set_joint_positions(angles).is_err() {
+                return Vec::new();
+            }
+            self.collision_checker
+                .field_distances(&self.collision_check_robot, field)
+                .into_iter()
+                .map(|d| {
+                    let gradient = self.field_distance_gradient(&joint_names, &d);
+                    (d.distance, gradient)
+                })
+                .collect()
+        });
+        self.check_optimized_path(using_joints, optimized, &current_angles, objects)
+    }
+    /// Gradient of the field distance by the joint positions of `joint_names`
+    ///
+    /// The velocity of the closest sphere is computed by the Jacobian of the link, and its dot
+    /// product with the gradient of the field is the gradient of the distance. The joints
+    /// which do not move the link are zero.
+    fn field_distance_gradient(
+        &self,
+        joint_names: &[String],
+        distance: &FieldDistance<N>,
+    ) -> Vec<N> {
+        let mut gradient = vec![na::zero(); joint_names.len()];
+        let link_name = match self.collision_checker.attached_object(&distance.link_name) {
+            Some(attached) => &attached.link_name,
+            None => &distance.link_name,
+        };
+        let node = match self.collision_check_robot.find(link_name) {
+            Some(node) => node,
+            None => return gradient,
+        };
+        let chain = k::SerialChain::from_end(node);
+        let jacobian = k::jacobian(&chain);
+        // the Jacobian is for the origin of the link
+        let offset = distance.point.coords - node.world_transform().unwrap().translation.vector;
+        for (column, joint) in chain.iter_joints().enumerate() {
+            if let Some(index) = joint_names.iter().position(|name| *name == joint.name) {
+                let linear = na::Vector3::new(
+                    jacobian[(0, column)],
+                    jacobian[(1, column)],
+                    jacobian[(2, column)],
+                );
+                let angular = na::Vector3::new(
+                    jacobian[(3, column)],
+                    jacobian[(4, column)],
+                    jacobian[(5, column)],
+                );
+                gradient[index] = distance.gradient.dot(&(linear + angular.cross(&offset)));
+            }
+        }
+        gradient
+    }
+    /// Restore the joint positions and check if the optimized path is collision free
+    fn check_optimized_path<O>(
+        &self,
+        using_joints: &k::Chain<N>,
+        optimized: Vec<Vec<N>>,
+        current_angles: &[N],
+        objects: &O,
+    ) -> Result<Vec<Vec<N>>>
+    where
+        O: CollisionObjects<N>,
+    {
+        let is_feasible = |angles: &[N]| self.is_feasible(using_joints, angles, objects);
+        let is_valid = optimized.iter().all(|angles| is_feasible(angles))
+            && optimized.windows(2).all(|edge| {
+                if self.continuous_collision_check {
+                    self.is_motion_valid(using_joints, &edge[0], &edge[1], objects)
+                } else {
+                    is_edge_feasible(&edge[0], &edge[1], self.step_length, &is_feasible)
+                }
+            });
+        using_joints.set_joint_positions(current_angles)?;
+        if is_valid {
+            Ok(optimized)
+        } else {
+            Err(Error::from(
+                "optimized path is not collision free".to_owned(),
+            ))
+        }
+    }
     /// Calculate the transforms of all of the links
     pub fn update_transforms(&self) -> Vec<na::Isometry3<N>> {
         self.collision_check_robot.update_transforms()
@@ -186,8 +748,15 @@ where
     step_length: N,
     max_try: usize,
     num_smoothing: usize,
+    algorithm: Option<Box<PlanningAlgorithm<N>>>,
+    timeout: Option<Duration>,
     collision_check_margin: Option<N>,
+    link_paddings: HashMap<String, N>,
+    obstacle_paddings: HashMap<String, N>,
+    continuous_collision_check: bool,
+    sphere_trees: bool,
     urdf_robot: Option<urdf_rs::Robot>,
+    srdf: Option<Srdf>,
 }
 
 impl<N> JointPathPlannerBuilder<N>
@@ -204,14 +773,36 @@ where
             step_length: na::convert(0.1),
             max_try: 5000,
             num_smoothing: 100,
+            algorithm: None,
+            timeout: None,
             collision_check_margin: None,
+            link_paddings: HashMap::new(),
+            obstacle_paddings: HashMap::new(),
+            continuous_collision_check: false,
+            sphere_trees: false,
             urdf_robot: None,
+            srdf: None,
         }
     }
+    /// Set the default padding of the links
     pub fn collision_check_margin(mut self, length: N) -> Self {
         self.collision_check_margin = Some(length);
         self
     }
+    /// Set the padding of the link, which overrides `collision_check_margin`
+    pub fn link_padding(mut self, link_name: &str, length: N) -> Self {
+        self.link_paddings.insert(link_name.to_owned(), length);
+        self
+    }
+    /// Set the padding of the obstacle
+    ///
+    /// `obstacle_id` is the id of the object, or the index of the shape if the obstacles are
+    /// not named (e.g. `Compound`). See `CollisionChecker::obstacle_paddings`.
+    pub fn obstacle_padding(mut self, obstacle_id: &str, length: N) -> Self {
+        self.obstacle_paddings
+            .insert(obstacle_id.to_owned(), length);
+        self
+    }
     pub fn step_length(mut self, step_length: N) -> Self {
         self.step_length = step_length;
         self
@@ -224,6 +815,36 @@ where
         self.num_smoothing = num_smoothing;
         self
     }
+    /// Set the algorithm to search the path, `RrtConnect` is used if it is not set
+    pub fn algorithm<A>(mut self, algorithm: A) -> Self
+    where
+        A: PlanningAlgorithm<N> + 'static,
+    {
+        self.algorithm = Some(Box::new(algorithm));
+        self
+    }
+    /// Give up the search after the duration
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Use `JointPathPlanner::is_motion_valid` for smoothing and validating the path
+    pub fn continuous_collision_check(mut self, enabled: bool) -> Self {
+        self.continuous_collision_check = enabled;
+        self
+    }
+    /// Check the sphere trees of the links before their exact shapes
+    ///
+    /// See `CollisionChecker::enable_sphere_trees`.
+    pub fn sphere_trees(mut self, enabled: bool) -> Self {
+        self.sphere_trees = enabled;
+        self
+    }
+    /// Set SRDF, `<disable_collisions>` are added to the allowed collision matrix
+    pub fn srdf(mut self, srdf: Srdf) -> Self {
+        self.srdf = Some(srdf);
+        self
+    }
     pub fn finalize(mut self) -> JointPathPlanner<N> {
         let mut planner = JointPathPlanner::new(
             self.collision_check_robot,
@@ -233,9 +854,35 @@ where
             self.num_smoothing,
         );
         if let Some(margin) = self.collision_check_margin {
-            self.collision_checker.prediction = margin;
+            planner.collision_checker.prediction = margin;
+        }
+        if let Some(algorithm) = self.algorithm.take() {
+            planner.algorithm = algorithm;
         }
+        planner.timeout = self.timeout;
+        planner.collision_checker.link_paddings = self.link_paddings;
+        planner.collision_checker.obstacle_paddings = self.obstacle_paddings;
+        if self.sphere_trees {
+            planner.collision_checker.enable_sphere_trees();
+        }
+        if let Some(ref srdf) = self.srdf {
+            let link_joint_map = match self.urdf_robot {
+                Some(ref urdf_robot) => k::urdf::link_to_joint_map(urdf_robot),
+                None => HashMap::new(),
+            };
+            let to_joint_name = |link: &String| link_joint_map.get(link).unwrap_or(link).to_owned();
+            for pair in &srdf.disable_collisions {
+                planner
+                    .collision_checker
+                    .allowed_collision_matrix
+                    .allow(&to_joint_name(&pair.link1), &to_joint_name(&pair.link2));
+            }
+        }
+        planner.continuous_collision_check = self.continuous_collision_check;
         planner.urdf_robot = self.urdf_robot;
+        planner.srdf = self.srdf;
+        // the shared algorithm may keep the results with the other paddings
+        planner.algorithm.invalidate();
         planner
     }
 }
@@ -245,17 +892,60 @@ where
     N: na::Real,
 {
     /// Try to create `JointPathPlannerBuilder` instance from URDF file and end link name
+    ///
+    /// The `<capsule>` extension is supported (see `read_urdf_with_capsules`).
     pub fn from_urdf_file<P>(file: P) -> Result<JointPathPlannerBuilder<N>>
     where
         P: AsRef<Path>,
     {
-        let robot = urdf_rs::utils::read_urdf_or_xacro(file.as_ref())?;
+        Self::from_urdf_file_with_options(file, CollisionModelOptions::default())
+    }
+    /// Try to create `JointPathPlannerBuilder` instance from URDF file with the options for
+    /// the collision models (e.g. `MeshMode`)
+    ///
+    /// The capsules in the file are added to `options`, and the directory of the file is used
+    /// as `base_dir` if it is not set.
+    pub fn from_urdf_file_with_options<P>(
+        file: P,
+        options: CollisionModelOptions,
+    ) -> Result<JointPathPlannerBuilder<N>>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_urdf_file_with_mesh_cache(file, options, &MeshCache::new())
+    }
+    /// Try to create `JointPathPlannerBuilder` instance sharing the loaded meshes
+    ///
+    /// Use `collision_checker.mesh_cache()` of another planner to share the meshes with it.
+    pub fn from_urdf_file_with_mesh_cache<P>(
+        file: P,
+        mut options: CollisionModelOptions,
+        mesh_cache: &MeshCache<N>,
+    ) -> Result<JointPathPlannerBuilder<N>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = file.as_ref();
+        let xml = match path.extension() {
+            Some(ext) if ext == "xacro" => urdf_rs::utils::convert_xacro_to_urdf(path)?,
+            _ => {
+                let mut xml = String::new();
+                File::open(path)?.read_to_string(&mut xml)?;
+                xml
+            }
+        };
+        let (robot, capsules) = read_urdf_with_capsules(&xml)?;
+        options.capsules = capsules;
+        if options.base_dir.is_none() {
+            options.base_dir = path.parent().map(Path::to_path_buf);
+        }
         let default_margin = na::convert(0.0);
-        let collision_checker = CollisionChecker::from_urdf_robot_with_base_dir(
+        let collision_checker = CollisionChecker::from_urdf_robot_with_mesh_cache(
             &robot,
-            file.as_ref().parent(),
+            &options,
+            mesh_cache,
             default_margin,
-        );
+        )?;
         get_joint_path_planner_builder_from_urdf(robot, collision_checker)
     }
     /// Try to create `JointPathPlannerBuilder` instance from `urdf_rs::Robot` instance
@@ -279,8 +969,15 @@ where
         step_length: na::convert(0.1),
         max_try: 5000,
         num_smoothing: 100,
+        algorithm: None,
+        timeout: None,
         collision_check_margin: None,
+        link_paddings: HashMap::new(),
+        obstacle_paddings: HashMap::new(),
+        continuous_collision_check: false,
+        sphere_trees: false,
         urdf_robot: Some(urdf_robot),
+        srdf: None,
     })
 }
 
@@ -288,8 +985,13 @@ where
 mod tests {
     use super::*;
     use na;
-    use na::{Isometry3, Vector3};
-    use ncollide3d::shape::Cuboid;
+    use na::{Isometry3, Point3, Vector3};
+    use ncollide3d::bounding_volume::AABB;
+    use ncollide3d::shape::{Compound, Cuboid, ShapeHandle};
+    use distance_field::*;
+    use planning_algorithm::*;
+    use planning_scene::*;
+    use rrt_star::*;
     use urdf_rs;
 
     #[test]
@@ -340,6 +1042,517 @@ mod tests {
         );
     }
     #[test]
+    fn distance_query() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let checker = CollisionChecker::from_urdf_robot(&urdf_robot, 0.01);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let far = ShapeHandle::new(Cuboid::new(Vector3::new(0.1, 0.1, 0.1)));
+        let near = ShapeHandle::new(Cuboid::new(Vector3::new(0.5, 1.0, 0.5)));
+        let objects = Compound::new(vec![
+            (Isometry3::new(Vector3::new(0.0, 0.0, 3.0), na::zero()), far),
+            (Isometry3::new(Vector3::new(0.9, 0.0, 0.0), na::zero()), near),
+        ]);
+        let distances = checker.closest_obstacles(&robot, &objects);
+        let elbow = distances
+            .iter()
+            .find(|d| d.link_name == "l_elbow_pitch")
+            .unwrap();
+        assert_eq!(elbow.obstacle_index, 1);
+        assert!(elbow.distance < 0.0);
+        let shoulder = distances
+            .iter()
+            .find(|d| d.link_name == "l_shoulder_yaw")
+            .unwrap();
+        // shoulder box is [-0.05, 0.05] in x, the near box starts from 0.4, and the link
+        // padding (the prediction 0.01) is subtracted
+        assert_eq!(shoulder.obstacle_index, 1);
+        assert!((shoulder.distance - 0.34).abs() < 1e-6);
+        assert!((shoulder.link_point.x - 0.05).abs() < 1e-6);
+        assert!((shoulder.obstacle_point.x - 0.4).abs() < 1e-6);
+    }
+    #[test]
+    fn contact_report() {
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .finalize();
+        let target = ShapeHandle::new(Cuboid::new(Vector3::new(0.5, 1.0, 0.5)));
+        let objects = Compound::new(vec![(
+            Isometry3::new(Vector3::new(0.9, 0.0, 0.0), na::zero()),
+            target,
+        )]);
+        let contacts = planner.contacts(&objects);
+        assert_eq!(contacts.len(), planner.colliding_link_names(&objects).len());
+        for contact in &contacts {
+            assert_eq!(contact.obstacle_index, 0);
+            assert!(contact.depth > 0.0);
+        }
+        let robot = &planner.collision_check_robot;
+        let angles = robot.joint_positions();
+        match planner.plan(robot, &angles, &angles, &objects) {
+            Err(Error::Collision {
+                part: CollisionPart::Start,
+                contacts,
+                self_colliding_pairs,
+            }) => {
+                assert_eq!(contacts.len(), 5);
+                assert!(self_colliding_pairs.is_empty());
+            }
+            _ => panic!("start must be colliding"),
+        }
+    }
+    #[test]
+    fn broad_phase() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let checker = CollisionChecker::from_urdf_robot(&urdf_robot, 0.01);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let mut shapes = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let pose = Isometry3::new(
+                    Vector3::new(-2.0 + 0.2 * i as f64, -2.0 + 0.2 * j as f64, 1.0),
+                    na::zero(),
+                );
+                let shape = ShapeHandle::new(Cuboid::new(Vector3::new(0.05, 0.05, 0.05)));
+                shapes.push((pose, shape));
+            }
+        }
+        let target = ShapeHandle::new(Cuboid::new(Vector3::new(0.5, 1.0, 0.5)));
+        shapes.push((Isometry3::new(Vector3::new(0.9, 0.0, 0.0), na::zero()), target));
+        let objects = Compound::new(shapes);
+        let mut brute_force = Vec::new();
+        for shape in objects.shapes() {
+            for name in checker.colliding_link_names(&robot, &*shape.1, &shape.0) {
+                if !brute_force.contains(&name) {
+                    brute_force.push(name);
+                }
+            }
+        }
+        let mut names = checker.colliding_link_names_with_objects(&robot, &objects);
+        brute_force.sort();
+        names.sort();
+        assert_eq!(names.len(), 5);
+        assert_eq!(names, brute_force);
+        assert!(checker.has_any_colliding_with_objects(&robot, &objects));
+    }
+    #[test]
+    fn motion_validity() {
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .continuous_collision_check(true)
+            .finalize();
+        let arm = k::SerialChain::from_end(
+            planner
+                .collision_check_robot
+                .find("l_wrist_pitch")
+                .unwrap(),
+        );
+        // thin pole which is hit only in the middle of the motion of the shoulder
+        let pole = ShapeHandle::new(Cuboid::new(Vector3::new(0.005, 0.005, 1.0)));
+        let objects = Compound::new(vec![(
+            Isometry3::new(Vector3::new(0.6, 0.4, 0.0), na::zero()),
+            pole,
+        )]);
+        let start = vec![-1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let goal = vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        assert!(planner.is_feasible(&arm, &start, &objects));
+        assert!(planner.is_feasible(&arm, &goal, &objects));
+        assert!(!planner.is_motion_valid(&arm, &start, &goal, &objects));
+        let goal = vec![-0.5, 0.0, 0.0, 0.0, 0.0, 0.0];
+        assert!(planner.is_motion_valid(&arm, &start, &goal, &objects));
+    }
+    #[test]
+    fn padding() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let mut checker = CollisionChecker::from_urdf_robot(&urdf_robot, 0.01);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        // the shoulder box is [-0.05, 0.05] in x
+        let target = ShapeHandle::new(Cuboid::new(Vector3::new(0.1, 0.1, 0.1)));
+        let objects = Compound::new(vec![(
+            Isometry3::new(Vector3::new(0.2, 0.2, 0.5), na::zero()),
+            target.clone(),
+        )]);
+        let is_shoulder_colliding = |checker: &CollisionChecker<f64>| {
+            checker
+                .colliding_link_names_with_objects(&robot, &objects)
+                .contains(&"l_shoulder_yaw".to_owned())
+        };
+        assert!(!is_shoulder_colliding(&checker));
+        checker
+            .link_paddings
+            .insert("l_shoulder_yaw".to_owned(), 0.06);
+        assert!(is_shoulder_colliding(&checker));
+        checker.link_paddings.clear();
+        checker.obstacle_paddings.insert("0".to_owned(), 0.05);
+        assert!(is_shoulder_colliding(&checker));
+        let shoulder = checker
+            .closest_obstacles(&robot, &objects)
+            .into_iter()
+            .find(|d| d.link_name == "l_shoulder_yaw")
+            .unwrap();
+        // 0.05 (actual distance) - 0.01 (default) - 0.05 (obstacle)
+        assert!((shoulder.distance - (-0.01)).abs() < 1e-6);
+
+        // the padding is kept by the id when the indices of the shapes are changed
+        let mut scene = PlanningScene::from_objects(vec![
+            CollisionObject::new(
+                "far",
+                target.clone(),
+                Isometry3::new(Vector3::new(5.0, 0.0, 0.0), na::zero()),
+            ),
+            CollisionObject::new(
+                "near",
+                target,
+                Isometry3::new(Vector3::new(0.2, 0.2, 0.5), na::zero()),
+            ),
+        ]);
+        checker.obstacle_paddings.clear();
+        checker.obstacle_paddings.insert("near".to_owned(), 0.05);
+        let is_shoulder_colliding_with_scene =
+            |checker: &CollisionChecker<f64>, scene: &PlanningScene<f64>| {
+                checker
+                    .colliding_link_names_with_objects(&robot, scene)
+                    .contains(&"l_shoulder_yaw".to_owned())
+            };
+        assert!(is_shoulder_colliding_with_scene(&checker, &scene));
+        scene.remove_object("far").unwrap();
+        assert!(is_shoulder_colliding_with_scene(&checker, &scene));
+    }
+    #[test]
+    fn planning_scene() {
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .finalize();
+        let mut scene = PlanningScene::from_urdf_file("obstacles.urdf").unwrap();
+        assert_eq!(scene.object_ids(), vec!["obj1", "obj2"]);
+        assert!(planner.colliding_object_ids(&scene).is_empty());
+        let target = ShapeHandle::new(Cuboid::new(Vector3::new(0.5, 1.0, 0.5)));
+        scene.add_object(CollisionObject::new(
+            "target",
+            target,
+            Isometry3::new(Vector3::new(3.0, 0.0, 0.0), na::zero()),
+        ));
+        assert!(planner.colliding_object_ids(&scene).is_empty());
+        scene
+            .move_object(
+                "target",
+                Isometry3::new(Vector3::new(0.9, 0.0, 0.0), na::zero()),
+            )
+            .unwrap();
+        assert_eq!(planner.colliding_object_ids(&scene), vec!["target"]);
+        assert_eq!(
+            planner
+                .colliding_link_names_with_object(&scene, "target")
+                .unwrap()
+                .len(),
+            5
+        );
+        assert!(
+            planner
+                .contacts(&scene)
+                .iter()
+                .all(|c| c.obstacle_id == Some("target".to_owned()))
+        );
+        scene.remove_object("target").unwrap();
+        assert!(!planner.has_any_colliding(&scene));
+    }
+    #[test]
+    fn attached_object() {
+        let mut planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .finalize();
+        let mut scene = PlanningScene::new();
+        scene.add_object(CollisionObject::new(
+            "part",
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.02, 0.02, 0.02))),
+            Isometry3::new(Vector3::new(1.2, 0.4, 0.5), na::zero()),
+        ));
+        scene.add_object(CollisionObject::new(
+            "wall",
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.05, 0.05, 0.05))),
+            Isometry3::new(Vector3::new(1.25, 0.4, 0.5), na::zero()),
+        ));
+        assert!(planner.colliding_link_names(&scene).is_empty());
+        planner
+            .attach_object(&mut scene, "part", "l_wrist2", &[])
+            .unwrap();
+        assert_eq!(scene.object_ids(), vec!["wall"]);
+        assert_eq!(planner.colliding_link_names(&scene), vec!["part"]);
+        assert!(planner.attach_object(&mut scene, "part", "l_wrist2", &[]).is_err());
+
+        // the touch links are given by the link names of URDF
+        scene.add_object(CollisionObject::new(
+            "grasped",
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.03, 0.04, 0.02))),
+            Isometry3::new(Vector3::new(0.85, 0.4, 0.5), na::zero()),
+        ));
+        planner
+            .attach_object(&mut scene, "grasped", "l_wrist2", &["l_gripper1", "l_gripper2"])
+            .unwrap();
+        assert!(!planner.has_any_self_colliding());
+
+        planner.detach_object(&mut scene, "part").unwrap();
+        let part = scene.object("part").unwrap();
+        assert!((part.pose.translation.vector - Vector3::new(1.2, 0.4, 0.5)).norm() < 1e-6);
+        assert!(planner.colliding_link_names(&scene).is_empty());
+        assert!(planner.detach_object(&mut scene, "part").is_err());
+    }
+    #[test]
+    fn self_collision_check() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let mut checker = CollisionChecker::from_urdf_robot(&urdf_robot, 0.01);
+        let robot = k::Chain::<f32>::from(&urdf_robot);
+        assert!(checker.self_colliding_link_pairs(&robot).is_empty());
+
+        // close the gripper fingers into each other
+        robot
+            .find("l_gripper_linear1")
+            .unwrap()
+            .set_joint_position(0.05)
+            .unwrap();
+        robot
+            .find("l_gripper_linear2")
+            .unwrap()
+            .set_joint_position(-0.05)
+            .unwrap();
+        let pairs = checker.self_colliding_link_pairs(&robot);
+        assert_eq!(pairs.len(), 1);
+        assert!(checker.has_self_colliding(&robot));
+        checker
+            .allowed_collision_matrix
+            .allow("l_gripper_linear1", "l_gripper_linear2");
+        assert!(!checker.has_self_colliding(&robot));
+    }
+    #[test]
+    fn plan_with_srdf() {
+        let srdf = ::srdf::read_from_string(
+            r#"
+<robot name="robo">
+  <group name="left_arm">
+    <chain base_link="root" tip_link="l_wrist2" />
+  </group>
+  <group_state name="home" group="left_arm">
+    <joint name="l_elbow_pitch" value="-0.5" />
+  </group_state>
+  <disable_collisions link1="l_gripper1" link2="l_gripper2" reason="Never" />
+</robot>"#,
+        ).unwrap();
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .srdf(srdf)
+            .finalize();
+        assert!(
+            planner
+                .collision_checker
+                .allowed_collision_matrix
+                .is_allowed("l_gripper_linear2", "l_gripper_linear1")
+        );
+        let arm = planner.planning_group("left_arm").unwrap();
+        assert_eq!(arm.joint_positions().len(), 6);
+        let goal = planner
+            .named_joint_positions(&arm, "left_arm", "home")
+            .unwrap();
+        assert_eq!(goal, vec![0.0, 0.0, 0.0, -0.5, 0.0, 0.0]);
+        let start = arm.joint_positions();
+        let path = planner
+            .plan_to_named_state(&arm, &start, "left_arm", "home", &Compound::new(vec![]))
+            .unwrap();
+        assert_eq!(path.last().unwrap(), &goal);
+    }
+    #[derive(Debug)]
+    struct StraightLine;
+
+    impl PlanningAlgorithm<f64> for StraightLine {
+        fn plan(
+            &self,
+            start: &[f64],
+            goal: &[f64],
+            is_feasible: &Fn(&[f64]) -> bool,
+            _sample: &Fn() -> Vec<f64>,
+            budget: &PlanningBudget<f64>,
+        ) -> Result<Vec<Vec<f64>>> {
+            let length = start
+                .iter()
+                .zip(goal)
+                .map(|(s, g)| (g - s).powi(2))
+                .sum::<f64>()
+                .sqrt();
+            let num = (length / budget.step_length).ceil().max(1.0) as usize;
+            let path = (0..num + 1)
+                .map(|i| {
+                    let t = i as f64 / num as f64;
+                    start
+                        .iter()
+                        .zip(goal)
+                        .map(|(s, g)| s + (g - s) * t)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            if path.iter().all(|p| is_feasible(p)) {
+                Ok(path)
+            } else {
+                Err(Error::from("straight line is not feasible".to_owned()))
+            }
+        }
+    }
+
+    #[test]
+    fn planning_algorithm() {
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .step_length(0.05)
+            .num_smoothing(0)
+            .algorithm(StraightLine)
+            .finalize();
+        let arm = k::SerialChain::from_end(
+            planner
+                .collision_check_robot
+                .find("l_wrist_pitch")
+                .unwrap(),
+        );
+        let start = vec![0.0; 6];
+        let goal = vec![0.0, 0.0, 0.0, -0.5, 0.0, 0.0];
+        let path = planner
+            .plan(&arm, &start, &goal, &Compound::new(vec![]))
+            .unwrap();
+        assert_eq!(path.len(), 11);
+        assert_eq!(path.last().unwrap(), &goal);
+
+        // the box is between the start and the goal
+        let goal = vec![0.0, 0.0, 0.0, -1.0, 0.0, 0.0];
+        arm.set_joint_positions(&[0.0, 0.0, 0.0, -0.5, 0.0, 0.0]).unwrap();
+        let gripper = planner
+            .collision_check_robot
+            .find("l_gripper_linear1")
+            .unwrap()
+            .world_transform()
+            .unwrap();
+        let objects = Compound::new(vec![(
+            Isometry3::new(gripper.translation.vector, na::zero()),
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.05, 0.05, 0.05))),
+        )]);
+        assert!(planner.is_feasible(&arm, &start, &objects));
+        assert!(planner.is_feasible(&arm, &goal, &objects));
+        assert!(planner.plan(&arm, &start, &goal, &objects).is_err());
+
+        // RRT* minimizes the length of the path
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .num_smoothing(0)
+            .max_try(1000)
+            .algorithm(RrtStar::new())
+            .finalize();
+        let arm = k::SerialChain::from_end(
+            planner
+                .collision_check_robot
+                .find("l_wrist_pitch")
+                .unwrap(),
+        );
+        let path = planner
+            .plan(&arm, &start, &goal, &Compound::new(vec![]))
+            .unwrap();
+        assert_eq!(path.last().unwrap(), &goal);
+        assert!(path_cost(&JointDistance, &path) < 1.5);
+
+        // RRT-Connect is the default, and it gives up after the timeout
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .timeout(::std::time::Duration::new(0, 0))
+            .finalize();
+        let arm = k::SerialChain::from_end(
+            planner
+                .collision_check_robot
+                .find("l_wrist_pitch")
+                .unwrap(),
+        );
+        match planner.plan(&arm, &start, &goal, &objects) {
+            Err(Error::Timeout { .. }) => {}
+            result => panic!("expected timeout but {:?}", result),
+        }
+    }
+    #[test]
+    fn path_optimizer() {
+        let planner = JointPathPlannerBuilder::<f64>::from_urdf_file("sample.urdf")
+            .unwrap()
+            .finalize();
+        let arm = k::SerialChain::from_end(
+            planner
+                .collision_check_robot
+                .find("l_wrist_pitch")
+                .unwrap(),
+        );
+        let optimizer = PathOptimizer::new();
+        let start = vec![0.0; 6];
+        let goal = vec![0.0, 0.0, 0.0, -1.0, 0.0, 0.0];
+        let no_objects = Compound::new(vec![]);
+        let path = planner.plan(&arm, &start, &goal, &no_objects).unwrap();
+        let optimized = planner
+            .optimize_path(&optimizer, &arm, &path, &no_objects)
+            .unwrap();
+        assert_eq!(optimized.len(), optimizer.num_waypoints);
+        assert_eq!(optimized[0], start);
+        assert_eq!(optimized.last().unwrap(), &goal);
+        let cost = path_cost(&JointDistance, &optimized);
+        assert!(cost < 1.05, "{}", cost);
+        assert!(cost <= path_cost(&JointDistance, &path) + 1e-9);
+
+        arm.set_joint_positions(&[0.0, 0.0, 0.0, -0.5, 0.0, 0.0]).unwrap();
+        let gripper = planner
+            .collision_check_robot
+            .find("l_gripper_linear1")
+            .unwrap()
+            .world_transform()
+            .unwrap();
+        let aabb = AABB::new(Point3::new(-0.5, -1.0, -0.5), Point3::new(1.5, 1.0, 1.5));
+        // the box is beside the straight path in the clearance, so the optimized path is
+        // collision free and farther from the box
+        let objects = Compound::new(vec![(
+            Isometry3::new(
+                gripper.translation.vector + Vector3::new(0.0, 0.2, 0.0),
+                na::zero(),
+            ),
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.03, 0.03, 0.03))),
+        )]);
+        let field = SignedDistanceField::from_objects(&objects, &aabb, 0.05).unwrap();
+        // the start and the goal are not moved by the optimizer
+        let min_distance = |path: &[Vec<f64>]| {
+            path[1..path.len() - 1]
+                .iter()
+                .map(|angles| {
+                    arm.set_joint_positions(angles).unwrap();
+                    planner
+                        .collision_checker
+                        .field_distances(&planner.collision_check_robot, &field)
+                        .iter()
+                        .map(|d| d.distance)
+                        .fold(::std::f64::MAX, f64::min)
+                })
+                .fold(::std::f64::MAX, f64::min)
+        };
+        let straight = vec![start.clone(), goal.clone()];
+        let limits = arm
+            .iter_joints()
+            .map(|j| j.limits.clone())
+            .collect::<Vec<_>>();
+
+        let mut optimizer = PathOptimizer::new();
+        optimizer.clearance = 0.3;
+        for angles in &straight {
+            assert!(planner.is_feasible(&arm, angles, &objects));
+        }
+        let before = min_distance(&optimizer.optimize(&straight, &limits, &|_: &[f64]| Vec::new()));
+        let optimized = planner
+            .optimize_path_with_field(&optimizer, &arm, &straight, &field, &objects)
+            .unwrap();
+        assert_eq!(optimized.len(), optimizer.num_waypoints);
+        assert_eq!(optimized[0], start);
+        assert_eq!(optimized.last().unwrap(), &goal);
+        for angles in &optimized {
+            assert!(planner.is_feasible(&arm, angles, &objects));
+        }
+        let after = min_distance(&optimized);
+        assert!(after > before, "{} > {}", after, before);
+    }
+    #[test]
     fn from_urdf() {
         let _planner = JointPathPlannerBuilder::from_urdf_file("sample.urdf")
             .unwrap()
@@ -0,0 +1,319 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Real};
+use ncollide3d::bounding_volume::AABB;
+use num_traits::Float;
+use rrt;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use planning_scene::CollisionObjects;
+
+/// Limits of the search of `PlanningAlgorithm`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlanningBudget<N> {
+    /// Unit length to extend the path
+    pub step_length: N,
+    /// Max number of the iterations
+    pub max_try: usize,
+    /// Stop the search after this duration if it is set
+    pub timeout: Option<Duration>,
+}
+
+impl<N> PlanningBudget<N> {
+    /// The time to stop the search, which is measured from now
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+}
+
+/// Sampling-based algorithm to find a path in the joint space
+///
+/// `JointPathPlanner::plan` validates the start and the goal, calls the algorithm and smooths
+/// the returned path, so the algorithm only needs to search the path.
+pub trait PlanningAlgorithm<N>: fmt::Debug + Send + Sync
+where
+    N: Real,
+{
+    /// Find the path from `start` to `goal`, both of them are included in the path
+    ///
+    /// `is_feasible` checks the joint positions, and `sample` returns random joint positions
+    /// in the limits.
+    fn plan(
+        &self,
+        start: &[N],
+        goal: &[N],
+        is_feasible: &Fn(&[N]) -> bool,
+        sample: &Fn() -> Vec<N>,
+        budget: &PlanningBudget<N>,
+    ) -> Result<Vec<Vec<N>>>;
+    /// Called with the obstacles before `plan`
+    ///
+    /// The algorithms which keep the results of the collision checks between the queries
+    /// (e.g. `Prm`) use it to know that the obstacles are changed.
+    /// `swept_bounds(from, to)` returns the AABBs which contain the robot while it moves
+    /// straight from `from` to `to`. They are loosened by the paddings, so the results of the
+    /// checks are not changed by the obstacles which don't overlap with them.
+    fn update_obstacles(
+        &self,
+        _obstacles: &CollisionObjects<N>,
+        _swept_bounds: &Fn(&[N], &[N]) -> Vec<AABB<N>>,
+    ) {
+    }
+    /// Called with the joints of the query before `plan`
+    ///
+    /// `joint_names` are the joints of the positions, and `fixed_joint_positions` are the
+    /// positions of the other joints of the robot, which don't move during the query.
+    fn update_joints(&self, _joint_names: &[String], _fixed_joint_positions: &[N]) -> Result<()> {
+        Ok(())
+    }
+    /// Discard the results of the collision checks which are kept between the queries
+    ///
+    /// It is called when the robot is changed, e.g. an object is attached or the paddings are
+    /// changed.
+    fn invalidate(&self) {}
+}
+
+/// Share the algorithm with the planner, e.g. to save the roadmap of `Prm` after planning
+impl<N, A> PlanningAlgorithm<N> for Arc<A>
+where
+    N: Real,
+    A: PlanningAlgorithm<N> + ?Sized,
+{
+    fn plan(
+        &self,
+        start: &[N],
+        goal: &[N],
+        is_feasible: &Fn(&[N]) -> bool,
+        sample: &Fn() -> Vec<N>,
+        budget: &PlanningBudget<N>,
+    ) -> Result<Vec<Vec<N>>> {
+        (**self).plan(start, goal, is_feasible, sample, budget)
+    }
+    fn update_obstacles(
+        &self,
+        obstacles: &CollisionObjects<N>,
+        swept_bounds: &Fn(&[N], &[N]) -> Vec<AABB<N>>,
+    ) {
+        (**self).update_obstacles(obstacles, swept_bounds)
+    }
+    fn update_joints(&self, joint_names: &[String], fixed_joint_positions: &[N]) -> Result<()> {
+        (**self).update_joints(joint_names, fixed_joint_positions)
+    }
+    fn invalidate(&self) {
+        (**self).invalidate()
+    }
+}
+
+/// RRT-Connect (`rrt::dual_rrt_connect`), the default algorithm of `JointPathPlanner`
+///
+/// The search stops with `Error::Timeout` at the deadline of the budget.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RrtConnect;
+
+impl<N> PlanningAlgorithm<N> for RrtConnect
+where
+    N: Real + Float,
+{
+    fn plan(
+        &self,
+        start: &[N],
+        goal: &[N],
+        is_feasible: &Fn(&[N]) -> bool,
+        sample: &Fn() -> Vec<N>,
+        budget: &PlanningBudget<N>,
+    ) -> Result<Vec<Vec<N>>> {
+        // all the joint positions are infeasible after the deadline, so the search fails soon
+        let deadline = budget.deadline();
+        let is_timed_out = || deadline.map_or(false, |d| Instant::now() >= d);
+        rrt::dual_rrt_connect(
+            start,
+            goal,
+            |angles: &[N]| !is_timed_out() && is_feasible(angles),
+            sample,
+            budget.step_length,
+            budget.max_try,
+        )
+        .map_err(|error| {
+            if is_timed_out() {
+                Error::Timeout {
+                    error: "RRT-Connect timed out".to_owned(),
+                }
+            } else {
+                Error::from(error)
+            }
+        })
+    }
+}
+
+/// Cost of the motion between joint positions, which is minimized by the optimal planners
+pub trait PathCost<N>: fmt::Debug + Send + Sync {
+    /// Cost of the straight motion from `from` to `to`
+    ///
+    /// It must satisfy the triangle inequality, because the cost of the straight motion is
+    /// used as the lower bound of the cost of any path.
+    fn cost(&self, from: &[N], to: &[N]) -> N;
+}
+
+/// Euclidean distance in the joint space, the default cost
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JointDistance;
+
+impl<N> PathCost<N> for JointDistance
+where
+    N: Real,
+{
+    fn cost(&self, from: &[N], to: &[N]) -> N {
+        from.iter()
+            .zip(to)
+            .fold(N::zero(), |sum, (a, b)| sum + (*b - *a) * (*b - *a))
+            .sqrt()
+    }
+}
+
+/// Euclidean distance in the joint space scaled by the weight of each joint
+///
+/// The weight is one if it is not given, e.g. `[2.0, 2.0]` prefers the motion of the joints
+/// except the first two.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedJointDistance<N> {
+    pub weights: Vec<N>,
+}
+
+impl<N> PathCost<N> for WeightedJointDistance<N>
+where
+    N: Real,
+{
+    fn cost(&self, from: &[N], to: &[N]) -> N {
+        from.iter()
+            .zip(to)
+            .enumerate()
+            .fold(N::zero(), |sum, (i, (a, b))| {
+                let d = (*b - *a) * self.weights.get(i).cloned().unwrap_or_else(N::one);
+                sum + d * d
+            })
+            .sqrt()
+    }
+}
+
+/// Total cost of the path
+pub fn path_cost<N, C>(cost: &C, path: &[Vec<N>]) -> N
+where
+    N: Real,
+    C: PathCost<N> + ?Sized,
+{
+    path.windows(2)
+        .fold(N::zero(), |sum, edge| sum + cost.cost(&edge[0], &edge[1]))
+}
+
+/// Euclidean distance in the joint space
+pub(crate) fn distance<N>(a: &[N], b: &[N]) -> N
+where
+    N: Real,
+{
+    JointDistance.cost(a, b)
+}
+
+/// Move from `from` toward `to` by `step_length` at most
+pub(crate) fn steer<N>(from: &[N], to: &[N], step_length: N) -> Vec<N>
+where
+    N: Real,
+{
+    let d = distance(from, to);
+    if d <= step_length {
+        return to.to_vec();
+    }
+    let t = step_length / d;
+    from.iter()
+        .zip(to)
+        .map(|(a, b)| *a + (*b - *a) * t)
+        .collect()
+}
+
+/// Number of the points which are checked by `is_edge_feasible`
+pub(crate) fn num_edge_checks<N>(from: &[N], to: &[N], step_length: N) -> usize
+where
+    N: Real + Float,
+{
+    Float::ceil(distance(from, to) / step_length)
+        .to_usize()
+        .unwrap_or(1)
+        .saturating_sub(1)
+}
+
+/// Check the points between `from` and `to` at intervals of `step_length`
+///
+/// Both ends are not checked, they are checked when they are added to the tree or the roadmap.
+pub(crate) fn is_edge_feasible<N>(
+    from: &[N],
+    to: &[N],
+    step_length: N,
+    is_feasible: &Fn(&[N]) -> bool,
+) -> bool
+where
+    N: Real + Float,
+{
+    let num = num_edge_checks(from, to, step_length) + 1;
+    (1..num).all(|i| {
+        let t: N = na::convert(i as f64 / num as f64);
+        let point = from
+            .iter()
+            .zip(to)
+            .map(|(a, b)| *a + (*b - *a) * t)
+            .collect::<Vec<_>>();
+        is_feasible(&point)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand;
+
+    #[test]
+    fn rrt_connect() {
+        // go around the circle at the origin whose radius is 0.5
+        let is_feasible = |p: &[f64]| p[0] * p[0] + p[1] * p[1] > 0.25;
+        let sample = || {
+            vec![
+                rand::random::<f64>() * 4.0 - 2.0,
+                rand::random::<f64>() * 4.0 - 2.0,
+            ]
+        };
+        let start = [-1.0, 0.0];
+        let goal = [1.0, 0.0];
+        let mut budget = PlanningBudget {
+            step_length: 0.1,
+            max_try: 3000,
+            timeout: None,
+        };
+        let path = RrtConnect
+            .plan(&start, &goal, &is_feasible, &sample, &budget)
+            .unwrap();
+        assert_eq!(path[0], start.to_vec());
+        assert_eq!(path.last().unwrap(), &goal.to_vec());
+        for p in &path {
+            assert!(is_feasible(p));
+        }
+        budget.timeout = Some(Duration::new(0, 0));
+        match RrtConnect.plan(&start, &goal, &is_feasible, &sample, &budget) {
+            Err(Error::Timeout { .. }) => {}
+            result => panic!("expected timeout but {:?}", result),
+        }
+    }
+}
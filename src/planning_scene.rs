@@ -0,0 +1,422 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{Isometry3, Real};
+use ncollide3d::bounding_volume::AABB;
+use ncollide3d::partitioning::{DBVTLeaf, DBVTLeafId, BVH, DBVT};
+use ncollide3d::query::visitors::BoundingVolumeInterferencesCollector;
+use ncollide3d::shape::{Compound, ShapeHandle};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use errors::*;
+
+/// Obstacles which can be checked by `CollisionChecker`
+///
+/// The index of the shape in `shapes()` is used as the obstacle index in the reports.
+pub trait CollisionObjects<T>
+where
+    T: Real,
+{
+    /// All the shapes of the obstacles and their poses in world frame
+    fn shapes(&self) -> &[(Isometry3<T>, ShapeHandle<T>)];
+    /// AABBs of the shapes in world frame, in the same order as `shapes()`
+    fn bounding_volumes(&self) -> &[AABB<T>];
+    /// Collect the indices of the shapes whose AABB intersects with `aabb`
+    ///
+    /// `indices` is cleared first.
+    fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>);
+    /// Padding of the obstacle
+    ///
+    /// `CollisionChecker::obstacle_paddings` is used if it returns `None`.
+    fn obstacle_padding(&self, _index: usize) -> Option<T> {
+        None
+    }
+    /// Name of the obstacle
+    fn obstacle_id(&self, _index: usize) -> Option<&str> {
+        None
+    }
+}
+
+impl<T> CollisionObjects<T> for Compound<T>
+where
+    T: Real,
+{
+    fn shapes(&self) -> &[(Isometry3<T>, ShapeHandle<T>)] {
+        Compound::shapes(self)
+    }
+    fn bounding_volumes(&self) -> &[AABB<T>] {
+        Compound::bounding_volumes(self)
+    }
+    fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>) {
+        indices.clear();
+        let mut visitor = BoundingVolumeInterferencesCollector::new(aabb, indices);
+        self.bvt().visit(&mut visitor);
+    }
+}
+
+/// Shapes in world frame with the dynamic bounding volume tree
+///
+/// Unlike `Compound`, the shapes can be added, moved and removed without rebuilding the whole
+/// tree. Only the leaves of the changed shapes are updated.
+pub(crate) struct DynamicCompound<T>
+where
+    T: Real,
+{
+    shapes: Vec<(Isometry3<T>, ShapeHandle<T>)>,
+    bounding_volumes: Vec<AABB<T>>,
+    /// Leaf of each shape in `tree`
+    leaves: Vec<DBVTLeafId>,
+    /// Key of each shape, which is the data of the leaf and does not change by the removal
+    keys: Vec<usize>,
+    /// Index of the shape for each key
+    indices: HashMap<usize, usize>,
+    next_key: usize,
+    tree: DBVT<T, usize, AABB<T>>,
+}
+
+impl<T> DynamicCompound<T>
+where
+    T: Real,
+{
+    pub fn new() -> Self {
+        DynamicCompound {
+            shapes: Vec::new(),
+            bounding_volumes: Vec::new(),
+            leaves: Vec::new(),
+            keys: Vec::new(),
+            indices: HashMap::new(),
+            next_key: 0,
+            tree: DBVT::new(),
+        }
+    }
+    pub fn shapes(&self) -> &[(Isometry3<T>, ShapeHandle<T>)] {
+        &self.shapes
+    }
+    pub fn bounding_volumes(&self) -> &[AABB<T>] {
+        &self.bounding_volumes
+    }
+    /// Add the shape to the end
+    pub fn push(&mut self, pose: Isometry3<T>, shape: ShapeHandle<T>) {
+        let aabb = shape.aabb(&pose);
+        let key = self.next_key;
+        self.next_key += 1;
+        self.leaves
+            .push(self.tree.insert(DBVTLeaf::new(aabb.clone(), key)));
+        self.indices.insert(key, self.shapes.len());
+        self.keys.push(key);
+        self.shapes.push((pose, shape));
+        self.bounding_volumes.push(aabb);
+    }
+    /// Move the shape of the index
+    pub fn set_pose(&mut self, index: usize, pose: Isometry3<T>) {
+        let aabb = self.shapes[index].1.aabb(&pose);
+        self.tree.remove(self.leaves[index]);
+        self.leaves[index] = self
+            .tree
+            .insert(DBVTLeaf::new(aabb.clone(), self.keys[index]));
+        self.shapes[index].0 = pose;
+        self.bounding_volumes[index] = aabb;
+    }
+    /// Remove the shapes in the range, the indices of the following shapes are shifted
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        for leaf in self.leaves.drain(range.clone()) {
+            self.tree.remove(leaf);
+        }
+        for key in self.keys.drain(range.clone()) {
+            self.indices.remove(&key);
+        }
+        self.shapes.drain(range.clone());
+        self.bounding_volumes.drain(range.clone());
+        for (i, key) in self.keys.iter().enumerate().skip(range.start) {
+            self.indices.insert(*key, i);
+        }
+    }
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+    pub fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>) {
+        indices.clear();
+        {
+            let mut visitor = BoundingVolumeInterferencesCollector::new(aabb, indices);
+            self.tree.visit(&mut visitor);
+        }
+        // the leaves have the keys
+        for index in indices.iter_mut() {
+            let key = *index;
+            *index = self.indices[&key];
+        }
+    }
+}
+
+/// Named collision object in `PlanningScene`
+#[derive(Clone)]
+pub struct CollisionObject<T>
+where
+    T: Real,
+{
+    /// Unique name of the object
+    pub id: String,
+    /// Shapes and their poses relative to `pose`
+    pub shapes: Vec<(Isometry3<T>, ShapeHandle<T>)>,
+    /// Pose of the object in world frame
+    pub pose: Isometry3<T>,
+    /// Padding of the object, `CollisionChecker::obstacle_paddings` is used if `None`
+    pub padding: Option<T>,
+}
+
+impl<T> CollisionObject<T>
+where
+    T: Real,
+{
+    /// Create the object which has one shape
+    pub fn new(id: &str, shape: ShapeHandle<T>, pose: Isometry3<T>) -> Self {
+        Self::with_shapes(id, vec![(Isometry3::identity(), shape)], pose)
+    }
+    /// Create the object which has several shapes
+    pub fn with_shapes(
+        id: &str,
+        shapes: Vec<(Isometry3<T>, ShapeHandle<T>)>,
+        pose: Isometry3<T>,
+    ) -> Self {
+        CollisionObject {
+            id: id.to_owned(),
+            shapes,
+            pose,
+            padding: None,
+        }
+    }
+}
+
+/// Mutable set of the named collision objects
+///
+/// The objects can be added, removed and moved by their ids. The shapes are kept in the
+/// dynamic bounding volume tree, so only the shapes of the changed object are updated.
+pub struct PlanningScene<T>
+where
+    T: Real,
+{
+    objects: Vec<CollisionObject<T>>,
+    shapes: DynamicCompound<T>,
+    /// Index of the owner object for each shape in `shapes`
+    shape_owners: Vec<usize>,
+}
+
+impl<T> Default for PlanningScene<T>
+where
+    T: Real,
+{
+    fn default() -> Self {
+        PlanningScene {
+            objects: Vec::new(),
+            shapes: DynamicCompound::new(),
+            shape_owners: Vec::new(),
+        }
+    }
+}
+
+impl<T> PlanningScene<T>
+where
+    T: Real,
+{
+    /// Create empty scene
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create scene from the objects
+    pub fn from_objects(objects: Vec<CollisionObject<T>>) -> Self {
+        let mut scene = Self::new();
+        for object in objects {
+            scene.add_object(object);
+        }
+        scene
+    }
+    /// Indices of the shapes of the object in `shapes`
+    fn shape_range(&self, object_index: usize) -> Range<usize> {
+        let start: usize = self.objects[..object_index]
+            .iter()
+            .map(|o| o.shapes.len())
+            .sum();
+        start..start + self.objects[object_index].shapes.len()
+    }
+    /// Add the object, the object which has the same id is replaced
+    pub fn add_object(&mut self, object: CollisionObject<T>) {
+        self.remove_object(&object.id);
+        for &(ref local_pose, ref shape) in &object.shapes {
+            self.shapes.push(object.pose * local_pose, shape.clone());
+            self.shape_owners.push(self.objects.len());
+        }
+        self.objects.push(object);
+    }
+    /// Remove the object and return it
+    pub fn remove_object(&mut self, id: &str) -> Option<CollisionObject<T>> {
+        let index = self.objects.iter().position(|o| o.id == id)?;
+        let range = self.shape_range(index);
+        self.shapes.remove_range(range.clone());
+        self.shape_owners.drain(range.clone());
+        for owner in &mut self.shape_owners[range.start..] {
+            *owner -= 1;
+        }
+        Some(self.objects.remove(index))
+    }
+    /// Set the pose of the object
+    pub fn move_object(&mut self, id: &str, pose: Isometry3<T>) -> Result<()> {
+        let index = self
+            .objects
+            .iter()
+            .position(|o| o.id == id)
+            .ok_or_else(|| Error::from(format!("object {} not found", id)))?;
+        let range = self.shape_range(index);
+        let object = &mut self.objects[index];
+        object.pose = pose;
+        for (i, &(ref local_pose, _)) in range.zip(object.shapes.iter()) {
+            self.shapes.set_pose(i, pose * local_pose);
+        }
+        Ok(())
+    }
+    /// Set the padding of the object
+    pub fn set_object_padding(&mut self, id: &str, padding: Option<T>) -> Result<()> {
+        self.object_mut(id)?.padding = padding;
+        Ok(())
+    }
+    fn object_mut(&mut self, id: &str) -> Result<&mut CollisionObject<T>> {
+        self.objects
+            .iter_mut()
+            .find(|o| o.id == id)
+            .ok_or_else(|| Error::from(format!("object {} not found", id)))
+    }
+    /// Find the object by id
+    pub fn object(&self, id: &str) -> Option<&CollisionObject<T>> {
+        self.objects.iter().find(|o| o.id == id)
+    }
+    /// All the objects
+    pub fn objects(&self) -> &[CollisionObject<T>] {
+        &self.objects
+    }
+    /// Ids of all the objects
+    pub fn object_ids(&self) -> Vec<String> {
+        self.objects.iter().map(|o| o.id.to_owned()).collect()
+    }
+    /// Remove all the objects
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.shapes.clear();
+        self.shape_owners.clear();
+    }
+    fn owner(&self, index: usize) -> Option<&CollisionObject<T>> {
+        self.shape_owners.get(index).map(|i| &self.objects[*i])
+    }
+}
+
+impl<T> CollisionObjects<T> for PlanningScene<T>
+where
+    T: Real,
+{
+    fn shapes(&self) -> &[(Isometry3<T>, ShapeHandle<T>)] {
+        self.shapes.shapes()
+    }
+    fn bounding_volumes(&self) -> &[AABB<T>] {
+        self.shapes.bounding_volumes()
+    }
+    fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>) {
+        self.shapes.interferences_with_aabb(aabb, indices)
+    }
+    fn obstacle_padding(&self, index: usize) -> Option<T> {
+        self.owner(index).and_then(|o| o.padding)
+    }
+    fn obstacle_id(&self, index: usize) -> Option<&str> {
+        self.owner(index).map(|o| o.id.as_str())
+    }
+}
+
+impl<T> From<Compound<T>> for PlanningScene<T>
+where
+    T: Real,
+{
+    /// The index of each shape is used as the id of the object
+    fn from(compound: Compound<T>) -> Self {
+        Self::from_objects(
+            compound
+                .shapes()
+                .iter()
+                .enumerate()
+                .map(|(i, &(ref pose, ref shape))| {
+                    CollisionObject::new(&i.to_string(), shape.clone(), *pose)
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::{self, Vector3};
+    use ncollide3d::shape::Cuboid;
+
+    #[test]
+    fn planning_scene() {
+        let shape = ShapeHandle::new(Cuboid::new(Vector3::new(0.1, 0.1, 0.1)));
+        let mut scene = PlanningScene::new();
+        scene.add_object(CollisionObject::new(
+            "box1",
+            shape.clone(),
+            Isometry3::new(Vector3::new(1.0, 0.0, 0.0), na::zero()),
+        ));
+        scene.add_object(CollisionObject::with_shapes(
+            "box2",
+            vec![
+                (Isometry3::identity(), shape.clone()),
+                (
+                    Isometry3::new(Vector3::new(0.0, 0.0, 0.5), na::zero()),
+                    shape.clone(),
+                ),
+            ],
+            Isometry3::new(Vector3::new(0.0, 1.0, 0.0), na::zero()),
+        ));
+        assert_eq!(scene.shapes().len(), 3);
+        assert_eq!(scene.obstacle_id(2), Some("box2"));
+        let origin = AABB::new(
+            na::Point3::new(-0.05, -0.05, -0.05),
+            na::Point3::new(0.05, 0.05, 0.05),
+        );
+        let mut indices = Vec::new();
+        scene.interferences_with_aabb(&origin, &mut indices);
+        assert!(indices.is_empty());
+        scene.move_object("box2", Isometry3::identity()).unwrap();
+        assert!((scene.shapes()[2].0.translation.vector.z - 0.5).abs() < 1e-6);
+        assert!((scene.bounding_volumes()[2].mins().z - 0.4).abs() < 1e-6);
+        scene.interferences_with_aabb(&origin, &mut indices);
+        assert_eq!(indices, vec![1]);
+        assert!(scene.move_object("box3", Isometry3::identity()).is_err());
+        scene.set_object_padding("box1", Some(0.1)).unwrap();
+        assert_eq!(scene.obstacle_padding(0), Some(0.1));
+        assert!(scene.remove_object("box1").is_some());
+        assert_eq!(scene.shapes().len(), 2);
+        assert_eq!(scene.obstacle_id(0), Some("box2"));
+        assert_eq!(scene.object_ids(), vec!["box2"]);
+        // the indices are shifted by the removal
+        scene.interferences_with_aabb(&origin, &mut indices);
+        assert_eq!(indices, vec![0]);
+        scene.add_object(CollisionObject::new(
+            "box2",
+            shape,
+            Isometry3::new(Vector3::new(0.0, 0.0, 0.5), na::zero()),
+        ));
+        assert_eq!(scene.shapes().len(), 1);
+        scene.interferences_with_aabb(&origin, &mut indices);
+        assert!(indices.is_empty());
+    }
+}
@@ -0,0 +1,650 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Isometry3, Point3, Real, Vector3};
+use ncollide3d::bounding_volume::AABB;
+use ncollide3d::shape::{Cuboid, ShapeHandle};
+use num_traits::Float;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use errors::*;
+use planning_scene::{CollisionObject, CollisionObjects, DynamicCompound};
+
+/// Points measured by a depth sensor
+#[derive(Debug, Clone, Default)]
+pub struct PointCloud<T>
+where
+    T: Real,
+{
+    pub points: Vec<Point3<T>>,
+}
+
+impl<T> PointCloud<T>
+where
+    T: Real,
+{
+    pub fn new(points: Vec<Point3<T>>) -> Self {
+        PointCloud { points }
+    }
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+    /// Transform all the points, e.g. from the camera frame to the world frame
+    pub fn transform(&mut self, pose: &Isometry3<T>) {
+        for point in &mut self.points {
+            *point = pose * *point;
+        }
+    }
+}
+
+fn point_cloud_error<S>(error: S) -> Error
+where
+    S: Into<String>,
+{
+    Error::PointCloud {
+        error: error.into(),
+    }
+}
+
+/// Scalar types of the fields of PCD and PLY
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn from_pcd(type_name: &str, size: usize) -> Result<Self> {
+        Ok(match (type_name, size) {
+            ("I", 1) => Scalar::I8,
+            ("U", 1) => Scalar::U8,
+            ("I", 2) => Scalar::I16,
+            ("U", 2) => Scalar::U16,
+            ("I", 4) => Scalar::I32,
+            ("U", 4) => Scalar::U32,
+            ("F", 4) => Scalar::F32,
+            ("F", 8) => Scalar::F64,
+            _ => {
+                return Err(point_cloud_error(format!(
+                    "unsupported PCD type {} with size {}",
+                    type_name, size
+                )))
+            }
+        })
+    }
+    fn from_ply(type_name: &str) -> Result<Self> {
+        Ok(match type_name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(point_cloud_error(format!("unknown PLY type {}", type_name))),
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+    /// Decode the value from the head of `bytes`
+    fn decode(self, bytes: &[u8], little_endian: bool) -> f64 {
+        let mut bits: u64 = 0;
+        for i in 0..self.size() {
+            let byte = if little_endian {
+                bytes[self.size() - 1 - i]
+            } else {
+                bytes[i]
+            };
+            bits = (bits << 8) | u64::from(byte);
+        }
+        match self {
+            Scalar::I8 => f64::from(bits as u8 as i8),
+            Scalar::U8 => f64::from(bits as u8),
+            Scalar::I16 => f64::from(bits as u16 as i16),
+            Scalar::U16 => f64::from(bits as u16),
+            Scalar::I32 => f64::from(bits as u32 as i32),
+            Scalar::U32 => f64::from(bits as u32),
+            Scalar::F32 => f64::from(f32::from_bits(bits as u32)),
+            Scalar::F64 => f64::from_bits(bits),
+        }
+    }
+}
+
+fn parse_f64(value: &str) -> Result<f64> {
+    value
+        .parse::<f64>()
+        .map_err(|_| point_cloud_error(format!("invalid number {}", value)))
+}
+
+fn parse_usize(value: &str) -> Result<usize> {
+    value
+        .parse::<usize>()
+        .map_err(|_| point_cloud_error(format!("invalid number {}", value)))
+}
+
+/// Read the next line, returns `None` at EOF
+fn read_header_line<R>(reader: &mut R) -> Result<Option<String>>
+where
+    R: BufRead,
+{
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_owned()))
+}
+
+/// Push the point if it is finite (organized clouds contain NaN for the invalid pixels)
+fn push_point<T>(points: &mut Vec<Point3<T>>, x: f64, y: f64, z: f64)
+where
+    T: Real,
+{
+    if x.is_finite() && y.is_finite() && z.is_finite() {
+        points.push(Point3::new(na::convert(x), na::convert(y), na::convert(z)));
+    }
+}
+
+/// Read PCD (Point Cloud Library format) from `BufRead`
+///
+/// `ascii` and `binary` data are supported, `binary_compressed` is not.
+/// Only the `x`, `y` and `z` fields are used.
+pub fn read_pcd<T, R>(mut reader: R) -> Result<PointCloud<T>>
+where
+    T: Real,
+    R: BufRead,
+{
+    let mut fields = Vec::new();
+    let mut sizes = Vec::new();
+    let mut types = Vec::new();
+    let mut counts = Vec::new();
+    let mut num_points = None;
+    let mut data = None;
+    while let Some(line) = read_header_line(&mut reader)? {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let key = words.next().unwrap_or_default().to_uppercase();
+        let values = words.collect::<Vec<_>>();
+        match key.as_str() {
+            "FIELDS" => fields = values.iter().map(|v| v.to_string()).collect(),
+            "SIZE" => {
+                sizes = values
+                    .iter()
+                    .map(|v| parse_usize(v))
+                    .collect::<Result<_>>()?
+            }
+            "TYPE" => types = values.iter().map(|v| v.to_string()).collect(),
+            "COUNT" => {
+                counts = values
+                    .iter()
+                    .map(|v| parse_usize(v))
+                    .collect::<Result<_>>()?
+            }
+            "POINTS" => num_points = Some(parse_usize(values.get(0).unwrap_or(&""))?),
+            "DATA" => {
+                data = values.get(0).map(|v| v.to_string());
+                break;
+            }
+            _ => {}
+        }
+    }
+    let data = data.ok_or_else(|| point_cloud_error("PCD has no DATA"))?;
+    let num_points = num_points.ok_or_else(|| point_cloud_error("PCD has no POINTS"))?;
+    if sizes.len() != fields.len() || types.len() != fields.len() {
+        return Err(point_cloud_error("FIELDS, SIZE and TYPE mismatch"));
+    }
+    if counts.is_empty() {
+        counts = vec![1; fields.len()];
+    } else if counts.len() != fields.len() {
+        return Err(point_cloud_error("FIELDS and COUNT mismatch"));
+    }
+    // offset in the values (ascii) or in the bytes (binary) for each field
+    let mut value_offsets = Vec::new();
+    let mut byte_offsets = Vec::new();
+    let mut scalars = Vec::new();
+    let (mut value_offset, mut byte_offset) = (0, 0);
+    for i in 0..fields.len() {
+        let scalar = Scalar::from_pcd(&types[i], sizes[i])?;
+        value_offsets.push(value_offset);
+        byte_offsets.push(byte_offset);
+        scalars.push(scalar);
+        value_offset += counts[i];
+        byte_offset += counts[i] * scalar.size();
+    }
+    let index_of = |name: &str| {
+        fields
+            .iter()
+            .position(|f| f == name)
+            .ok_or_else(|| point_cloud_error(format!("PCD has no {} field", name)))
+    };
+    let xyz = [index_of("x")?, index_of("y")?, index_of("z")?];
+
+    let mut points = Vec::with_capacity(num_points);
+    match data.as_str() {
+        "ascii" => {
+            for line in reader.lines().take(num_points) {
+                let line = line?;
+                let values = line.split_whitespace().collect::<Vec<_>>();
+                let value = |i: usize| {
+                    values
+                        .get(value_offsets[i])
+                        .ok_or_else(|| point_cloud_error(format!("too short line: {}", line)))
+                        .and_then(|v| parse_f64(v))
+                };
+                push_point(&mut points, value(xyz[0])?, value(xyz[1])?, value(xyz[2])?);
+            }
+        }
+        "binary" => {
+            let mut record = vec![0u8; byte_offset];
+            for _ in 0..num_points {
+                reader.read_exact(&mut record)?;
+                let value = |i: usize| scalars[i].decode(&record[byte_offsets[i]..], true);
+                push_point(&mut points, value(xyz[0]), value(xyz[1]), value(xyz[2]));
+            }
+        }
+        other => {
+            return Err(point_cloud_error(format!("unsupported PCD data {}", other)));
+        }
+    }
+    Ok(PointCloud::new(points))
+}
+
+/// Read PCD file
+pub fn read_pcd_file<T, P>(path: P) -> Result<PointCloud<T>>
+where
+    T: Real,
+    P: AsRef<Path>,
+{
+    read_pcd(BufReader::new(File::open(path)?))
+}
+
+/// Read PLY (Polygon File Format) from `BufRead`
+///
+/// `ascii`, `binary_little_endian` and `binary_big_endian` are supported.
+/// Only the `x`, `y` and `z` properties of the `vertex` element are used, so the faces are
+/// ignored. The `vertex` element must be the first element.
+pub fn read_ply<T, R>(mut reader: R) -> Result<PointCloud<T>>
+where
+    T: Real,
+    R: BufRead,
+{
+    match read_header_line(&mut reader)? {
+        Some(ref magic) if magic == "ply" => {}
+        _ => return Err(point_cloud_error("not a PLY file")),
+    }
+    let mut format = None;
+    let mut num_vertices = None;
+    let mut properties = Vec::new();
+    let mut is_first_element = true;
+    let mut in_vertex = false;
+    loop {
+        let line = read_header_line(&mut reader)?
+            .ok_or_else(|| point_cloud_error("PLY has no end_header"))?;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.get(0).cloned() {
+            Some("format") => format = words.get(1).map(|f| f.to_string()),
+            Some("element") => {
+                in_vertex = words.get(1) == Some(&"vertex");
+                if in_vertex {
+                    if !is_first_element {
+                        return Err(point_cloud_error("vertex must be the first element"));
+                    }
+                    num_vertices = Some(parse_usize(words.get(2).unwrap_or(&""))?);
+                }
+                is_first_element = false;
+            }
+            Some("property") if in_vertex => {
+                if words.get(1) == Some(&"list") {
+                    return Err(point_cloud_error(
+                        "list property of vertex is not supported",
+                    ));
+                }
+                if words.len() < 3 {
+                    return Err(point_cloud_error(format!("invalid property: {}", line)));
+                }
+                properties.push((words[2].to_owned(), Scalar::from_ply(words[1])?));
+            }
+            Some("end_header") => break,
+            _ => {}
+        }
+    }
+    let num_vertices = num_vertices.ok_or_else(|| point_cloud_error("PLY has no vertex"))?;
+    let index_of = |name: &str| {
+        properties
+            .iter()
+            .position(|p| p.0 == name)
+            .ok_or_else(|| point_cloud_error(format!("PLY has no {} property", name)))
+    };
+    let xyz = [index_of("x")?, index_of("y")?, index_of("z")?];
+
+    let mut points = Vec::with_capacity(num_vertices);
+    let format = format.ok_or_else(|| point_cloud_error("PLY has no format"))?;
+    match format.as_str() {
+        "ascii" => {
+            for line in reader.lines().take(num_vertices) {
+                let line = line?;
+                let values = line.split_whitespace().collect::<Vec<_>>();
+                let value = |i: usize| {
+                    values
+                        .get(i)
+                        .ok_or_else(|| point_cloud_error(format!("too short line: {}", line)))
+                        .and_then(|v| parse_f64(v))
+                };
+                push_point(&mut points, value(xyz[0])?, value(xyz[1])?, value(xyz[2])?);
+            }
+        }
+        "binary_little_endian" | "binary_big_endian" => {
+            let little_endian = format == "binary_little_endian";
+            let mut offsets = Vec::new();
+            let mut record_size = 0;
+            for &(_, scalar) in &properties {
+                offsets.push(record_size);
+                record_size += scalar.size();
+            }
+            let mut record = vec![0u8; record_size];
+            for _ in 0..num_vertices {
+                reader.read_exact(&mut record)?;
+                let value = |i: usize| properties[i].1.decode(&record[offsets[i]..], little_endian);
+                push_point(&mut points, value(xyz[0]), value(xyz[1]), value(xyz[2]));
+            }
+        }
+        other => {
+            return Err(point_cloud_error(format!(
+                "unsupported PLY format {}",
+                other
+            )));
+        }
+    }
+    Ok(PointCloud::new(points))
+}
+
+/// Read PLY file
+pub fn read_ply_file<T, P>(path: P) -> Result<PointCloud<T>>
+where
+    T: Real,
+    P: AsRef<Path>,
+{
+    read_ply(BufReader::new(File::open(path)?))
+}
+
+/// Occupancy grid of the voxels which contain any points
+///
+/// Each occupied voxel is a cube obstacle whose edge length is `resolution`. The voxels
+/// are aligned to the world origin, so the grid can be updated by new clouds. Only the
+/// newly occupied voxels are added to the bounding volume tree.
+pub struct VoxelGrid<T>
+where
+    T: Real,
+{
+    resolution: T,
+    voxels: BTreeSet<(i64, i64, i64)>,
+    cube: ShapeHandle<T>,
+    shapes: DynamicCompound<T>,
+}
+
+impl<T> VoxelGrid<T>
+where
+    T: Real + Float,
+{
+    /// Create empty grid, returns error if `resolution` is not positive
+    pub fn new(resolution: T) -> Result<Self> {
+        if Float::is_nan(resolution) || resolution <= na::zero() {
+            return Err(point_cloud_error(format!(
+                "resolution must be positive, but {:?}",
+                resolution
+            )));
+        }
+        let half = resolution * na::convert(0.5);
+        Ok(VoxelGrid {
+            resolution,
+            voxels: BTreeSet::new(),
+            cube: ShapeHandle::new(Cuboid::new(Vector3::new(half, half, half))),
+            shapes: DynamicCompound::new(),
+        })
+    }
+    /// Create the grid which contains the points of the cloud
+    pub fn from_point_cloud(cloud: &PointCloud<T>, resolution: T) -> Result<Self> {
+        let mut grid = Self::new(resolution)?;
+        grid.insert_points(&cloud.points);
+        Ok(grid)
+    }
+    pub fn resolution(&self) -> T {
+        self.resolution
+    }
+    /// Number of the occupied voxels
+    pub fn len(&self) -> usize {
+        self.voxels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.voxels.is_empty()
+    }
+    fn voxel_index(&self, point: &Point3<T>) -> (i64, i64, i64) {
+        let index = |v: T| Float::floor(v / self.resolution).to_i64().unwrap_or(0);
+        (index(point.x), index(point.y), index(point.z))
+    }
+    fn voxel_center(&self, index: &(i64, i64, i64)) -> Point3<T> {
+        let center =
+            |i: i64| (na::convert::<f64, T>(i as f64) + na::convert(0.5)) * self.resolution;
+        Point3::new(center(index.0), center(index.1), center(index.2))
+    }
+    /// Mark the voxels which contain the points as occupied
+    pub fn insert_points(&mut self, points: &[Point3<T>]) {
+        for point in points {
+            let index = self.voxel_index(point);
+            if self.voxels.insert(index) {
+                let center = self.voxel_center(&index);
+                self.shapes
+                    .push(Isometry3::new(center.coords, na::zero()), self.cube.clone());
+            }
+        }
+    }
+    /// Check if the voxel which contains the point is occupied
+    pub fn is_occupied(&self, point: &Point3<T>) -> bool {
+        self.voxels.contains(&self.voxel_index(point))
+    }
+    /// Centers of all the occupied voxels
+    pub fn voxel_centers(&self) -> Vec<Point3<T>> {
+        self.voxels.iter().map(|i| self.voxel_center(i)).collect()
+    }
+    /// Remove all the voxels
+    pub fn clear(&mut self) {
+        self.voxels.clear();
+        self.shapes.clear();
+    }
+    /// Create the object to add the voxels to `PlanningScene` with the other obstacles
+    pub fn to_collision_object(&self, id: &str) -> CollisionObject<T> {
+        CollisionObject::with_shapes(
+            id,
+            self.shapes
+                .shapes()
+                .iter()
+                .map(|&(ref pose, ref shape)| (*pose, shape.clone()))
+                .collect(),
+            Isometry3::identity(),
+        )
+    }
+}
+
+impl<T> CollisionObjects<T> for VoxelGrid<T>
+where
+    T: Real,
+{
+    fn shapes(&self) -> &[(Isometry3<T>, ShapeHandle<T>)] {
+        self.shapes.shapes()
+    }
+    fn bounding_volumes(&self) -> &[AABB<T>] {
+        self.shapes.bounding_volumes()
+    }
+    fn interferences_with_aabb(&self, aabb: &AABB<T>, indices: &mut Vec<usize>) {
+        self.shapes.interferences_with_aabb(aabb, indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collision_checker::{CollisionChecker, FromUrdf};
+    use k;
+    use planning_scene::PlanningScene;
+    use urdf_rs;
+
+    #[test]
+    fn read_point_clouds() {
+        let pcd = read_pcd::<f64, _>(
+            "# .PCD v0.7
+VERSION 0.7
+FIELDS x y z rgb
+SIZE 4 4 4 4
+TYPE F F F F
+COUNT 1 1 1 1
+WIDTH 3
+HEIGHT 1
+POINTS 3
+DATA ascii
+0.1 0.2 0.3 4.2108e+06
+nan nan nan 0
+1.0 -1.0 0.5 4.2108e+06
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(pcd.len(), 2);
+        assert!((pcd.points[1].y - (-1.0)).abs() < 1e-6);
+
+        let mut binary = b"VERSION 0.7\nFIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nCOUNT 1 1 1\n\
+WIDTH 1\nHEIGHT 1\nPOINTS 1\nDATA binary\n"
+            .to_vec();
+        for v in &[0.5f32, -0.25, 2.0] {
+            let bits = v.to_bits();
+            binary.extend((0..4).map(|i| (bits >> (8 * i)) as u8));
+        }
+        let pcd = read_pcd::<f32, _>(binary.as_slice()).unwrap();
+        assert_eq!(pcd.points, vec![Point3::new(0.5, -0.25, 2.0)]);
+
+        let ply = read_ply::<f64, _>(
+            "ply
+format ascii 1.0
+element vertex 2
+property float x
+property float y
+property float z
+property uchar red
+element face 0
+property list uchar int vertex_indices
+end_header
+0 0 1 255
+1 2 3 0
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(ply.points[1], Point3::new(1.0, 2.0, 3.0));
+
+        let mut binary = b"ply\nformat binary_big_endian 1.0\nelement vertex 1\n\
+property double x\nproperty double y\nproperty double z\nend_header\n"
+            .to_vec();
+        for v in &[1.5f64, 0.0, -3.0] {
+            let bits = v.to_bits();
+            binary.extend((0..8).rev().map(|i| (bits >> (8 * i)) as u8));
+        }
+        let ply = read_ply::<f64, _>(binary.as_slice()).unwrap();
+        assert_eq!(ply.points, vec![Point3::new(1.5, 0.0, -3.0)]);
+        assert!(read_ply::<f64, _>("pcd".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn voxel_grid() {
+        let cloud = PointCloud::<f64>::new(vec![
+            Point3::new(0.01, 0.01, 0.01),
+            Point3::new(0.02, 0.03, 0.04),
+            Point3::new(-0.01, 0.01, 0.01),
+            Point3::new(1.0, 1.0, 1.0),
+        ]);
+        let mut grid = VoxelGrid::from_point_cloud(&cloud, 0.05).unwrap();
+        assert_eq!(grid.len(), 3);
+        assert_eq!(grid.shapes().len(), 3);
+        assert!(grid.is_occupied(&Point3::new(0.04, 0.0, 0.0)));
+        assert!(!grid.is_occupied(&Point3::new(0.06, 0.0, 0.0)));
+        assert!((grid.voxel_centers()[0].x - (-0.025)).abs() < 1e-6);
+        assert_eq!(grid.to_collision_object("cloud").shapes.len(), 3);
+        // only the new voxel is added
+        grid.insert_points(&[Point3::new(0.01, 0.01, 0.01), Point3::new(0.32, 0.0, 0.0)]);
+        assert_eq!(grid.shapes().len(), 4);
+        let mut indices = Vec::new();
+        grid.interferences_with_aabb(
+            &AABB::new(Point3::new(0.31, 0.01, 0.01), Point3::new(0.32, 0.02, 0.02)),
+            &mut indices,
+        );
+        assert_eq!(indices, vec![3]);
+        grid.clear();
+        assert!(grid.is_empty());
+        assert!(grid.shapes().is_empty());
+        assert!(VoxelGrid::<f64>::new(0.0).is_err());
+        assert!(VoxelGrid::<f64>::new(::std::f64::NAN).is_err());
+    }
+
+    #[test]
+    fn point_cloud_obstacle() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let checker = CollisionChecker::<f64>::from_urdf_robot(&urdf_robot, 0.0);
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        // points on the surface of the wrist link, and a wall in front of the robot
+        let mut points = vec![Point3::new(0.6, 0.4, 0.55), Point3::new(0.6, 0.4, 0.45)];
+        for i in 0..10 {
+            for j in 0..10 {
+                points.push(Point3::new(1.5, -0.5 + 0.1 * i as f64, 0.1 * j as f64));
+            }
+        }
+        let cloud = PointCloud::new(checker.filter_robot_points(&robot, &points, 0.02));
+        assert_eq!(cloud.len(), 100);
+        let grid = VoxelGrid::from_point_cloud(&cloud, 0.05).unwrap();
+        assert!(!checker.has_any_colliding_with_objects(&robot, &grid));
+
+        let mut scene = PlanningScene::from_urdf_file("obstacles.urdf").unwrap();
+        scene.add_object(grid.to_collision_object("cloud"));
+        assert!(!checker.has_any_colliding_with_objects(&robot, &scene));
+        let near = PointCloud::new(vec![Point3::new(0.6, 0.4, 0.52)]);
+        scene.add_object(
+            VoxelGrid::from_point_cloud(&near, 0.05)
+                .unwrap()
+                .to_collision_object("cloud"),
+        );
+        let contacts = checker.contacts(&robot, &scene);
+        assert!(!contacts.is_empty());
+        assert!(contacts
+            .iter()
+            .all(|c| c.obstacle_id.as_deref() == Some("cloud")));
+    }
+}
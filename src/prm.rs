@@ -0,0 +1,1078 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Isometry3, Real};
+use ncollide3d::bounding_volume::{BoundingVolume, AABB};
+use ncollide3d::shape::Shape;
+use num_traits::Float;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use errors::*;
+use planning_algorithm::{
+    distance, is_edge_feasible, num_edge_checks, JointDistance, PathCost, PlanningAlgorithm,
+    PlanningBudget,
+};
+use planning_scene::CollisionObjects;
+
+const HEADER: &str = "gear roadmap 1";
+
+/// Result of the collision check of a node or an edge
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Validity {
+    /// Not checked yet, or the obstacles are changed after the check
+    Unknown,
+    Valid,
+    Invalid,
+}
+
+impl From<bool> for Validity {
+    fn from(is_valid: bool) -> Self {
+        if is_valid {
+            Validity::Valid
+        } else {
+            Validity::Invalid
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RoadmapNode<N>
+where
+    N: Real,
+{
+    positions: Vec<N>,
+    validity: Validity,
+    /// Indices of the edges which are connected to this node
+    edges: Vec<usize>,
+    /// Bounds of the robot at this node, which are computed when the obstacles are changed
+    swept_bounds: Option<Vec<AABB<N>>>,
+}
+
+#[derive(Debug)]
+struct RoadmapEdge<N>
+where
+    N: Real,
+{
+    nodes: (usize, usize),
+    cost: N,
+    validity: Validity,
+    /// Bounds of the robot moving along this edge, which are computed when the obstacles are
+    /// changed
+    swept_bounds: Option<Vec<AABB<N>>>,
+}
+
+impl<N> RoadmapEdge<N>
+where
+    N: Real,
+{
+    fn other(&self, node: usize) -> usize {
+        if self.nodes.0 == node {
+            self.nodes.1
+        } else {
+            self.nodes.0
+        }
+    }
+}
+
+/// Item of the priority queue of A*, the smallest `priority` is popped first
+struct QueueItem<N> {
+    priority: N,
+    index: usize,
+}
+
+impl<N: PartialOrd> PartialEq for QueueItem<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<N: PartialOrd> Eq for QueueItem<N> {}
+
+impl<N: PartialOrd> PartialOrd for QueueItem<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N: PartialOrd> Ord for QueueItem<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .partial_cmp(&self.priority)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Number of the collision checks (calls of `is_feasible`) of `Prm`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollisionCheckStats {
+    /// Checks which are actually done
+    pub num_checks: usize,
+    /// Checks which are needed if all the nodes and the edges are checked eagerly
+    ///
+    /// It includes the checks of the nodes and the edges which are affected by the changes of
+    /// the obstacles and the checks of the edges to connect the start and the goal.
+    pub num_eager_checks: usize,
+}
+
+impl CollisionCheckStats {
+    /// Checks which are saved by the lazy checking
+    pub fn num_saved_checks(&self) -> usize {
+        self.num_eager_checks.saturating_sub(self.num_checks)
+    }
+}
+
+/// Id of the object (the index of the shape if the obstacles are not named) and the address
+/// of the shape
+type ObstacleKey = (String, usize);
+
+/// Poses, AABBs and paddings of the obstacle shapes
+type ObstacleSnapshot<N> = HashMap<ObstacleKey, Vec<(Isometry3<N>, AABB<N>, Option<N>)>>;
+
+fn obstacle_snapshot<N>(obstacles: &CollisionObjects<N>) -> ObstacleSnapshot<N>
+where
+    N: Real,
+{
+    let mut snapshot = HashMap::new();
+    for (i, (&(ref pose, ref shape), aabb)) in obstacles
+        .shapes()
+        .iter()
+        .zip(obstacles.bounding_volumes())
+        .enumerate()
+    {
+        let id = obstacles
+            .obstacle_id(i)
+            .map_or_else(|| i.to_string(), str::to_owned);
+        let address = &**shape as *const Shape<N> as *const u8 as usize;
+        snapshot
+            .entry((id, address))
+            .or_insert_with(Vec::new)
+            .push((*pose, aabb.clone(), obstacles.obstacle_padding(i)));
+    }
+    snapshot
+}
+
+/// AABBs of the obstacle shapes which are added, removed or moved (both before and after)
+fn changed_regions<N>(old: &ObstacleSnapshot<N>, new: &ObstacleSnapshot<N>) -> Vec<AABB<N>>
+where
+    N: Real,
+{
+    let mut regions = Vec::new();
+    for &(a, b) in &[(old, new), (new, old)] {
+        for (key, shapes) in a {
+            if b.get(key) != Some(shapes) {
+                regions.extend(shapes.iter().map(|s| s.1.clone()));
+            }
+        }
+    }
+    regions
+}
+
+#[derive(Debug)]
+struct Roadmap<N>
+where
+    N: Real,
+{
+    nodes: Vec<RoadmapNode<N>>,
+    edges: Vec<RoadmapEdge<N>>,
+    /// The obstacles which the roadmap is checked with, `None` if it is unknown
+    obstacles: Option<ObstacleSnapshot<N>>,
+    /// Names of the joints of the positions, `None` if it is unknown
+    joint_names: Option<Vec<String>>,
+    /// Positions of the other joints which the roadmap is checked with, `None` if it is unknown
+    fixed_joint_positions: Option<Vec<N>>,
+    stats: CollisionCheckStats,
+    /// The whole roadmap needs to be checked again by the eager checking
+    needs_eager_recheck: bool,
+    /// Step length of the last query, which the edges are checked with
+    step_length: Option<N>,
+}
+
+impl<N> Roadmap<N>
+where
+    N: Real + Float,
+{
+    fn new() -> Self {
+        Roadmap {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            obstacles: None,
+            joint_names: None,
+            fixed_joint_positions: None,
+            stats: CollisionCheckStats::default(),
+            needs_eager_recheck: false,
+            step_length: None,
+        }
+    }
+    fn add_node(&mut self, positions: Vec<N>, validity: Validity) -> usize {
+        self.nodes.push(RoadmapNode {
+            positions,
+            validity,
+            edges: Vec::new(),
+            swept_bounds: None,
+        });
+        self.nodes.len() - 1
+    }
+    fn add_edge(&mut self, a: usize, b: usize, cost: N, validity: Validity) {
+        let index = self.edges.len();
+        self.edges.push(RoadmapEdge {
+            nodes: (a, b),
+            cost,
+            validity,
+            swept_bounds: None,
+        });
+        self.nodes[a].edges.push(index);
+        self.nodes[b].edges.push(index);
+    }
+    fn is_connected(&self, a: usize, b: usize) -> bool {
+        self.nodes[a]
+            .edges
+            .iter()
+            .any(|&e| self.edges[e].other(a) == b)
+    }
+    /// The nearest `num` nodes in `0..end` which are not known to be invalid
+    fn nearest_nodes(&self, positions: &[N], end: usize, num: usize) -> Vec<usize> {
+        let mut nodes = (0..end)
+            .filter(|&i| self.nodes[i].validity != Validity::Invalid)
+            .map(|i| (i, distance(&self.nodes[i].positions, positions)))
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        nodes.into_iter().take(num).map(|(i, _)| i).collect()
+    }
+    /// Number of the collision checks to check all the nodes and the edges
+    fn num_eager_checks(&self, step_length: N) -> usize {
+        self.nodes.len()
+            + self
+                .edges
+                .iter()
+                .map(|e| {
+                    num_edge_checks(
+                        &self.nodes[e.nodes.0].positions,
+                        &self.nodes[e.nodes.1].positions,
+                        step_length,
+                    )
+                })
+                .sum::<usize>()
+    }
+    /// Forget the results of the collision checks and the bounds of the robot
+    fn invalidate(&mut self) {
+        self.needs_eager_recheck = true;
+        for node in &mut self.nodes {
+            node.validity = Validity::Unknown;
+            node.swept_bounds = None;
+        }
+        for edge in &mut self.edges {
+            edge.validity = Validity::Unknown;
+            edge.swept_bounds = None;
+        }
+    }
+    /// Forget the results of the collision checks of the nodes and the edges whose bounds
+    /// overlap with `regions`
+    ///
+    /// The bounds are computed by `swept_bounds` only for the checked nodes and edges, and
+    /// kept until `invalidate`. Returns the number of the checks to check them again.
+    fn invalidate_regions(
+        &mut self,
+        regions: &[AABB<N>],
+        swept_bounds: &Fn(&[N], &[N]) -> Vec<AABB<N>>,
+    ) -> usize {
+        let step_length = match self.step_length {
+            Some(step_length) => step_length,
+            // nothing is checked yet
+            None => return 0,
+        };
+        let overlaps = |bounds: &[AABB<N>]| {
+            bounds
+                .iter()
+                .any(|b| regions.iter().any(|region| b.intersects(region)))
+        };
+        let mut num_checks = 0;
+        for node in &mut self.nodes {
+            if node.validity == Validity::Unknown {
+                continue;
+            }
+            let positions = &node.positions;
+            if overlaps(
+                node.swept_bounds
+                    .get_or_insert_with(|| swept_bounds(positions, positions)),
+            ) {
+                node.validity = Validity::Unknown;
+                num_checks += 1;
+            }
+        }
+        let nodes = &self.nodes;
+        for edge in &mut self.edges {
+            if edge.validity == Validity::Unknown {
+                continue;
+            }
+            let from = &nodes[edge.nodes.0].positions;
+            let to = &nodes[edge.nodes.1].positions;
+            if overlaps(edge.swept_bounds.get_or_insert_with(|| swept_bounds(from, to))) {
+                edge.validity = Validity::Unknown;
+                num_checks += num_edge_checks(from, to, step_length);
+            }
+        }
+        num_checks
+    }
+    /// Number of the joints of the positions, `None` if the roadmap is empty
+    fn dof(&self) -> Option<usize> {
+        self.nodes.first().map(|node| node.positions.len())
+    }
+    /// Remove the nodes and the edges which are added after the sizes
+    fn truncate(&mut self, num_nodes: usize, num_edges: usize) {
+        self.nodes.truncate(num_nodes);
+        self.edges.truncate(num_edges);
+        for node in &mut self.nodes {
+            node.edges.retain(|&e| e < num_edges);
+        }
+    }
+    /// Find the cheapest path by A*, the nodes and the edges which are known to be invalid are
+    /// ignored
+    ///
+    /// The indices of the nodes and the edges on the path are returned.
+    fn shortest_path(
+        &self,
+        start: usize,
+        goal: usize,
+        cost: &PathCost<N>,
+    ) -> Option<(Vec<usize>, Vec<usize>)> {
+        let heuristic = |i: usize| cost.cost(&self.nodes[i].positions, &self.nodes[goal].positions);
+        let mut costs = vec![None; self.nodes.len()];
+        let mut parents = vec![None; self.nodes.len()];
+        let mut closed = vec![false; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+        costs[start] = Some(na::zero());
+        queue.push(QueueItem {
+            priority: heuristic(start),
+            index: start,
+        });
+        while let Some(QueueItem { index, .. }) = queue.pop() {
+            if index == goal {
+                let mut nodes = vec![goal];
+                let mut edges = Vec::new();
+                let mut current = goal;
+                while let Some((parent, edge)) = parents[current] {
+                    nodes.push(parent);
+                    edges.push(edge);
+                    current = parent;
+                }
+                nodes.reverse();
+                edges.reverse();
+                return Some((nodes, edges));
+            }
+            if closed[index] {
+                continue;
+            }
+            closed[index] = true;
+            let cost_to_come: N = costs[index].unwrap();
+            for &e in &self.nodes[index].edges {
+                let edge = &self.edges[e];
+                let next = edge.other(index);
+                if edge.validity == Validity::Invalid
+                    || self.nodes[next].validity == Validity::Invalid
+                    || closed[next]
+                {
+                    continue;
+                }
+                let next_cost = cost_to_come + edge.cost;
+                if costs[next].map_or(true, |c| next_cost < c) {
+                    costs[next] = Some(next_cost);
+                    parents[next] = Some((index, e));
+                    queue.push(QueueItem {
+                        priority: next_cost + heuristic(next),
+                        index: next,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Probabilistic roadmap for the multi-query planning in a static environment
+///
+/// The roadmap is built at the first `plan`, and the later queries connect the start and the
+/// goal to the roadmap and search the cheapest path in it. Share the planner by `Arc` to save
+/// or load the roadmap:
+///
+/// ```no_run
+/// # extern crate gear;
+/// # use std::sync::Arc;
+/// # fn main() {
+/// let prm = Arc::new(gear::Prm::<f64>::new());
+/// let planner = gear::JointPathPlannerBuilder::from_urdf_file("sample.urdf")
+///     .unwrap()
+///     .algorithm(prm.clone())
+///     .finalize();
+/// // ... plan with `planner`
+/// prm.save("roadmap.txt").unwrap();
+/// # }
+/// ```
+///
+/// When the obstacles are changed, the results of the collision checks of the nodes and the
+/// edges which the robot can touch the changed obstacles are discarded. The obstacles are
+/// compared by the ids of the objects and the shapes, so reuse the same `ShapeHandle` for
+/// the unchanged obstacles. When the positions of the other joints of the robot or the robot
+/// itself (e.g. the attached objects) are changed, all the results are discarded, but the
+/// nodes and the edges are kept. They are checked again only when they are
+/// on the path of a later query. The roadmap is for the joints of the first query, and the
+/// queries for the other joints fail until `clear` is called.
+///
+/// If `lazy` is true, it works as Lazy PRM: the roadmap is built without any collision check,
+/// and only the nodes and the edges on the candidate shortest paths are checked until a valid
+/// path is found. `collision_check_stats` reports how many checks are saved.
+#[derive(Debug)]
+pub struct Prm<N>
+where
+    N: Real,
+{
+    /// Number of the feasible nodes to build the roadmap
+    pub num_samples: usize,
+    /// Number of the nearest nodes to be connected with each node
+    pub num_neighbors: usize,
+    /// Build the roadmap without collision checks (Lazy PRM)
+    pub lazy: bool,
+    /// Cost of the edges, `JointDistance` by default
+    pub cost: Arc<PathCost<N>>,
+    roadmap: Mutex<Roadmap<N>>,
+}
+
+impl<N> Default for Prm<N>
+where
+    N: Real + Float,
+{
+    fn default() -> Self {
+        Prm {
+            num_samples: 1000,
+            num_neighbors: 10,
+            lazy: false,
+            cost: Arc::new(JointDistance),
+            roadmap: Mutex::new(Roadmap::new()),
+        }
+    }
+}
+
+impl<N> Prm<N>
+where
+    N: Real + Float,
+{
+    /// PRM with an empty roadmap
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn num_samples(mut self, num_samples: usize) -> Self {
+        self.num_samples = num_samples;
+        self
+    }
+    pub fn num_neighbors(mut self, num_neighbors: usize) -> Self {
+        self.num_neighbors = num_neighbors;
+        self
+    }
+    /// Check the nodes and the edges only when they are on the candidate paths
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+    /// Set the cost of the edges
+    pub fn cost<C>(mut self, cost: C) -> Self
+    where
+        C: PathCost<N> + 'static,
+    {
+        self.cost = Arc::new(cost);
+        self
+    }
+    fn lock(&self) -> Result<MutexGuard<Roadmap<N>>> {
+        self.roadmap
+            .lock()
+            .map_err(|_| Error::from("roadmap is poisoned".to_owned()))
+    }
+    /// Number of the nodes in the roadmap
+    pub fn num_nodes(&self) -> usize {
+        self.lock().map(|r| r.nodes.len()).unwrap_or(0)
+    }
+    /// Number of the edges in the roadmap
+    pub fn num_edges(&self) -> usize {
+        self.lock().map(|r| r.edges.len()).unwrap_or(0)
+    }
+    /// Number of the collision checks since the roadmap is created
+    pub fn collision_check_stats(&self) -> CollisionCheckStats {
+        self.lock().map(|r| r.stats).unwrap_or_default()
+    }
+    /// Remove the roadmap, it is built again at the next `plan`
+    pub fn clear(&self) {
+        if let Ok(mut roadmap) = self.lock() {
+            *roadmap = Roadmap::new();
+        }
+    }
+    /// Discard the results of the collision checks, e.g. after changing the robot model
+    ///
+    /// The changes of the obstacles and the joint positions are detected by
+    /// `JointPathPlanner::plan`, and `JointPathPlanner` calls this when the objects are
+    /// attached or the paddings are changed, so it is not needed for them.
+    pub fn invalidate(&self) {
+        if let Ok(mut roadmap) = self.lock() {
+            roadmap.invalidate();
+        }
+    }
+    /// Sample the nodes and connect them with their nearest nodes
+    ///
+    /// The infeasible samples and edges are marked unless `lazy` is true. The infeasible
+    /// samples are kept with their edges, which are not checked, so they can be used after the
+    /// obstacles are changed.
+    fn build(
+        &self,
+        roadmap: &mut Roadmap<N>,
+        is_feasible: &Fn(&[N]) -> bool,
+        sample: &Fn() -> Vec<N>,
+        budget: &PlanningBudget<N>,
+        deadline: Option<Instant>,
+    ) {
+        let mut num_feasible_nodes = roadmap
+            .nodes
+            .iter()
+            .filter(|node| node.validity != Validity::Invalid)
+            .count();
+        for _ in 0..budget.max_try {
+            if num_feasible_nodes >= self.num_samples
+                || deadline.map_or(false, |d| Instant::now() >= d)
+            {
+                break;
+            }
+            let positions = sample();
+            roadmap.stats.num_eager_checks += 1;
+            let node_validity = if self.lazy {
+                Validity::Unknown
+            } else {
+                Validity::from(is_feasible(&positions))
+            };
+            if node_validity != Validity::Invalid {
+                num_feasible_nodes += 1;
+            }
+            let index = roadmap.add_node(positions, node_validity);
+            let neighbors =
+                roadmap.nearest_nodes(&roadmap.nodes[index].positions, index, self.num_neighbors);
+            for neighbor in neighbors {
+                let (cost, validity, num_checks) = {
+                    let from = &roadmap.nodes[neighbor].positions;
+                    let to = &roadmap.nodes[index].positions;
+                    let validity = if self.lazy || node_validity == Validity::Invalid {
+                        Validity::Unknown
+                    } else {
+                        Validity::from(is_edge_feasible(from, to, budget.step_length, is_feasible))
+                    };
+                    (
+                        self.cost.cost(from, to),
+                        validity,
+                        num_edge_checks(from, to, budget.step_length),
+                    )
+                };
+                roadmap.stats.num_eager_checks += num_checks;
+                roadmap.add_edge(neighbor, index, cost, validity);
+            }
+        }
+        debug!(
+            "roadmap is built with {} nodes and {} edges",
+            roadmap.nodes.len(),
+            roadmap.edges.len()
+        );
+    }
+    /// Connect the node to the nearest nodes, the edges are checked later
+    fn connect(&self, roadmap: &mut Roadmap<N>, index: usize, step_length: N) {
+        roadmap.stats.num_eager_checks += 1;
+        let end = roadmap.nodes.len();
+        let neighbors =
+            roadmap.nearest_nodes(&roadmap.nodes[index].positions, end, self.num_neighbors + 1);
+        for neighbor in neighbors {
+            if neighbor != index && !roadmap.is_connected(neighbor, index) {
+                let (cost, num_checks) = {
+                    let from = &roadmap.nodes[neighbor].positions;
+                    let to = &roadmap.nodes[index].positions;
+                    (
+                        self.cost.cost(from, to),
+                        num_edge_checks(from, to, step_length),
+                    )
+                };
+                roadmap.stats.num_eager_checks += num_checks;
+                roadmap.add_edge(neighbor, index, cost, Validity::Unknown);
+            }
+        }
+    }
+    /// Search the path and check the unknown nodes and edges on it until a valid path is found
+    fn search(
+        &self,
+        roadmap: &mut Roadmap<N>,
+        start: usize,
+        goal: usize,
+        is_feasible: &Fn(&[N]) -> bool,
+        step_length: N,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<Vec<N>>> {
+        loop {
+            if deadline.map_or(false, |d| Instant::now() >= d) {
+                return Err(Error::Timeout {
+                    error: "PRM timed out".to_owned(),
+                });
+            }
+            let (nodes, edges) = roadmap
+                .shortest_path(start, goal, &*self.cost)
+                .ok_or_else(|| Error::from("no path is found in the roadmap".to_owned()))?;
+            // the nodes are checked first because they are cheaper than the edges
+            let mut is_valid = true;
+            for &i in &nodes {
+                if roadmap.nodes[i].validity == Validity::Unknown {
+                    is_valid = is_feasible(&roadmap.nodes[i].positions);
+                    roadmap.nodes[i].validity = Validity::from(is_valid);
+                    if !is_valid {
+                        break;
+                    }
+                }
+            }
+            if !is_valid {
+                continue;
+            }
+            for &e in &edges {
+                if roadmap.edges[e].validity == Validity::Unknown {
+                    let (a, b) = roadmap.edges[e].nodes;
+                    is_valid = is_edge_feasible(
+                        &roadmap.nodes[a].positions,
+                        &roadmap.nodes[b].positions,
+                        step_length,
+                        is_feasible,
+                    );
+                    roadmap.edges[e].validity = Validity::from(is_valid);
+                    if !is_valid {
+                        break;
+                    }
+                }
+            }
+            if is_valid {
+                return Ok(nodes
+                    .into_iter()
+                    .map(|i| roadmap.nodes[i].positions.clone())
+                    .collect());
+            }
+        }
+    }
+    /// Write the nodes and the edges of the roadmap as text
+    ///
+    /// The results of the collision checks are not written.
+    pub fn write<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        let roadmap = self.lock()?;
+        let to_f64 = |v: N| v.to_f64().unwrap_or(::std::f64::NAN);
+        writeln!(writer, "{}", HEADER)?;
+        match roadmap.joint_names {
+            Some(ref names) => writeln!(writer, "joints {}", names.join(" "))?,
+            None => writeln!(writer, "joints")?,
+        }
+        writeln!(writer, "nodes {}", roadmap.nodes.len())?;
+        for node in &roadmap.nodes {
+            let line = node
+                .positions
+                .iter()
+                .map(|v| to_f64(*v).to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(writer, "{}", line)?;
+        }
+        writeln!(writer, "edges {}", roadmap.edges.len())?;
+        for edge in &roadmap.edges {
+            writeln!(writer, "{} {}", edge.nodes.0, edge.nodes.1)?;
+        }
+        Ok(())
+    }
+    /// Replace the roadmap with the one which is written by `write`
+    ///
+    /// All the nodes and the edges are checked again when they are used. The queries for the
+    /// other joints than the ones of the written roadmap fail.
+    pub fn read<R>(&self, reader: R) -> Result<()>
+    where
+        R: BufRead,
+    {
+        let invalid = |message: &str| Error::from(format!("invalid roadmap: {}", message));
+        let mut lines = reader.lines();
+        let mut next_line = || -> Result<String> {
+            match lines.next() {
+                Some(line) => Ok(line?),
+                None => Err(invalid("unexpected end")),
+            }
+        };
+        if next_line()? != HEADER {
+            return Err(invalid("unknown header"));
+        }
+        let parse_count = |line: &str, name: &str| -> Result<usize> {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if words.len() != 2 || words[0] != name {
+                return Err(invalid(name));
+            }
+            words[1].parse::<usize>().map_err(|_| invalid(name))
+        };
+        let mut roadmap = Roadmap::new();
+        let joint_names = {
+            let line = next_line()?;
+            let mut words = line.split_whitespace();
+            if words.next() != Some("joints") {
+                return Err(invalid("joints"));
+            }
+            words.map(|w| w.to_owned()).collect::<Vec<_>>()
+        };
+        let num_nodes = parse_count(&next_line()?, "nodes")?;
+        for _ in 0..num_nodes {
+            let positions = next_line()?
+                .split_whitespace()
+                .map(|w| {
+                    w.parse::<f64>()
+                        .map(na::convert)
+                        .map_err(|_| invalid("node"))
+                })
+                .collect::<Result<Vec<N>>>()?;
+            if roadmap
+                .nodes
+                .first()
+                .map_or(false, |n| n.positions.len() != positions.len())
+            {
+                return Err(invalid("number of the joints"));
+            }
+            roadmap.add_node(positions, Validity::Unknown);
+        }
+        let num_edges = parse_count(&next_line()?, "edges")?;
+        for _ in 0..num_edges {
+            let indices = next_line()?
+                .split_whitespace()
+                .map(|w| w.parse::<usize>().map_err(|_| invalid("edge")))
+                .collect::<Result<Vec<_>>>()?;
+            if indices.len() != 2 || indices.iter().any(|&i| i >= num_nodes) {
+                return Err(invalid("edge"));
+            }
+            let cost = self.cost.cost(
+                &roadmap.nodes[indices[0]].positions,
+                &roadmap.nodes[indices[1]].positions,
+            );
+            roadmap.add_edge(indices[0], indices[1], cost, Validity::Unknown);
+        }
+        if !joint_names.is_empty() {
+            if roadmap.dof().map_or(false, |dof| dof != joint_names.len()) {
+                return Err(invalid("number of the joints"));
+            }
+            roadmap.joint_names = Some(joint_names);
+        }
+        roadmap.needs_eager_recheck = true;
+        *self.lock()? = roadmap;
+        Ok(())
+    }
+    /// Save the roadmap to the file
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.write(&mut BufWriter::new(File::create(path)?))
+    }
+    /// Load the roadmap from the file which is saved by `save`
+    pub fn load<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.read(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<N> PlanningAlgorithm<N> for Prm<N>
+where
+    N: Real + Float,
+{
+    fn plan(
+        &self,
+        start: &[N],
+        goal: &[N],
+        is_feasible: &Fn(&[N]) -> bool,
+        sample: &Fn() -> Vec<N>,
+        budget: &PlanningBudget<N>,
+    ) -> Result<Vec<Vec<N>>> {
+        let deadline = budget.deadline();
+        let num_checks = Cell::new(0);
+        let counted_is_feasible = |positions: &[N]| {
+            num_checks.set(num_checks.get() + 1);
+            is_feasible(positions)
+        };
+        let mut roadmap = self.lock()?;
+        roadmap.step_length = Some(budget.step_length);
+        if let Some(dof) = roadmap.dof() {
+            if dof != start.len() || dof != goal.len() {
+                return Err(Error::from(format!(
+                    "roadmap is for {} joints, but the query is for {} joints",
+                    dof,
+                    start.len()
+                )));
+            }
+        }
+        if roadmap.needs_eager_recheck {
+            let num_eager_checks = roadmap.num_eager_checks(budget.step_length);
+            roadmap.stats.num_eager_checks += num_eager_checks;
+            roadmap.needs_eager_recheck = false;
+        }
+        if roadmap.nodes.is_empty() {
+            self.build(&mut roadmap, &counted_is_feasible, sample, budget, deadline);
+        }
+        // the start and the goal are removed after the query
+        let num_nodes = roadmap.nodes.len();
+        let num_edges = roadmap.edges.len();
+        let start_index = roadmap.add_node(start.to_vec(), Validity::Unknown);
+        self.connect(&mut roadmap, start_index, budget.step_length);
+        let goal_index = roadmap.add_node(goal.to_vec(), Validity::Unknown);
+        self.connect(&mut roadmap, goal_index, budget.step_length);
+        let result = self.search(
+            &mut roadmap,
+            start_index,
+            goal_index,
+            &counted_is_feasible,
+            budget.step_length,
+            deadline,
+        );
+        roadmap.truncate(num_nodes, num_edges);
+        roadmap.stats.num_checks += num_checks.get();
+        debug!(
+            "{} collision checks are done, {} checks are saved in total",
+            num_checks.get(),
+            roadmap.stats.num_saved_checks()
+        );
+        result
+    }
+    fn update_obstacles(
+        &self,
+        obstacles: &CollisionObjects<N>,
+        swept_bounds: &Fn(&[N], &[N]) -> Vec<AABB<N>>,
+    ) {
+        let snapshot = obstacle_snapshot(obstacles);
+        if let Ok(mut roadmap) = self.lock() {
+            let regions = match roadmap.obstacles {
+                Some(ref old) => changed_regions(old, &snapshot),
+                None => Vec::new(),
+            };
+            if !regions.is_empty() {
+                let num_checks = roadmap.invalidate_regions(&regions, swept_bounds);
+                debug!(
+                    "{} obstacle shapes are changed, {} checks of the roadmap are invalidated",
+                    regions.len(),
+                    num_checks
+                );
+                roadmap.stats.num_eager_checks += num_checks;
+            }
+            roadmap.obstacles = Some(snapshot);
+        }
+    }
+    fn update_joints(&self, joint_names: &[String], fixed_joint_positions: &[N]) -> Result<()> {
+        let mut roadmap = self.lock()?;
+        if !roadmap.nodes.is_empty() {
+            if let Some(ref names) = roadmap.joint_names {
+                if names.as_slice() != joint_names {
+                    return Err(Error::from(format!(
+                        "roadmap is for the joints {:?}, not {:?}, clear it to plan for them",
+                        names, joint_names
+                    )));
+                }
+            }
+        }
+        roadmap.joint_names = Some(joint_names.to_vec());
+        if roadmap.fixed_joint_positions.as_deref() != Some(fixed_joint_positions) {
+            if roadmap.fixed_joint_positions.is_some() {
+                debug!("other joints are moved, the roadmap is invalidated");
+                roadmap.invalidate();
+            }
+            roadmap.fixed_joint_positions = Some(fixed_joint_positions.to_vec());
+        }
+        Ok(())
+    }
+    fn invalidate(&self) {
+        if let Ok(mut roadmap) = self.lock() {
+            roadmap.invalidate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use na::{Point3, Vector3};
+    use ncollide3d::shape::{Ball, Compound, ShapeHandle};
+    use rand;
+
+    fn circle(x: f64, radius: f64) -> impl Fn(&[f64]) -> bool {
+        move |p: &[f64]| (p[0] - x).powi(2) + p[1] * p[1] > radius * radius
+    }
+
+    /// Bounds of the point robot at `(p[0], p[1], 0)`
+    fn swept_bounds(from: &[f64], to: &[f64]) -> Vec<AABB<f64>> {
+        vec![AABB::new(
+            Point3::new(from[0].min(to[0]), from[1].min(to[1]), 0.0),
+            Point3::new(from[0].max(to[0]), from[1].max(to[1]), 0.0),
+        )]
+    }
+
+    fn obstacles(x: f64, radius: f64) -> Compound<f64> {
+        Compound::new(vec![(
+            Isometry3::new(Vector3::new(x, 0.0, 0.0), na::zero()),
+            ShapeHandle::new(Ball::new(radius)),
+        )])
+    }
+
+    #[test]
+    fn prm() {
+        let sample = || {
+            vec![
+                rand::random::<f64>() * 4.0 - 2.0,
+                rand::random::<f64>() * 4.0 - 2.0,
+            ]
+        };
+        let budget = PlanningBudget {
+            step_length: 0.05,
+            max_try: 1000,
+            timeout: None,
+        };
+        let start = [-1.5, 0.0];
+        let goal = [1.5, 0.0];
+        let check_path = |path: &[Vec<f64>], is_feasible: &Fn(&[f64]) -> bool| {
+            assert_eq!(path[0], start.to_vec());
+            assert_eq!(path.last().unwrap(), &goal.to_vec());
+            for edge in path.windows(2) {
+                assert!(is_feasible(&edge[0]));
+                assert!(is_edge_feasible(&edge[0], &edge[1], 0.05, is_feasible));
+            }
+        };
+        let prm = Prm::new().num_samples(300).num_neighbors(8);
+        let is_feasible = circle(0.0, 0.5);
+        let first_obstacles = obstacles(0.0, 0.5);
+        prm.update_obstacles(&first_obstacles, &swept_bounds);
+        let path = prm
+            .plan(&start, &goal, &is_feasible, &sample, &budget)
+            .unwrap();
+        check_path(&path, &is_feasible);
+        let num_nodes = prm.num_nodes();
+        let num_edges = prm.num_edges();
+        // the infeasible samples are kept
+        assert!(num_nodes > 300);
+
+        // the roadmap is reused, and the start and the goal are removed after the query
+        prm.plan(&goal, &start, &is_feasible, &sample, &budget)
+            .unwrap();
+        assert_eq!(prm.num_nodes(), num_nodes);
+        assert_eq!(prm.num_edges(), num_edges);
+
+        // the same obstacles don't invalidate the roadmap
+        let num_unknown_nodes = |prm: &Prm<f64>| {
+            prm.lock()
+                .unwrap()
+                .nodes
+                .iter()
+                .filter(|node| node.validity == Validity::Unknown)
+                .count()
+        };
+        let num_unknown = num_unknown_nodes(&prm);
+        prm.update_obstacles(&first_obstacles, &swept_bounds);
+        assert_eq!(num_unknown_nodes(&prm), num_unknown);
+
+        // the moved obstacle is detected, and only the nodes near it are checked again
+        let is_feasible = circle(0.5, 0.5);
+        prm.update_obstacles(&obstacles(0.5, 0.5), &swept_bounds);
+        let num_invalidated = num_unknown_nodes(&prm) - num_unknown;
+        assert!(num_invalidated > 0 && num_invalidated < num_nodes / 2);
+        let path = prm
+            .plan(&start, &goal, &is_feasible, &sample, &budget)
+            .unwrap();
+        check_path(&path, &is_feasible);
+
+        // the roadmap is for the joints of the first query, and the other joints are watched
+        // like the obstacles
+        let names = vec!["x".to_owned(), "y".to_owned()];
+        prm.update_joints(&names, &[0.0]).unwrap();
+        prm.plan(&start, &goal, &is_feasible, &sample, &budget)
+            .unwrap();
+        let is_feasible = circle(-0.5, 0.5);
+        prm.update_joints(&names, &[1.0]).unwrap();
+        let path = prm
+            .plan(&start, &goal, &is_feasible, &sample, &budget)
+            .unwrap();
+        check_path(&path, &is_feasible);
+        assert!(prm
+            .update_joints(&["x".to_owned(), "z".to_owned()], &[1.0])
+            .is_err());
+        assert!(prm
+            .plan(&[0.0; 3], &[1.0; 3], &is_feasible, &sample, &budget)
+            .is_err());
+
+        let mut bytes = Vec::new();
+        prm.write(&mut bytes).unwrap();
+        let loaded = Prm::new();
+        loaded.read(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.num_nodes(), num_nodes);
+        assert_eq!(loaded.num_edges(), num_edges);
+        // the loaded roadmap is checked lazily
+        let path = loaded
+            .plan(&start, &goal, &is_feasible, &sample, &budget)
+            .unwrap();
+        check_path(&path, &is_feasible);
+        assert_eq!(loaded.num_nodes(), num_nodes);
+        assert!(loaded.update_joints(&names, &[1.0]).is_ok());
+        assert!(loaded
+            .update_joints(&["x".to_owned(), "z".to_owned()], &[1.0])
+            .is_err());
+        assert!(loaded.read(&bytes[..bytes.len() / 2]).is_err());
+    }
+    #[test]
+    fn lazy_prm() {
+        let sample = || {
+            vec![
+                rand::random::<f64>() * 4.0 - 2.0,
+                rand::random::<f64>() * 4.0 - 2.0,
+            ]
+        };
+        let budget = PlanningBudget {
+            step_length: 0.05,
+            max_try: 1000,
+            timeout: None,
+        };
+        let is_feasible = circle(0.0, 0.5);
+        let eager = Prm::new().num_samples(300).num_neighbors(8);
+        let lazy = Prm::new().num_samples(300).num_neighbors(8).lazy(true);
+        for prm in &[&eager, &lazy] {
+            let path = prm
+                .plan(&[-1.5, 0.0], &[1.5, 0.0], &is_feasible, &sample, &budget)
+                .unwrap();
+            for edge in path.windows(2) {
+                assert!(is_feasible(&edge[1]));
+                assert!(is_edge_feasible(&edge[0], &edge[1], 0.05, &is_feasible));
+            }
+        }
+        let eager_stats = eager.collision_check_stats();
+        let lazy_stats = lazy.collision_check_stats();
+        assert!(eager_stats.num_checks <= eager_stats.num_eager_checks);
+        assert!(lazy_stats.num_checks < eager_stats.num_checks);
+        assert!(lazy_stats.num_saved_checks() > lazy_stats.num_checks);
+
+        // the second query checks only the new nodes and edges
+        lazy.plan(&[1.5, 0.0], &[-1.5, 0.0], &is_feasible, &sample, &budget)
+            .unwrap();
+        let stats = lazy.collision_check_stats();
+        assert!(stats.num_checks - lazy_stats.num_checks < lazy_stats.num_checks);
+    }
+}
@@ -0,0 +1,202 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use urdf_rs;
+
+/// Mesh which is found by `ResourceResolver`
+#[derive(Debug, Clone)]
+pub enum MeshResource {
+    /// Mesh file
+    File(PathBuf),
+    /// Mesh file in memory
+    ///
+    /// `name` is the key of `MeshCache`, and its extension decides the format.
+    Bytes { name: String, data: Arc<Vec<u8>> },
+}
+
+/// Finds the mesh of the `filename` of `<mesh>` in URDF
+pub trait ResourceResolver: fmt::Debug + Send + Sync {
+    /// Returns `None` if the mesh is not found
+    fn resolve(&self, uri: &str) -> Option<MeshResource>;
+}
+
+type SchemeHandler = Arc<Fn(&str) -> Option<MeshResource> + Send + Sync>;
+
+/// `ResourceResolver` which can be configured without ROS
+///
+/// The uri is resolved in this order.
+///
+/// 1. meshes in memory added by `mesh_bytes()`
+/// 2. custom schemes added by `scheme()`
+/// 3. `package://` with `package_path()`, `search_path()` and then `rospack`
+///    (if `ros_package_path` is enabled)
+/// 4. `file://`, absolute paths and the paths relative to `base_dir()`
+///
+/// # Examples
+///
+/// ```
+/// let resolver = gear::Resolver::new()
+///     .ros_package_path(false)
+///     .package_path("my_robot_description", "/opt/robots/my_robot_description")
+///     .search_path("/opt/robots")
+///     .mesh_bytes("memory://box.stl", b"solid box\nendsolid box\n".to_vec());
+/// assert!(gear::ResourceResolver::resolve(&resolver, "memory://box.stl").is_some());
+/// assert!(gear::ResourceResolver::resolve(&resolver, "memory://ball.stl").is_none());
+/// ```
+#[derive(Clone)]
+pub struct Resolver {
+    base_dir: Option<PathBuf>,
+    package_paths: HashMap<String, PathBuf>,
+    search_paths: Vec<PathBuf>,
+    use_ros_package_path: bool,
+    meshes: HashMap<String, Arc<Vec<u8>>>,
+    schemes: HashMap<String, SchemeHandler>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver {
+            base_dir: None,
+            package_paths: HashMap::new(),
+            search_paths: Vec::new(),
+            use_ros_package_path: true,
+            meshes: HashMap::new(),
+            schemes: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resolver")
+            .field("base_dir", &self.base_dir)
+            .field("package_paths", &self.package_paths)
+            .field("search_paths", &self.search_paths)
+            .field("use_ros_package_path", &self.use_ros_package_path)
+            .field("meshes", &self.meshes.keys().collect::<Vec<_>>())
+            .field("schemes", &self.schemes.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn existing_file(path: PathBuf) -> Option<MeshResource> {
+    if path.exists() {
+        Some(MeshResource::File(path))
+    } else {
+        None
+    }
+}
+
+impl Resolver {
+    /// Create the resolver which works like `urdf_rs::utils::expand_package_path`
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Relative paths are resolved from this dir
+    pub fn base_dir<P>(mut self, base_dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.base_dir = Some(base_dir.as_ref().to_owned());
+        self
+    }
+    /// `package://<name>/...` is resolved to `<dir>/...`
+    pub fn package_path<P>(mut self, name: &str, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.package_paths
+            .insert(name.to_owned(), dir.as_ref().to_owned());
+        self
+    }
+    /// `package://<name>/...` is resolved to `<dir>/<name>/...` if it exists
+    pub fn search_path<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.search_paths.push(dir.as_ref().to_owned());
+        self
+    }
+    /// Use `rospack` for the packages which are not found (enabled by default)
+    pub fn ros_package_path(mut self, enable: bool) -> Self {
+        self.use_ros_package_path = enable;
+        self
+    }
+    /// Use `data` as the mesh of `uri`, the extension of `uri` decides the format
+    pub fn mesh_bytes(mut self, uri: &str, data: Vec<u8>) -> Self {
+        self.meshes.insert(uri.to_owned(), Arc::new(data));
+        self
+    }
+    /// Resolve the uris of `<scheme>://...` by `handler`, which gets the whole uri
+    pub fn scheme<F>(mut self, scheme: &str, handler: F) -> Self
+    where
+        F: Fn(&str) -> Option<MeshResource> + Send + Sync + 'static,
+    {
+        self.schemes.insert(scheme.to_owned(), Arc::new(handler));
+        self
+    }
+    fn resolve_package(&self, uri: &str, path: &str) -> Option<MeshResource> {
+        let mut split = path.splitn(2, '/');
+        let package = split.next()?;
+        let relative_path = split.next().unwrap_or("");
+        if let Some(dir) = self.package_paths.get(package) {
+            return existing_file(dir.join(relative_path));
+        }
+        for dir in &self.search_paths {
+            let path = dir.join(package).join(relative_path);
+            if path.exists() {
+                return Some(MeshResource::File(path));
+            }
+        }
+        if self.use_ros_package_path {
+            existing_file(PathBuf::from(urdf_rs::utils::expand_package_path(
+                uri, None,
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+impl ResourceResolver for Resolver {
+    fn resolve(&self, uri: &str) -> Option<MeshResource> {
+        if let Some(data) = self.meshes.get(uri) {
+            return Some(MeshResource::Bytes {
+                name: uri.to_owned(),
+                data: data.clone(),
+            });
+        }
+        if let Some(index) = uri.find("://") {
+            let (scheme, path) = (&uri[..index], &uri[index + 3..]);
+            if let Some(handler) = self.schemes.get(scheme) {
+                return handler(uri);
+            }
+            return match scheme {
+                "package" => self.resolve_package(uri, path),
+                "file" => existing_file(PathBuf::from(path)),
+                _ => None,
+            };
+        }
+        let path = Path::new(uri);
+        match self.base_dir {
+            Some(ref base_dir) if path.is_relative() => existing_file(base_dir.join(path)),
+            _ => existing_file(path.to_owned()),
+        }
+    }
+}
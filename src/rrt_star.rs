@@ -0,0 +1,342 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Real};
+use num_traits::Float;
+use rand;
+use std::cmp::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+
+use errors::*;
+use planning_algorithm::{
+    distance, is_edge_feasible, steer, JointDistance, PathCost, PlanningAlgorithm,
+    PlanningBudget,
+};
+
+/// Max number of the samples to find one which can improve the best path
+const MAX_INFORMED_SAMPLING_TRY: usize = 1000;
+
+struct Node<N> {
+    positions: Vec<N>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Cost from the start
+    cost: N,
+}
+
+/// Asymptotically optimal RRT* in the joint space, which is Informed RRT* if `informed` is true
+///
+/// The tree is grown from the start, and the nodes near the new node are rewired if the path
+/// through the new node is cheaper. The search continues until the budget (`max_try` or
+/// `timeout`) runs out, and the best path found so far is returned.
+#[derive(Debug, Clone)]
+pub struct RrtStar<N> {
+    /// Cost to minimize, `JointDistance` by default
+    pub cost: Arc<PathCost<N>>,
+    /// Sample only the joint positions which can improve the best path after a path is found
+    ///
+    /// This is rejection sampling, not the direct sampling of the ellipsoid: the positions
+    /// from `sample` are redrawn while the costs from the start and to the goal are not less
+    /// than the best cost, because `cost` is not always `JointDistance` and the positions must
+    /// be in the joint limits. It gives up after `MAX_INFORMED_SAMPLING_TRY` tries, so it is
+    /// not efficient if the best path is close to the straight motion.
+    pub informed: bool,
+    /// Probability to sample the goal
+    pub goal_bias: f64,
+    /// Radius to rewire the tree, which is the ratio to the step length
+    pub rewire_radius_ratio: N,
+}
+
+impl<N> Default for RrtStar<N>
+where
+    N: Real,
+{
+    fn default() -> Self {
+        RrtStar {
+            cost: Arc::new(JointDistance),
+            informed: true,
+            goal_bias: 0.05,
+            rewire_radius_ratio: na::convert(3.0),
+        }
+    }
+}
+
+impl<N> RrtStar<N>
+where
+    N: Real + Float,
+{
+    /// Informed RRT* which minimizes `JointDistance`
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the cost to minimize
+    pub fn cost<C>(mut self, cost: C) -> Self
+    where
+        C: PathCost<N> + 'static,
+    {
+        self.cost = Arc::new(cost);
+        self
+    }
+    /// Use the informed sampling or not
+    pub fn informed(mut self, informed: bool) -> Self {
+        self.informed = informed;
+        self
+    }
+    /// Sample the target to extend the tree, with the rejection sampling if it is informed
+    fn sample_target(
+        &self,
+        start: &[N],
+        goal: &[N],
+        sample: &Fn() -> Vec<N>,
+        best_cost: Option<N>,
+    ) -> Vec<N> {
+        if rand::random::<f64>() < self.goal_bias {
+            return goal.to_vec();
+        }
+        let mut positions = sample();
+        if let (true, Some(best_cost)) = (self.informed, best_cost) {
+            // the cost of the straight motion is the lower bound, so the samples which can not
+            // improve the best path are rejected
+            for _ in 0..MAX_INFORMED_SAMPLING_TRY {
+                if self.cost.cost(start, &positions) + self.cost.cost(&positions, goal) < best_cost
+                {
+                    break;
+                }
+                positions = sample();
+            }
+        }
+        positions
+    }
+    /// The node which is connected to the goal, and the cost of the path through it
+    fn best_goal_parent(
+        &self,
+        tree: &[Node<N>],
+        goal_parents: &[usize],
+        goal: &[N],
+    ) -> Option<(usize, N)> {
+        goal_parents
+            .iter()
+            .map(|&i| (i, tree[i].cost + self.cost.cost(&tree[i].positions, goal)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+    }
+}
+
+/// Change the parent of the node and update the costs of the descendants
+fn rewire<N>(tree: &mut [Node<N>], index: usize, new_parent: usize, new_cost: N)
+where
+    N: Real,
+{
+    if let Some(old_parent) = tree[index].parent {
+        tree[old_parent].children.retain(|&c| c != index);
+    }
+    tree[index].parent = Some(new_parent);
+    tree[new_parent].children.push(index);
+    let diff = tree[index].cost - new_cost;
+    let mut stack = vec![index];
+    while let Some(i) = stack.pop() {
+        tree[i].cost -= diff;
+        stack.extend_from_slice(&tree[i].children);
+    }
+}
+
+impl<N> PlanningAlgorithm<N> for RrtStar<N>
+where
+    N: Real + Float,
+{
+    fn plan(
+        &self,
+        start: &[N],
+        goal: &[N],
+        is_feasible: &Fn(&[N]) -> bool,
+        sample: &Fn() -> Vec<N>,
+        budget: &PlanningBudget<N>,
+    ) -> Result<Vec<Vec<N>>> {
+        let step_length = budget.step_length;
+        let radius = step_length * self.rewire_radius_ratio;
+        let deadline = budget.deadline();
+        let mut tree = vec![Node {
+            positions: start.to_vec(),
+            parent: None,
+            children: Vec::new(),
+            cost: na::zero(),
+        }];
+        // nodes which are connected to the goal by the straight motion
+        let mut goal_parents = Vec::new();
+        if distance(start, goal) <= radius
+            && is_edge_feasible(start, goal, step_length, is_feasible)
+        {
+            goal_parents.push(0);
+        }
+        let mut is_timed_out = false;
+        for _ in 0..budget.max_try {
+            if deadline.map_or(false, |d| Instant::now() >= d) {
+                is_timed_out = true;
+                break;
+            }
+            let best_cost = self
+                .best_goal_parent(&tree, &goal_parents, goal)
+                .map(|(_, cost)| cost);
+            let target = self.sample_target(start, goal, sample, best_cost);
+            let nearest = (0..tree.len())
+                .map(|i| (i, distance(&tree[i].positions, &target)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+                .map(|(i, _)| i)
+                .unwrap();
+            let positions = steer(&tree[nearest].positions, &target, step_length);
+            if distance(&tree[nearest].positions, &positions) <= na::zero()
+                || !is_feasible(&positions)
+            {
+                continue;
+            }
+            // choose the cheapest parent in the neighbors
+            let mut neighbors = (0..tree.len())
+                .filter(|&i| i == nearest || distance(&tree[i].positions, &positions) <= radius)
+                .map(|i| {
+                    (
+                        i,
+                        tree[i].cost + self.cost.cost(&tree[i].positions, &positions),
+                    )
+                })
+                .collect::<Vec<_>>();
+            neighbors.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+            let (parent, cost) = match neighbors.iter().find(|&&(i, _)| {
+                i == nearest
+                    || is_edge_feasible(&tree[i].positions, &positions, step_length, is_feasible)
+            }) {
+                Some(&(parent, cost)) => (parent, cost),
+                None => continue,
+            };
+            let new_index = tree.len();
+            tree[parent].children.push(new_index);
+            tree.push(Node {
+                positions,
+                parent: Some(parent),
+                children: Vec::new(),
+                cost,
+            });
+            // rewire the neighbors through the new node
+            for &(i, _) in &neighbors {
+                if i == parent {
+                    continue;
+                }
+                let new_cost = cost
+                    + self
+                        .cost
+                        .cost(&tree[new_index].positions, &tree[i].positions);
+                if new_cost < tree[i].cost
+                    && is_edge_feasible(
+                        &tree[new_index].positions,
+                        &tree[i].positions,
+                        step_length,
+                        is_feasible,
+                    )
+                {
+                    rewire(&mut tree, i, new_index, new_cost);
+                }
+            }
+            if distance(&tree[new_index].positions, goal) <= radius
+                && is_edge_feasible(&tree[new_index].positions, goal, step_length, is_feasible)
+            {
+                goal_parents.push(new_index);
+            }
+        }
+        let (mut index, cost) = self
+            .best_goal_parent(&tree, &goal_parents, goal)
+            .ok_or_else(|| {
+                if is_timed_out {
+                    Error::Timeout {
+                        error: format!("RRT* timed out with {} nodes", tree.len()),
+                    }
+                } else {
+                    Error::from(format!(
+                        "RRT* failed to find a path with {} nodes",
+                        tree.len()
+                    ))
+                }
+            })?;
+        debug!(
+            "RRT* found a path of cost {:?} with {} nodes",
+            cost,
+            tree.len()
+        );
+        let mut path = vec![tree[index].positions.clone()];
+        while let Some(parent) = tree[index].parent {
+            path.push(tree[parent].positions.clone());
+            index = parent;
+        }
+        path.reverse();
+        if path.last().map_or(true, |last| last.as_slice() != goal) {
+            path.push(goal.to_vec());
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use planning_algorithm::path_cost;
+    use std::time::Duration;
+
+    #[test]
+    fn rrt_star() {
+        // go around the circle at the origin whose radius is 0.5
+        let is_feasible = |p: &[f64]| p[0] * p[0] + p[1] * p[1] > 0.25;
+        let sample = || {
+            vec![
+                rand::random::<f64>() * 4.0 - 2.0,
+                rand::random::<f64>() * 4.0 - 2.0,
+            ]
+        };
+        let start = [-1.0, 0.0];
+        let goal = [1.0, 0.0];
+        let mut budget = PlanningBudget {
+            step_length: 0.1,
+            max_try: 3000,
+            timeout: None,
+        };
+        // two tangents and the arc
+        let optimal = 2.0 * 0.75f64.sqrt() + 0.5 * ::std::f64::consts::PI / 3.0;
+        let mean_cost = |informed: bool| {
+            let planner = RrtStar::new().informed(informed);
+            let num_runs = 3;
+            let mut sum = 0.0;
+            for _ in 0..num_runs {
+                let path = planner
+                    .plan(&start, &goal, &is_feasible, &sample, &budget)
+                    .unwrap();
+                assert_eq!(path[0], start.to_vec());
+                assert_eq!(path.last().unwrap(), &goal.to_vec());
+                for edge in path.windows(2) {
+                    assert!(is_edge_feasible(&edge[0], &edge[1], 0.1, &is_feasible));
+                }
+                let cost = path_cost(&JointDistance, &path);
+                assert!(cost > optimal - 0.1 && cost < optimal * 1.2, "{}", cost);
+                sum += cost;
+            }
+            sum / num_runs as f64
+        };
+        // the informed sampling finds the shorter path with the same number of the iterations
+        let uninformed = mean_cost(false);
+        let informed = mean_cost(true);
+        assert!(informed < uninformed, "{} < {}", informed, uninformed);
+        budget.timeout = Some(Duration::new(0, 0));
+        match RrtStar::new().plan(&start, &goal, &is_feasible, &sample, &budget) {
+            Err(Error::Timeout { .. }) => {}
+            result => panic!("expected timeout but {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,395 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+use na::{self, Isometry3, Point3, Real, Vector3};
+use ncollide3d::bounding_volume::{BoundingSphere, AABB};
+use ncollide3d::shape::{Shape, ShapeHandle};
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone)]
+enum SphereTreeContent {
+    /// Index of the collision model of the link
+    Leaf(usize),
+    Branch(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+struct SphereTreeNode<T>
+where
+    T: Real,
+{
+    /// Center in the link frame
+    center: Point3<T>,
+    radius: T,
+    content: SphereTreeContent,
+}
+
+/// Maximum number of the leaf spheres along each axis of a primitive shape
+const MAX_SPLITS: usize = 8;
+
+/// Hierarchy of the spheres which contain the collision models of a link
+///
+/// Each leaf contains a convex part of a collision model (e.g. a triangle of a mesh) or a
+/// cell of a long primitive shape (e.g. a cylinder), and each branch contains its children. The spheres are checked from the root, and only the models
+/// whose leaves are near the obstacle need the exact check.
+#[derive(Debug, Clone)]
+pub struct SphereTree<T>
+where
+    T: Real,
+{
+    nodes: Vec<SphereTreeNode<T>>,
+    root: Option<usize>,
+}
+
+/// Collect the bounding spheres of the convex parts of the shape
+fn collect_leaves<T>(
+    shape: &Shape<T>,
+    pose: &Isometry3<T>,
+    model_index: usize,
+    leaves: &mut Vec<(Point3<T>, T, usize)>,
+) where
+    T: Real,
+{
+    match shape.as_composite_shape() {
+        Some(composite) => {
+            for i in 0..composite.nparts() {
+                composite.map_part_at(i, pose, &mut |part_pose, part| {
+                    collect_leaves(part, part_pose, model_index, leaves)
+                });
+            }
+        }
+        None => {
+            let sphere: BoundingSphere<T> = shape.bounding_sphere(pose);
+            leaves.push((*sphere.center(), sphere.radius(), model_index));
+        }
+    }
+}
+
+/// Collect the spheres of the cells of the primitive shape
+///
+/// The AABB of the shape in its frame is divided into the cells whose edges are about the
+/// shortest extent, so a long shape is not covered by one large sphere. The cells which don't
+/// touch the shape are skipped.
+fn collect_primitive_leaves<T>(
+    shape: &Shape<T>,
+    pose: &Isometry3<T>,
+    model_index: usize,
+    leaves: &mut Vec<(Point3<T>, T, usize)>,
+) where
+    T: Real,
+{
+    let aabb: AABB<T> = shape.aabb(&Isometry3::identity());
+    let extents = aabb.maxs() - aabb.mins();
+    let longest = na::sup(&extents[0], &na::sup(&extents[1], &extents[2]));
+    let shortest = na::inf(&extents[0], &na::inf(&extents[1], &extents[2]));
+    let max_splits: T = na::convert(MAX_SPLITS as f64);
+    let edge = na::sup(&shortest, &(longest / max_splits));
+    let mut splits = [1; 3];
+    if edge > T::zero() {
+        for i in 0..3 {
+            while splits[i] < MAX_SPLITS
+                && edge * na::convert(splits[i] as f64) < extents[i] * na::convert(0.99)
+            {
+                splits[i] += 1;
+            }
+        }
+    }
+    if splits == [1; 3] {
+        let sphere: BoundingSphere<T> = shape.bounding_sphere(pose);
+        leaves.push((*sphere.center(), sphere.radius(), model_index));
+        return;
+    }
+    let cell = Vector3::new(
+        extents[0] / na::convert(splits[0] as f64),
+        extents[1] / na::convert(splits[1] as f64),
+        extents[2] / na::convert(splits[2] as f64),
+    );
+    let half: T = na::convert(0.5);
+    let radius = cell.norm() * half;
+    let num_leaves = leaves.len();
+    for z in 0..splits[2] {
+        for y in 0..splits[1] {
+            for x in 0..splits[0] {
+                let offset = Vector3::new(
+                    na::convert::<f64, T>(x as f64) + half,
+                    na::convert::<f64, T>(y as f64) + half,
+                    na::convert::<f64, T>(z as f64) + half,
+                );
+                let center = aabb.mins() + offset.component_mul(&cell);
+                if let Some(query) = shape.as_point_query() {
+                    if query.distance_to_point(&Isometry3::identity(), &center, true) > radius {
+                        continue;
+                    }
+                }
+                leaves.push((pose * center, radius, model_index));
+            }
+        }
+    }
+    if leaves.len() == num_leaves {
+        let sphere: BoundingSphere<T> = shape.bounding_sphere(pose);
+        leaves.push((*sphere.center(), sphere.radius(), model_index));
+    }
+}
+
+impl<T> SphereTree<T>
+where
+    T: Real,
+{
+    /// Create the tree of the collision models of a link, the poses are in the link frame
+    pub fn new(models: &[(ShapeHandle<T>, Isometry3<T>)]) -> Self {
+        let mut leaves = Vec::new();
+        for (i, &(ref shape, ref pose)) in models.iter().enumerate() {
+            if shape.as_composite_shape().is_some() {
+                collect_leaves(&**shape, pose, i, &mut leaves);
+            } else {
+                collect_primitive_leaves(&**shape, pose, i, &mut leaves);
+            }
+        }
+        let mut tree = SphereTree {
+            nodes: Vec::with_capacity(leaves.len() * 2),
+            root: None,
+        };
+        if !leaves.is_empty() {
+            tree.root = Some(tree.build(&mut leaves));
+        }
+        tree
+    }
+    fn build(&mut self, leaves: &mut [(Point3<T>, T, usize)]) -> usize {
+        // the sphere which contains the AABB of the spheres
+        let mut mins = leaves[0].0.coords - Vector3::repeat(leaves[0].1);
+        let mut maxs = leaves[0].0.coords + Vector3::repeat(leaves[0].1);
+        for &(ref center, radius, _) in leaves.iter() {
+            for i in 0..3 {
+                mins[i] = na::inf(&mins[i], &(center[i] - radius));
+                maxs[i] = na::sup(&maxs[i], &(center[i] + radius));
+            }
+        }
+        let center = Point3::from((mins + maxs) * na::convert::<f64, T>(0.5));
+        let radius = leaves.iter().fold(T::zero(), |r, &(ref c, leaf_radius, _)| {
+            na::sup(&r, &(na::distance(&center, c) + leaf_radius))
+        });
+        let content = if leaves.len() == 1 {
+            SphereTreeContent::Leaf(leaves[0].2)
+        } else {
+            // split at the median of the longest axis
+            let extents = maxs - mins;
+            let axis = extents.imax();
+            leaves.sort_by(|a, b| a.0[axis].partial_cmp(&b.0[axis]).unwrap_or(Ordering::Equal));
+            let (left, right) = leaves.split_at_mut(leaves.len() / 2);
+            let left = self.build(left);
+            let right = self.build(right);
+            SphereTreeContent::Branch(left, right)
+        };
+        self.nodes.push(SphereTreeNode {
+            center,
+            radius,
+            content,
+        });
+        self.nodes.len() - 1
+    }
+    /// Number of the spheres
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    /// Number of the leaf spheres
+    pub fn num_leaves(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|n| matches!(n.content, SphereTreeContent::Leaf(_)))
+            .count()
+    }
+    /// Centers (in the link frame) and radii of the leaf spheres
+    pub fn leaf_spheres(&self) -> Vec<(Point3<T>, T)> {
+        self.nodes
+            .iter()
+            .filter_map(|n| match n.content {
+                SphereTreeContent::Leaf(_) => Some((n.center, n.radius)),
+                _ => None,
+            })
+            .collect()
+    }
+    /// AABB of the root sphere in the world frame
+    pub fn root_aabb(&self, link_pose: &Isometry3<T>) -> Option<AABB<T>> {
+        self.root.map(|root| {
+            let node = &self.nodes[root];
+            let center = link_pose * node.center;
+            let half = Vector3::repeat(node.radius);
+            AABB::new(center - half, center + half)
+        })
+    }
+    /// Collect the indices of the models whose leaf spheres are within `margin` from `aabb`
+    pub fn intersecting_models(
+        &self,
+        link_pose: &Isometry3<T>,
+        aabb: &AABB<T>,
+        margin: T,
+        models: &mut Vec<usize>,
+    ) {
+        models.clear();
+        let mut stack = self.root.into_iter().collect::<Vec<_>>();
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let center = link_pose * node.center;
+            let mut closest = center;
+            for i in 0..3 {
+                closest[i] = na::sup(&aabb.mins()[i], &na::inf(&aabb.maxs()[i], &center[i]));
+            }
+            let reach = node.radius + margin;
+            if na::distance_squared(&center, &closest) > reach * reach {
+                continue;
+            }
+            match node.content {
+                SphereTreeContent::Leaf(model) => {
+                    if !models.contains(&model) {
+                        models.push(model);
+                    }
+                }
+                SphereTreeContent::Branch(left, right) => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collision_checker::{CollisionChecker, FromUrdf};
+    use k;
+    use ncollide3d::shape::{Ball, Compound, Cuboid, TriMesh};
+    use urdf_rs;
+
+    #[test]
+    fn sphere_tree() {
+        let models = vec![
+            (
+                ShapeHandle::new(Cuboid::new(Vector3::new(0.1, 0.1, 0.1))),
+                Isometry3::new(Vector3::new(1.0, 0.0, 0.0), na::zero()),
+            ),
+            (
+                ShapeHandle::new(TriMesh::new(
+                    vec![
+                        Point3::new(0.0, 0.0, 0.0),
+                        Point3::new(0.1, 0.0, 0.0),
+                        Point3::new(0.0, 0.1, 0.0),
+                        Point3::new(0.0, 0.0, 1.0),
+                        Point3::new(0.1, 0.0, 1.0),
+                        Point3::new(0.0, 0.1, 1.0),
+                    ],
+                    vec![Point3::new(0, 1, 2), Point3::new(3, 4, 5)],
+                    None,
+                )),
+                Isometry3::identity(),
+            ),
+            (
+                ShapeHandle::new(Ball::new(0.1)),
+                Isometry3::new(Vector3::new(0.0, 1.0, 0.0), na::zero()),
+            ),
+        ];
+        let tree = SphereTree::new(&models);
+        // a sphere for the cuboid and the ball, one for each triangle
+        assert_eq!(tree.num_leaves(), 4);
+        assert_eq!(tree.len(), 7);
+        assert_eq!(tree.leaf_spheres().len(), 4);
+
+        let link_pose = Isometry3::new(Vector3::new(0.0, 0.0, 1.0), na::zero());
+        let aabb = |x: f64, y: f64, z: f64| {
+            AABB::new(Point3::new(x, y, z), Point3::new(x + 0.01, y + 0.01, z + 0.01))
+        };
+        let mut found = Vec::new();
+        tree.intersecting_models(&link_pose, &aabb(1.0, 0.0, 1.0), 0.0, &mut found);
+        assert_eq!(found, vec![0]);
+        tree.intersecting_models(&link_pose, &aabb(0.02, 0.02, 2.0), 0.0, &mut found);
+        assert_eq!(found, vec![1]);
+        tree.intersecting_models(&link_pose, &aabb(0.02, 0.02, 1.5), 0.0, &mut found);
+        assert!(found.is_empty());
+        tree.intersecting_models(&link_pose, &aabb(0.0, 1.2, 1.0), 0.0, &mut found);
+        assert!(found.is_empty());
+        tree.intersecting_models(&link_pose, &aabb(0.0, 1.2, 1.0), 0.2, &mut found);
+        assert_eq!(found, vec![2]);
+        let root = tree.root_aabb(&link_pose).unwrap();
+        assert!(root.mins()[0] <= -0.1 && root.maxs()[0] >= 1.1);
+        assert!(SphereTree::<f64>::new(&[]).root_aabb(&link_pose).is_none());
+    }
+    #[test]
+    fn long_primitive() {
+        // 2.0 x 0.2 x 0.2
+        let tree = SphereTree::new(&[(
+            ShapeHandle::new(Cuboid::new(Vector3::new(1.0, 0.1, 0.1))),
+            Isometry3::new(Vector3::new(0.0, 0.0, 1.0), na::zero()),
+        )]);
+        let spheres = tree.leaf_spheres();
+        assert_eq!(spheres.len(), MAX_SPLITS);
+        for &(ref center, radius) in &spheres {
+            assert!(radius < 0.2);
+            assert!((center[2] - 1.0).abs() < 1e-6);
+        }
+        // the spheres cover the ends of the box
+        let covers = |point: Point3<f64>| {
+            spheres
+                .iter()
+                .any(|&(ref center, radius)| na::distance(center, &point) <= radius + 1e-6)
+        };
+        assert!(covers(Point3::new(1.0, 0.1, 1.1)));
+        assert!(covers(Point3::new(-1.0, -0.1, 0.9)));
+    }
+    #[test]
+    fn sphere_trees_of_checker() {
+        let urdf_robot = urdf_rs::read_file("sample.urdf").unwrap();
+        let create_checker = |sphere_trees: bool| {
+            let mut checker = CollisionChecker::<f64>::from_urdf_robot(&urdf_robot, 0.01);
+            if sphere_trees {
+                checker.enable_sphere_trees();
+            }
+            checker
+        };
+        let checkers = vec![create_checker(false), create_checker(true)];
+        assert!(checkers[0].sphere_tree("l_wrist_pitch").is_none());
+        assert!(checkers[1].sphere_tree("l_wrist_pitch").is_some());
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let arm = k::SerialChain::from_end(robot.find("l_wrist_pitch").unwrap());
+        let obstacles = Compound::<f64>::from_urdf_file("obstacles.urdf").unwrap();
+        let ball = Ball::new(0.05);
+        let ball_pose = |x: f64, y: f64, z: f64| Isometry3::new(Vector3::new(x, y, z), na::zero());
+        for positions in &[vec![0.5, -0.5, 0.3, -1.0, 0.2, 0.4], vec![0.0; 6]] {
+            arm.set_joint_positions(positions).unwrap();
+            let results = checkers
+                .iter()
+                .map(|checker| {
+                    let ball_names = [(0.6, 0.4, 0.5), (0.9, 0.4, 0.5), (0.3, 0.2, 0.5)]
+                        .iter()
+                        .map(|&(x, y, z)| {
+                            checker.colliding_link_names(&robot, &ball, &ball_pose(x, y, z))
+                        })
+                        .collect::<Vec<_>>();
+                    (
+                        ball_names,
+                        checker.colliding_link_names_with_objects(&robot, &obstacles),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(results[0], results[1]);
+        }
+        // the gripper is at the ball
+        assert!(!checkers[1]
+            .colliding_link_names(&robot, &ball, &ball_pose(0.9, 0.4, 0.5))
+            .is_empty());
+    }
+}
@@ -0,0 +1,431 @@
+/*
+Copyright 2017 Takashi Ogura
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+//! SRDF (Semantic Robot Description Format) loader
+//!
+//! Only the elements which are used by `gear` are parsed:
+//! `<group>`, `<group_state>`, `<end_effector>` and `<disable_collisions>`.
+use k;
+use na::Real;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use errors::*;
+
+/// `<chain>` element in `<group>`
+#[derive(Debug, Clone, Default)]
+pub struct ChainElement {
+    pub base_link: String,
+    pub tip_link: String,
+}
+
+/// `<group>` element
+#[derive(Debug, Clone, Default)]
+pub struct Group {
+    pub name: String,
+    pub chains: Vec<ChainElement>,
+    pub joints: Vec<String>,
+    pub links: Vec<String>,
+    /// Names of the groups which are included in this group
+    pub subgroups: Vec<String>,
+}
+
+/// `<group_state>` element, named joint positions of the group
+#[derive(Debug, Clone, Default)]
+pub struct GroupState {
+    pub name: String,
+    pub group: String,
+    /// Pairs of joint name and the position
+    pub joints: Vec<(String, f64)>,
+}
+
+/// `<end_effector>` element
+#[derive(Debug, Clone, Default)]
+pub struct EndEffector {
+    pub name: String,
+    pub group: String,
+    pub parent_link: String,
+    pub parent_group: Option<String>,
+}
+
+/// `<disable_collisions>` element
+#[derive(Debug, Clone, Default)]
+pub struct DisableCollisions {
+    pub link1: String,
+    pub link2: String,
+    pub reason: Option<String>,
+}
+
+/// Semantic description of the robot
+#[derive(Debug, Clone, Default)]
+pub struct Srdf {
+    pub name: String,
+    pub groups: Vec<Group>,
+    pub group_states: Vec<GroupState>,
+    pub end_effectors: Vec<EndEffector>,
+    pub disable_collisions: Vec<DisableCollisions>,
+}
+
+fn attribute(attributes: &[OwnedAttribute], name: &str) -> Option<String> {
+    attributes
+        .iter()
+        .find(|attr| attr.name.local_name == name)
+        .map(|attr| attr.value.to_owned())
+}
+
+fn required_attribute(attributes: &[OwnedAttribute], element: &str, name: &str) -> Result<String> {
+    attribute(attributes, name).ok_or_else(|| Error::Srdf {
+        error: format!("<{}> requires {} attribute", element, name),
+    })
+}
+
+/// Read SRDF from `Read`
+pub fn read_from<R>(source: R) -> Result<Srdf>
+where
+    R: Read,
+{
+    let mut srdf = Srdf::default();
+    let mut current_group: Option<Group> = None;
+    let mut current_group_state: Option<GroupState> = None;
+    // depth of the `<group>` elements which refer the subgroups
+    let mut subgroup_depth = 0;
+    for event in EventReader::new(source) {
+        let event = event.map_err(|e| Error::Srdf {
+            error: e.to_string(),
+        })?;
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "robot" => {
+                    srdf.name = attribute(&attributes, "name").unwrap_or_default();
+                }
+                "group" => match current_group {
+                    Some(ref mut group) => {
+                        subgroup_depth += 1;
+                        group
+                            .subgroups
+                            .push(required_attribute(&attributes, "group", "name")?);
+                    }
+                    None => {
+                        current_group = Some(Group {
+                            name: required_attribute(&attributes, "group", "name")?,
+                            ..Default::default()
+                        });
+                    }
+                },
+                "chain" => {
+                    if let Some(ref mut group) = current_group {
+                        group.chains.push(ChainElement {
+                            base_link: required_attribute(&attributes, "chain", "base_link")?,
+                            tip_link: required_attribute(&attributes, "chain", "tip_link")?,
+                        });
+                    }
+                }
+                "link" => {
+                    if let Some(ref mut group) = current_group {
+                        group
+                            .links
+                            .push(required_attribute(&attributes, "link", "name")?);
+                    }
+                }
+                "joint" => {
+                    let joint_name = required_attribute(&attributes, "joint", "name")?;
+                    if let Some(ref mut group) = current_group {
+                        group.joints.push(joint_name);
+                    } else if let Some(ref mut state) = current_group_state {
+                        // multi dof joints have several values, only the first one is used.
+                        let value = required_attribute(&attributes, "joint", "value")?;
+                        let value = value
+                            .split_whitespace()
+                            .next()
+                            .and_then(|v| v.parse::<f64>().ok())
+                            .ok_or_else(|| Error::Srdf {
+                                error: format!("invalid value {} for joint {}", value, joint_name),
+                            })?;
+                        state.joints.push((joint_name, value));
+                    }
+                }
+                "group_state" => {
+                    current_group_state = Some(GroupState {
+                        name: required_attribute(&attributes, "group_state", "name")?,
+                        group: required_attribute(&attributes, "group_state", "group")?,
+                        joints: Vec::new(),
+                    });
+                }
+                "end_effector" => {
+                    srdf.end_effectors.push(EndEffector {
+                        name: required_attribute(&attributes, "end_effector", "name")?,
+                        group: required_attribute(&attributes, "end_effector", "group")?,
+                        parent_link: required_attribute(
+                            &attributes,
+                            "end_effector",
+                            "parent_link",
+                        )?,
+                        parent_group: attribute(&attributes, "parent_group"),
+                    });
+                }
+                "disable_collisions" => {
+                    srdf.disable_collisions.push(DisableCollisions {
+                        link1: required_attribute(&attributes, "disable_collisions", "link1")?,
+                        link2: required_attribute(&attributes, "disable_collisions", "link2")?,
+                        reason: attribute(&attributes, "reason"),
+                    });
+                }
+                other => {
+                    debug!("SRDF element <{}> is ignored", other);
+                }
+            },
+            XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                "group" => {
+                    if subgroup_depth > 0 {
+                        subgroup_depth -= 1;
+                    } else if let Some(group) = current_group.take() {
+                        srdf.groups.push(group);
+                    }
+                }
+                "group_state" => {
+                    if let Some(state) = current_group_state.take() {
+                        srdf.group_states.push(state);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(srdf)
+}
+
+/// Read SRDF from string
+pub fn read_from_string(string: &str) -> Result<Srdf> {
+    read_from(string.as_bytes())
+}
+
+/// Read SRDF file
+pub fn read_file<P>(path: P) -> Result<Srdf>
+where
+    P: AsRef<Path>,
+{
+    read_from(BufReader::new(File::open(path)?))
+}
+
+impl Srdf {
+    /// Find the group by name
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.name == name)
+    }
+    /// Find the end effector by name
+    pub fn end_effector(&self, name: &str) -> Option<&EndEffector> {
+        self.end_effectors.iter().find(|e| e.name == name)
+    }
+    /// Joint positions of the `<group_state>` which has the name `state_name` for `group_name`
+    pub fn named_joint_positions(
+        &self,
+        group_name: &str,
+        state_name: &str,
+    ) -> Option<HashMap<String, f64>> {
+        self.group_states
+            .iter()
+            .find(|s| s.group == group_name && s.name == state_name)
+            .map(|s| s.joints.iter().cloned().collect())
+    }
+    /// Returns the name of the link at the end of the group
+    ///
+    /// The `tip_link` of the last `<chain>` is used if it exists. Otherwise the last `<link>`,
+    /// or the last `<link>` of the subgroups is used. The subgroups which refer each other
+    /// are visited only once.
+    pub fn group_tip_link(&self, group_name: &str) -> Option<String> {
+        self.group_tip_link_visited(group_name, &mut HashSet::new())
+    }
+    fn group_tip_link_visited<'a>(
+        &'a self,
+        group_name: &'a str,
+        visited: &mut HashSet<&'a str>,
+    ) -> Option<String> {
+        if !visited.insert(group_name) {
+            return None;
+        }
+        let group = self.group(group_name)?;
+        if let Some(chain) = group.chains.last() {
+            return Some(chain.tip_link.to_owned());
+        }
+        if let Some(link) = group.links.last() {
+            return Some(link.to_owned());
+        }
+        group
+            .subgroups
+            .iter()
+            .rev()
+            .filter_map(|name| self.group_tip_link_visited(name, visited))
+            .next()
+    }
+    /// Create `k::SerialChain` for the group from `base_link` to `tip_link` of its `<chain>`
+    ///
+    /// `link_joint_map` is used to convert the link names in SRDF to the joint names of `k`.
+    /// Only the group which is defined by one `<chain>` is supported. The groups of
+    /// `<joint>`, `<link>` or subgroups return an error.
+    ///
+    /// `k::SerialChain` always starts from the root of `robot`, so it returns an error if
+    /// there are movable joints between the root and `base_link`.
+    pub fn create_serial_chain<T>(
+        &self,
+        group_name: &str,
+        robot: &k::Chain<T>,
+        link_joint_map: &HashMap<String, String>,
+    ) -> Result<k::SerialChain<T>>
+    where
+        T: Real,
+    {
+        let group = self.group(group_name).ok_or_else(|| Error::Srdf {
+            error: format!("group {} not found", group_name),
+        })?;
+        let chain = match (
+            group.chains.as_slice(),
+            group.joints.is_empty() && group.links.is_empty() && group.subgroups.is_empty(),
+        ) {
+            ([chain], true) => chain,
+            _ => {
+                return Err(Error::Srdf {
+                    error: format!(
+                        "group {} must be defined by one <chain> to create the serial chain",
+                        group_name
+                    ),
+                })
+            }
+        };
+        let joint_name = |link: &str| {
+            link_joint_map
+                .get(link)
+                .cloned()
+                .unwrap_or_else(|| link.to_owned())
+        };
+        let base_joint_name = joint_name(&chain.base_link);
+        let tip_joint_name = joint_name(&chain.tip_link);
+        let tip = robot
+            .find(&tip_joint_name)
+            .ok_or(format!("{} not found", tip_joint_name))?;
+        let base = tip
+            .iter_ancestors()
+            .find(|node| node.joint().name == base_joint_name)
+            .ok_or_else(|| Error::Srdf {
+                error: format!(
+                    "base_link {} is not an ancestor of tip_link {} in group {}",
+                    chain.base_link, chain.tip_link, group_name
+                ),
+            })?;
+        if base.iter_ancestors().any(|node| node.joint().is_movable()) {
+            return Err(Error::Srdf {
+                error: format!(
+                    "group {} has movable joints above base_link {}",
+                    group_name, chain.base_link
+                ),
+            });
+        }
+        Ok(k::SerialChain::from_end(tip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn read_srdf() {
+        let srdf = read_from_string(
+            r#"
+<robot name="robo">
+  <group name="left_arm">
+    <chain base_link="root" tip_link="l_wrist2" />
+  </group>
+  <group name="left_gripper">
+    <joint name="l_gripper_linear1" />
+    <joint name="l_gripper_linear2" />
+  </group>
+  <group name="left_all">
+    <group name="left_arm" />
+    <group name="left_gripper" />
+  </group>
+  <group_state name="home" group="left_arm">
+    <joint name="l_shoulder_yaw" value="0.5" />
+    <joint name="l_elbow_pitch" value="-1.0" />
+  </group_state>
+  <end_effector name="gripper" parent_link="l_wrist2" group="left_gripper" />
+  <disable_collisions link1="l_gripper1" link2="l_gripper2" reason="Never" />
+</robot>"#,
+        ).unwrap();
+        assert_eq!(srdf.name, "robo");
+        assert_eq!(srdf.groups.len(), 3);
+        assert_eq!(srdf.group("left_gripper").unwrap().joints.len(), 2);
+        assert_eq!(
+            srdf.group("left_all").unwrap().subgroups,
+            vec!["left_arm", "left_gripper"]
+        );
+        assert_eq!(srdf.group_tip_link("left_all").unwrap(), "l_wrist2");
+        let home = srdf.named_joint_positions("left_arm", "home").unwrap();
+        assert_eq!(home.len(), 2);
+        assert!(srdf.named_joint_positions("left_gripper", "home").is_none());
+        assert!((home["l_elbow_pitch"] - (-1.0)).abs() < 1e-6);
+        assert_eq!(srdf.end_effector("gripper").unwrap().parent_link, "l_wrist2");
+        assert_eq!(srdf.disable_collisions[0].link2, "l_gripper2");
+    }
+    #[test]
+    fn group_tip_link_with_cycle() {
+        let srdf = read_from_string(
+            r#"
+<robot name="robo">
+  <group name="a">
+    <group name="b" />
+  </group>
+  <group name="b">
+    <group name="a" />
+  </group>
+</robot>"#,
+        ).unwrap();
+        assert!(srdf.group_tip_link("a").is_none());
+    }
+    #[test]
+    fn create_serial_chain() {
+        let srdf = read_from_string(
+            r#"
+<robot name="robo">
+  <group name="left_arm">
+    <chain base_link="root" tip_link="l_wrist2" />
+  </group>
+  <group name="left_forearm">
+    <chain base_link="l_elbow1" tip_link="l_wrist2" />
+  </group>
+  <group name="wrong_base">
+    <chain base_link="l_gripper1" tip_link="l_wrist2" />
+  </group>
+  <group name="left_gripper">
+    <joint name="l_gripper_linear1" />
+  </group>
+</robot>"#,
+        ).unwrap();
+        let urdf_robot = ::urdf_rs::read_file("sample.urdf").unwrap();
+        let robot = k::Chain::<f64>::from(&urdf_robot);
+        let map = k::urdf::link_to_joint_map(&urdf_robot);
+        let arm = srdf.create_serial_chain("left_arm", &robot, &map).unwrap();
+        assert_eq!(arm.dof(), 6);
+        assert!(srdf.create_serial_chain("left_forearm", &robot, &map).is_err());
+        assert!(srdf.create_serial_chain("wrong_base", &robot, &map).is_err());
+        assert!(srdf.create_serial_chain("left_gripper", &robot, &map).is_err());
+        assert!(srdf.create_serial_chain("no_group", &robot, &map).is_err());
+    }
+}